@@ -4,6 +4,7 @@
 
 use clap::{Arg, command};
 use failure::{Backtrace, Context, Fail, ResultExt};
+use ilass_cli::vad::VadConfig;
 use ilass_cli::*;
 use rmp_serde as rmps;
 use std::cmp::Ordering;
@@ -871,6 +872,7 @@ fn perform_vad(movie: &database::Movie, cache: TCache) -> Result<Vec<Span>, TopL
             let video_file_handler: VideoFileHandler = VideoFileHandler::open_video_file(
                 movie.path.as_path(),
                 None,
+                &VadConfig::default(),
                 NoProgressInfo {},
                 /*ProgressInfo::new(
                     500,
@@ -954,35 +956,25 @@ fn align(
         .map(|span| span.scaled(scaling_factor.to_f64()))
         .collect();
 
-    let alg_deltas = match config.align_mode {
-        AlignMode::NoSplit => {
-            let num_inc_timespancs = in_alg_spans.len();
-
-            let (alg_delta, _score) = ilass::align_nosplit(
-                &ref_alg_spans,
-                &in_alg_spans,
-                get_scoring_fn(config.scoring_mode),
-                ilass::NoProgressHandler,
-            );
-            //println!("align score {}", score);
-
-            vec![alg_delta; num_inc_timespancs]
-        }
+    let alignment_config = match config.align_mode {
+        AlignMode::NoSplit => ilass::AlignmentConfig::new().mode(ilass::AlignMode::NoSplit),
         AlignMode::Split {
             split_penalty,
             optimization,
-        } => {
-            ilass::align(
-                &ref_alg_spans,
-                &in_alg_spans,
-                split_penalty.to_f64(),
-                optimization.map(FixedPointNumber::to_f64),
-                get_scoring_fn(config.scoring_mode),
-                ilass::NoProgressHandler,
-            )
-            .0
-        }
-    };
+        } => ilass::AlignmentConfig::new()
+            .mode(ilass::AlignMode::Split)
+            .split_penalty(split_penalty.to_f64())
+            .speed_optimization(optimization.map(FixedPointNumber::to_f64)),
+    }
+    .score_fn(get_scoring_fn(config.scoring_mode));
+
+    let alg_deltas = ilass::align_with_config(
+        &ref_alg_spans,
+        &in_alg_spans,
+        &alignment_config,
+        ilass::NoProgressHandler,
+    )
+    .deltas;
 
     alg_deltas_to_timing_deltas(&alg_deltas, config.ms_per_alg_step)
         .into_iter()
@@ -1030,7 +1022,7 @@ fn assert_nosplit_deltas(deltas: &[i64]) -> i64 {
     delta
 }
 
-fn get_scoring_fn(scoring_mode: ScoringMode) -> impl Fn(ilass::TimeDelta, ilass::TimeDelta) -> f64 + Copy {
+fn get_scoring_fn(scoring_mode: ScoringMode) -> ilass::ScoreFn {
     match scoring_mode {
         ScoringMode::Standard => ilass::standard_scoring,
         ScoringMode::Overlap => ilass::overlap_scoring,
@@ -0,0 +1,811 @@
+//! Converts the (already corrected) lines of a subtitle file into another text-based format.
+//!
+//! The text of every line is parsed into a small intermediate representation which only knows
+//! line breaks and italic/bold/underline styling. Everything else (positions, colors, fonts,
+//! the style section of SubStationAlpha files, ...) can not be carried over and is reported
+//! as a loss, so the user can be warned.
+
+use crate::errors::*;
+use crate::webvtt::VttFile;
+use crate::{ParsedSubtitleFile, SubtitleFileFormat};
+use failure::ResultExt;
+use std::path::Path;
+use subparse::timetypes::{TimePoint, TimeSpan};
+use subparse::{SubtitleFileInterface, SubtitleFormat};
+
+/// The formats a subtitle file can be converted to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TargetFormat {
+    SubRip,
+    SubStationAlpha,
+    MicroDVD,
+    WebVtt,
+}
+
+impl TargetFormat {
+    /// Returns the format selected by the extension of `path` (`.srt`, `.ass`/`.ssa`, `.sub` or `.vtt`).
+    pub fn from_path(path: &Path) -> Option<TargetFormat> {
+        let extension = path.extension()?.to_string_lossy().to_lowercase();
+        match extension.as_str() {
+            "srt" => Some(TargetFormat::SubRip),
+            "ass" | "ssa" => Some(TargetFormat::SubStationAlpha),
+            "sub" => Some(TargetFormat::MicroDVD),
+            "vtt" => Some(TargetFormat::WebVtt),
+            _ => None,
+        }
+    }
+
+    pub fn get_name(self) -> &'static str {
+        match self {
+            TargetFormat::SubRip => ".srt (SubRip)",
+            TargetFormat::SubStationAlpha => ".ass (SubStationAlpha)",
+            TargetFormat::MicroDVD => ".sub (MicroDVD)",
+            TargetFormat::WebVtt => ".vtt (WebVTT)",
+        }
+    }
+}
+
+/// Decides how the corrected file gets written: `None` if the extension of the output file fits
+/// the format of the incorrect file (the file is written without any conversion), otherwise the
+/// format the lines have to be converted to.
+pub fn conversion_target(
+    input_format: SubtitleFileFormat,
+    output_file_path: &Path,
+) -> Result<Option<TargetFormat>, ConversionError> {
+    let target_format = TargetFormat::from_path(output_file_path);
+
+    let is_same_format = match input_format {
+        SubtitleFileFormat::Subparse(format) => {
+            subparse::is_valid_extension_for_subtitle_format(output_file_path.extension(), format)
+        }
+        SubtitleFileFormat::WebVtt => target_format == Some(TargetFormat::WebVtt),
+    };
+    if is_same_format {
+        return Ok(None);
+    }
+
+    let target_format =
+        target_format.ok_or_else(|| ConversionErrorKind::UnknownOutputFormat(output_file_path.to_path_buf()))?;
+
+    if is_image_based(input_format) {
+        return Err(ConversionErrorKind::ImageBasedSubtitle {
+            input_format_name: input_format.get_name().to_string(),
+        }
+        .into());
+    }
+
+    Ok(Some(target_format))
+}
+
+fn is_image_based(format: SubtitleFileFormat) -> bool {
+    matches!(
+        format,
+        SubtitleFileFormat::Subparse(SubtitleFormat::VobSubIdx | SubtitleFormat::VobSubSub)
+    )
+}
+
+/// The data of a converted subtitle file.
+pub struct ConvertedSubtitle {
+    pub data: Vec<u8>,
+
+    /// Human readable descriptions of everything that could not be carried over (without duplicates).
+    pub losses: Vec<&'static str>,
+}
+
+/// Converts a subtitle file in `input_format` into a file in `target_format`.
+///
+/// `fps` is only used for MicroDVD output, which stores frame numbers instead of times.
+pub fn convert_subtitle_file(
+    file: &ParsedSubtitleFile,
+    input_format: SubtitleFileFormat,
+    target_format: TargetFormat,
+    fps: f64,
+) -> Result<ConvertedSubtitle, ConversionError> {
+    let mut losses = Losses::default();
+
+    let entries = file
+        .get_subtitle_entries()
+        .with_context(|_| ConversionErrorKind::FailedToReadSubtitleLines)?;
+
+    let is_ssa_file = input_format == SubtitleFileFormat::Subparse(SubtitleFormat::SubStationAlpha);
+    match file {
+        ParsedSubtitleFile::Subparse(_) if is_ssa_file => {
+            losses.add(
+                "styles, layers, actor names and effects of the SubStationAlpha file (all lines use a default style)",
+            );
+        }
+        ParsedSubtitleFile::WebVtt(vtt_file) => {
+            if vtt_file.has_cue_settings() {
+                losses.add("WebVTT cue settings (position, alignment and size)");
+            }
+            if vtt_file.has_other_blocks() {
+                losses.add("WebVTT NOTE, STYLE and REGION blocks");
+            }
+        }
+        _ => {}
+    }
+
+    let lines: Vec<(TimeSpan, Vec<TextPart>)> = entries
+        .iter()
+        .map(|entry| {
+            let text = entry.line.as_deref().unwrap_or("");
+            let parts = match input_format {
+                SubtitleFileFormat::Subparse(SubtitleFormat::SubStationAlpha) => parse_ssa_text(text, &mut losses),
+                SubtitleFileFormat::Subparse(SubtitleFormat::MicroDVD) => parse_mdvd_text(text, &mut losses),
+                SubtitleFileFormat::WebVtt => parse_html_text(text, true, &mut losses),
+                SubtitleFileFormat::Subparse(_) => parse_html_text(text, false, &mut losses),
+            };
+            (entry.timespan, balance_styles(parts))
+        })
+        .collect();
+
+    let data = match target_format {
+        TargetFormat::SubRip => {
+            let srt_lines: Vec<(TimeSpan, String)> = lines
+                .iter()
+                .map(|(timespan, parts)| (*timespan, write_html_text(parts, false)))
+                .collect();
+            subparse::SrtFile::create(srt_lines)
+                .with_context(|_| ConversionErrorKind::FailedToCreateSubtitle)?
+                .to_data()
+                .with_context(|_| ConversionErrorKind::FailedToCreateSubtitle)?
+        }
+        TargetFormat::SubStationAlpha => write_ssa_file(&lines, &mut losses),
+        TargetFormat::MicroDVD => write_mdvd_file(&lines, fps, &mut losses),
+        TargetFormat::WebVtt => {
+            let vtt_lines: Vec<(TimeSpan, String)> = lines
+                .iter()
+                .map(|(timespan, parts)| (*timespan, write_html_text(parts, true)))
+                .collect();
+            if vtt_lines.iter().any(|(timespan, _)| timespan.start.is_negative()) {
+                losses.add("negative timestamps (moved to zero)");
+            }
+            VttFile::create(vtt_lines).to_data()
+        }
+    };
+
+    Ok(ConvertedSubtitle { data, losses: losses.0 })
+}
+
+#[derive(Default)]
+struct Losses(Vec<&'static str>);
+
+impl Losses {
+    fn add(&mut self, loss: &'static str) {
+        if !self.0.contains(&loss) {
+            self.0.push(loss);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Style {
+    Italic,
+    Bold,
+    Underline,
+}
+
+const ALL_STYLES: [Style; 3] = [Style::Italic, Style::Bold, Style::Underline];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum TextPart {
+    Text(String),
+    LineBreak,
+    StyleOn(Style),
+    StyleOff(Style),
+}
+
+fn push_char(parts: &mut Vec<TextPart>, c: char) {
+    if let Some(TextPart::Text(text)) = parts.last_mut() {
+        text.push(c);
+    } else {
+        parts.push(TextPart::Text(c.to_string()));
+    }
+}
+
+/// Removes redundant style changes (e.g. closing an italic tag which was never opened) and
+/// closes all styles at the end of the line.
+fn balance_styles(parts: Vec<TextPart>) -> Vec<TextPart> {
+    let mut active_styles: Vec<Style> = Vec::new();
+    let mut result = Vec::with_capacity(parts.len());
+
+    for part in parts {
+        match part {
+            TextPart::StyleOn(style) if active_styles.contains(&style) => continue,
+            TextPart::StyleOff(style) if !active_styles.contains(&style) => continue,
+            TextPart::StyleOn(style) => active_styles.push(style),
+            TextPart::StyleOff(style) => active_styles.retain(|&active| active != style),
+            _ => {}
+        }
+        result.push(part);
+    }
+
+    result.extend(active_styles.into_iter().rev().map(TextPart::StyleOff));
+    result
+}
+
+/// Parses an override block like `{\i1\pos(10,10)}` (without the braces).
+fn parse_ssa_override_block(block: &str, parts: &mut Vec<TextPart>, losses: &mut Losses) {
+    // blocks without a backslash are comments
+    for tag in block.split('\\').skip(1).map(str::trim).filter(|tag| !tag.is_empty()) {
+        let mut tag_chars = tag.chars();
+        let style = match tag_chars.next() {
+            Some('i') => Some(Style::Italic),
+            Some('b') => Some(Style::Bold),
+            Some('u') => Some(Style::Underline),
+            _ => None,
+        };
+
+        // `\b` also accepts a font weight like `\b700`
+        match (style, tag_chars.as_str().parse::<u32>()) {
+            (Some(style), Ok(0)) => parts.push(TextPart::StyleOff(style)),
+            (Some(style), Ok(_)) => parts.push(TextPart::StyleOn(style)),
+            _ if tag == "r" => parts.extend(ALL_STYLES.iter().map(|&style| TextPart::StyleOff(style))),
+            _ => losses.add("position, color, font and animation override tags like '{\\pos(...)}'"),
+        }
+    }
+}
+
+fn parse_ssa_text(text: &str, losses: &mut Losses) -> Vec<TextPart> {
+    let mut parts = Vec::new();
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' => {
+                let block: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                parse_ssa_override_block(&block, &mut parts, losses);
+            }
+            '\\' => match chars.next() {
+                Some('N') | Some('n') => parts.push(TextPart::LineBreak),
+                Some('h') => push_char(&mut parts, ' '),
+                Some(other) => {
+                    push_char(&mut parts, '\\');
+                    push_char(&mut parts, other);
+                }
+                None => push_char(&mut parts, '\\'),
+            },
+            _ => push_char(&mut parts, c),
+        }
+    }
+
+    parts
+}
+
+/// Parses the text of SubRip (`webvtt == false`) and WebVTT lines, which both use HTML-like tags.
+fn parse_html_text(text: &str, webvtt: bool, losses: &mut Losses) -> Vec<TextPart> {
+    let mut parts = Vec::new();
+    let mut rest = text;
+
+    while let Some(c) = rest.chars().next() {
+        let closing = match c {
+            // a bare `<` in SubRip text (e.g. `a < b`) is not a tag (WebVTT escapes it as `&lt;`)
+            '<' if webvtt || rest[1..].starts_with(|next: char| next.is_alphabetic() || next == '/') => Some('>'),
+            '{' if !webvtt && rest.starts_with("{\\") => Some('}'),
+            '&' if webvtt => Some(';'),
+            _ => None,
+        };
+
+        if let Some(end) = closing.and_then(|closing| rest.find(closing)) {
+            let tag = &rest[1..end];
+
+            if c == '&' {
+                let decoded = match tag {
+                    "amp" => Some('&'),
+                    "lt" => Some('<'),
+                    "gt" => Some('>'),
+                    "nbsp" => Some('\u{a0}'),
+                    "lrm" => Some('\u{200e}'),
+                    "rlm" => Some('\u{200f}'),
+                    _ => None,
+                };
+                if let Some(decoded) = decoded {
+                    push_char(&mut parts, decoded);
+                    rest = &rest[end + 1..];
+                    continue;
+                }
+            } else {
+                rest = &rest[end + 1..];
+
+                if c == '{' {
+                    // many players accept SubStationAlpha override tags (mostly `{\an8}`) in .srt files
+                    parse_ssa_override_block(tag, &mut parts, losses);
+                    continue;
+                }
+
+                let tag = tag.trim().to_lowercase();
+                let (is_closing, tag) = match tag.strip_prefix('/') {
+                    Some(tag) => (true, tag),
+                    None => (false, tag.as_str()),
+                };
+
+                // WebVTT tags can have classes (`<i.loud>`) and annotations (`<v Bob>`)
+                let name = tag.split(['.', ' ', '\t']).next().unwrap_or("");
+                let style = match name {
+                    "i" => Style::Italic,
+                    "b" => Style::Bold,
+                    "u" => Style::Underline,
+                    _ if webvtt => {
+                        losses.add("WebVTT voice, class, language, ruby and karaoke tags");
+                        continue;
+                    }
+                    _ => {
+                        losses.add("font colors and other HTML tags");
+                        continue;
+                    }
+                };
+                parts.push(if is_closing {
+                    TextPart::StyleOff(style)
+                } else {
+                    TextPart::StyleOn(style)
+                });
+                continue;
+            }
+        }
+
+        match c {
+            '\n' => parts.push(TextPart::LineBreak),
+            '\r' => {}
+            _ => push_char(&mut parts, c),
+        }
+        rest = &rest[c.len_utf8()..];
+    }
+
+    parts
+}
+
+fn parse_mdvd_text(text: &str, losses: &mut Losses) -> Vec<TextPart> {
+    let mut parts = Vec::new();
+    let mut subtitle_styles = Vec::new();
+
+    for (line_nr, line) in text.split(['|', '\n']).enumerate() {
+        if line_nr > 0 {
+            parts.push(TextPart::LineBreak);
+        }
+
+        // formatting codes like `{y:i}` (this line) or `{Y:i}` (this and all following lines)
+        // are only allowed at the start of a line
+        let mut line_styles = subtitle_styles.clone();
+        let mut rest = line.trim_end_matches('\r');
+        while rest.starts_with('{') {
+            let end = match rest.find('}') {
+                Some(end) => end,
+                None => break,
+            };
+            let code = &rest[1..end];
+            rest = &rest[end + 1..];
+
+            match code.split_once(':') {
+                Some((key @ ("y" | "Y"), values)) => {
+                    for value in values.split(',').map(|value| value.trim().to_lowercase()) {
+                        let style = match value.as_str() {
+                            "i" => Style::Italic,
+                            "b" => Style::Bold,
+                            "u" => Style::Underline,
+                            _ => {
+                                losses.add("MicroDVD color, font, size and position codes");
+                                continue;
+                            }
+                        };
+                        if !line_styles.contains(&style) {
+                            line_styles.push(style);
+                        }
+                        if key == "Y" && !subtitle_styles.contains(&style) {
+                            subtitle_styles.push(style);
+                        }
+                    }
+                }
+                _ => losses.add("MicroDVD color, font, size and position codes"),
+            }
+        }
+
+        parts.extend(line_styles.iter().map(|&style| TextPart::StyleOn(style)));
+        parts.push(TextPart::Text(rest.to_string()));
+        parts.extend(line_styles.iter().rev().map(|&style| TextPart::StyleOff(style)));
+    }
+
+    parts
+}
+
+/// Writes the text of a SubRip or WebVTT (which needs escaped `&`, `<` and `>`) line.
+fn write_html_text(parts: &[TextPart], webvtt: bool) -> String {
+    let mut result = String::new();
+    for part in parts {
+        match part {
+            TextPart::Text(text) if webvtt => {
+                for c in text.chars() {
+                    match c {
+                        '&' => result.push_str("&amp;"),
+                        '<' => result.push_str("&lt;"),
+                        '>' => result.push_str("&gt;"),
+                        _ => result.push(c),
+                    }
+                }
+            }
+            TextPart::Text(text) => result.push_str(text),
+            TextPart::LineBreak => result.push('\n'),
+            TextPart::StyleOn(style) => result.push_str(html_tag(*style, false)),
+            TextPart::StyleOff(style) => result.push_str(html_tag(*style, true)),
+        }
+    }
+    result
+}
+
+fn html_tag(style: Style, closing: bool) -> &'static str {
+    match (style, closing) {
+        (Style::Italic, false) => "<i>",
+        (Style::Italic, true) => "</i>",
+        (Style::Bold, false) => "<b>",
+        (Style::Bold, true) => "</b>",
+        (Style::Underline, false) => "<u>",
+        (Style::Underline, true) => "</u>",
+    }
+}
+
+/// Returns the milliseconds of `t`; negative times can not be represented
+/// in SubStationAlpha and MicroDVD files and are moved to zero.
+fn non_negative_msecs(t: TimePoint, losses: &mut Losses) -> i64 {
+    if t.is_negative() {
+        losses.add("negative timestamps (moved to zero)");
+        0
+    } else {
+        t.msecs()
+    }
+}
+
+fn format_ssa_timepoint(msecs: i64) -> String {
+    let centis = msecs / 10;
+    format!(
+        "{}:{:02}:{:02}.{:02}",
+        centis / 360000,
+        centis / 6000 % 60,
+        centis / 100 % 60,
+        centis % 100
+    )
+}
+
+const SSA_HEADER: &str = "[Script Info]
+ScriptType: v4.00+
+WrapStyle: 0
+ScaledBorderAndShadow: yes
+
+[V4+ Styles]
+Format: Name, Fontname, Fontsize, PrimaryColour, SecondaryColour, OutlineColour, BackColour, Bold, Italic, Underline, StrikeOut, ScaleX, ScaleY, Spacing, Angle, BorderStyle, Outline, Shadow, Alignment, MarginL, MarginR, MarginV, Encoding
+Style: Default,Arial,20,&H00FFFFFF,&H000000FF,&H00000000,&H00000000,0,0,0,0,100,100,0,0,1,2,2,2,10,10,10,1
+
+[Events]
+Format: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text
+";
+
+/// Writes an Advanced SubStationAlpha (v4.00+) file, which is also understood by
+/// all players for `.ssa` files.
+fn write_ssa_file(lines: &[(TimeSpan, Vec<TextPart>)], losses: &mut Losses) -> Vec<u8> {
+    let mut result = SSA_HEADER.to_string();
+
+    for (timespan, parts) in lines {
+        let mut text = String::new();
+        for part in parts {
+            match part {
+                TextPart::Text(t) => text.push_str(t),
+                TextPart::LineBreak => text.push_str("\\N"),
+                TextPart::StyleOn(style) | TextPart::StyleOff(style) => {
+                    let on = matches!(part, TextPart::StyleOn(_));
+                    let tag = match style {
+                        Style::Italic => 'i',
+                        Style::Bold => 'b',
+                        Style::Underline => 'u',
+                    };
+                    text.push_str(&format!("{{\\{}{}}}", tag, if on { 1 } else { 0 }));
+                }
+            }
+        }
+
+        result.push_str(&format!(
+            "Dialogue: 0,{},{},Default,,0,0,0,,{}\n",
+            format_ssa_timepoint(non_negative_msecs(timespan.start, losses)),
+            format_ssa_timepoint(non_negative_msecs(timespan.end, losses)),
+            text
+        ));
+    }
+
+    result.into_bytes()
+}
+
+/// Writes a MicroDVD file. MicroDVD can only style whole lines, so a style
+/// is kept if it applies to all text in a line.
+fn write_mdvd_file(lines: &[(TimeSpan, Vec<TextPart>)], fps: f64, losses: &mut Losses) -> Vec<u8> {
+    let to_frame = |msecs: i64| (msecs as f64 * fps / 1000.).round() as i64;
+
+    let mut result = String::new();
+    for (timespan, parts) in lines {
+        let start_frame = to_frame(non_negative_msecs(timespan.start, losses));
+        let end_frame = to_frame(non_negative_msecs(timespan.end, losses));
+
+        let mut active_styles: Vec<Style> = Vec::new();
+        let mut output_lines: Vec<String> = Vec::new();
+
+        for line_parts in parts.split(|part| *part == TextPart::LineBreak) {
+            let mut text = String::new();
+            let mut styles_on_all_text: Option<Vec<Style>> = None;
+            let mut partially_styled = false;
+
+            for part in line_parts {
+                match part {
+                    TextPart::StyleOn(style) if !active_styles.contains(style) => active_styles.push(*style),
+                    TextPart::StyleOff(style) => active_styles.retain(|active| active != style),
+                    TextPart::Text(t) if !t.trim().is_empty() => {
+                        text.push_str(t);
+                        let common = styles_on_all_text.get_or_insert_with(|| active_styles.clone());
+                        partially_styled |= common.len() != active_styles.len()
+                            || !common.iter().all(|style| active_styles.contains(style));
+                        common.retain(|style| active_styles.contains(style));
+                    }
+                    TextPart::Text(t) => text.push_str(t),
+                    _ => {}
+                }
+            }
+
+            if partially_styled {
+                losses.add("styling of parts of a line (MicroDVD can only style whole lines)");
+            }
+
+            let codes: String = styles_on_all_text
+                .unwrap_or_default()
+                .iter()
+                .map(|style| match style {
+                    Style::Italic => "{y:i}",
+                    Style::Bold => "{y:b}",
+                    Style::Underline => "{y:u}",
+                })
+                .collect();
+            output_lines.push(format!("{}{}", codes, text));
+        }
+
+        result.push_str(&format!(
+            "{{{}}}{{{}}}{}\n",
+            start_frame,
+            end_frame,
+            output_lines.join("|")
+        ));
+    }
+
+    result.into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(s: &str) -> TextPart {
+        TextPart::Text(s.to_string())
+    }
+
+    fn parse(format: SubtitleFormat, data: &str) -> ParsedSubtitleFile {
+        ParsedSubtitleFile::Subparse(subparse::parse_bytes(format, data.as_bytes(), None, 25.).unwrap())
+    }
+
+    fn convert(file: &ParsedSubtitleFile, input_format: SubtitleFileFormat, target: TargetFormat) -> ConvertedSubtitle {
+        convert_subtitle_file(file, input_format, target, 25.).unwrap()
+    }
+
+    fn entries(file: &ParsedSubtitleFile) -> Vec<(i64, i64, String)> {
+        file.get_subtitle_entries()
+            .unwrap()
+            .into_iter()
+            .map(|entry| {
+                (
+                    entry.timespan.start.msecs(),
+                    entry.timespan.end.msecs(),
+                    entry.line.unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    fn has_loss(converted: &ConvertedSubtitle, loss: &str) -> bool {
+        converted.losses.iter().any(|l| l.contains(loss))
+    }
+
+    const SRT_DATA: &str = "1\n00:00:01,000 --> 00:00:02,500\n<i>Hello</i>\nworld\n\n2\n00:00:03,000 --> 00:00:04,000\na < b & <b>c</b>\n\n";
+
+    #[test]
+    fn html_text_bare_less_than() {
+        let mut losses = Losses::default();
+        assert_eq!(
+            parse_html_text("a < b, c<3 or x <> y", false, &mut losses),
+            vec![text("a < b, c<3 or x <> y")]
+        );
+        assert_eq!(
+            parse_html_text("<i>a</i> < b", false, &mut losses),
+            vec![
+                TextPart::StyleOn(Style::Italic),
+                text("a"),
+                TextPart::StyleOff(Style::Italic),
+                text(" < b")
+            ]
+        );
+        assert!(losses.0.is_empty());
+    }
+
+    #[test]
+    fn html_text_tags_and_entities() {
+        let mut losses = Losses::default();
+        assert_eq!(
+            parse_html_text(
+                "<v Bob>&lt;hi&gt; <i.loud>there</i></v>\r\n&unknown;",
+                true,
+                &mut losses
+            ),
+            vec![
+                text("<hi> "),
+                TextPart::StyleOn(Style::Italic),
+                text("there"),
+                TextPart::StyleOff(Style::Italic),
+                TextPart::LineBreak,
+                text("&unknown;"),
+            ]
+        );
+        assert_eq!(losses.0, vec!["WebVTT voice, class, language, ruby and karaoke tags"]);
+
+        let mut losses = Losses::default();
+        assert_eq!(
+            parse_html_text("{\\an8}<font color=\"red\">red</font>", false, &mut losses),
+            vec![text("red")]
+        );
+        assert_eq!(losses.0.len(), 2);
+    }
+
+    #[test]
+    fn ssa_text() {
+        let mut losses = Losses::default();
+        assert_eq!(
+            parse_ssa_text("{\\i1}Hi{\\i0}\\Nthere{\\pos(10,10)}\\hyou{comment}", &mut losses),
+            vec![
+                TextPart::StyleOn(Style::Italic),
+                text("Hi"),
+                TextPart::StyleOff(Style::Italic),
+                TextPart::LineBreak,
+                text("there you"),
+            ]
+        );
+        assert_eq!(
+            losses.0,
+            vec!["position, color, font and animation override tags like '{\\pos(...)}'"]
+        );
+    }
+
+    #[test]
+    fn mdvd_text() {
+        let mut losses = Losses::default();
+        assert_eq!(
+            parse_mdvd_text("{Y:i}first|{y:b}{c:$0000FF}second", &mut losses),
+            vec![
+                TextPart::StyleOn(Style::Italic),
+                text("first"),
+                TextPart::StyleOff(Style::Italic),
+                TextPart::LineBreak,
+                TextPart::StyleOn(Style::Italic),
+                TextPart::StyleOn(Style::Bold),
+                text("second"),
+                TextPart::StyleOff(Style::Bold),
+                TextPart::StyleOff(Style::Italic),
+            ]
+        );
+        assert_eq!(losses.0, vec!["MicroDVD color, font, size and position codes"]);
+    }
+
+    #[test]
+    fn balance_unmatched_styles() {
+        let parts = vec![
+            TextPart::StyleOff(Style::Bold),
+            TextPart::StyleOn(Style::Italic),
+            TextPart::StyleOn(Style::Italic),
+            text("a"),
+        ];
+        assert_eq!(
+            balance_styles(parts),
+            vec![
+                TextPart::StyleOn(Style::Italic),
+                text("a"),
+                TextPart::StyleOff(Style::Italic)
+            ]
+        );
+    }
+
+    #[test]
+    fn round_trip_srt_ssa() {
+        let srt_format = SubtitleFileFormat::Subparse(SubtitleFormat::SubRip);
+        let ssa_format = SubtitleFileFormat::Subparse(SubtitleFormat::SubStationAlpha);
+        let srt_file = parse(SubtitleFormat::SubRip, SRT_DATA);
+
+        let ssa = convert(&srt_file, srt_format, TargetFormat::SubStationAlpha);
+        assert!(ssa.losses.is_empty());
+        let ssa_file = parse(SubtitleFormat::SubStationAlpha, &String::from_utf8(ssa.data).unwrap());
+        assert_eq!(
+            entries(&ssa_file),
+            vec![
+                (1000, 2500, "{\\i1}Hello{\\i0}\\Nworld".to_string()),
+                (3000, 4000, "a < b & {\\b1}c{\\b0}".to_string()),
+            ]
+        );
+
+        let srt = convert(&ssa_file, ssa_format, TargetFormat::SubRip);
+        let srt_file_again = parse(SubtitleFormat::SubRip, &String::from_utf8(srt.data).unwrap());
+        assert_eq!(entries(&srt_file_again), entries(&srt_file));
+    }
+
+    #[test]
+    fn round_trip_srt_vtt() {
+        let srt_format = SubtitleFileFormat::Subparse(SubtitleFormat::SubRip);
+        let srt_file = parse(SubtitleFormat::SubRip, SRT_DATA);
+
+        let vtt = convert(&srt_file, srt_format, TargetFormat::WebVtt);
+        assert!(vtt.losses.is_empty());
+        let vtt_file = ParsedSubtitleFile::WebVtt(VttFile::parse(&String::from_utf8(vtt.data).unwrap()).unwrap());
+        assert_eq!(entries(&vtt_file)[1].2, "a &lt; b &amp; <b>c</b>");
+
+        let srt = convert(&vtt_file, SubtitleFileFormat::WebVtt, TargetFormat::SubRip);
+        let srt_file_again = parse(SubtitleFormat::SubRip, &String::from_utf8(srt.data).unwrap());
+        assert_eq!(entries(&srt_file_again), entries(&srt_file));
+    }
+
+    #[test]
+    fn mdvd_output() {
+        let srt_data = "1\n00:00:01,000 --> 00:00:02,000\n<i>all</i>\n<i>half</i> plain\n\n";
+        let srt_file = parse(SubtitleFormat::SubRip, srt_data);
+
+        let mdvd = convert(
+            &srt_file,
+            SubtitleFileFormat::Subparse(SubtitleFormat::SubRip),
+            TargetFormat::MicroDVD,
+        );
+        assert_eq!(String::from_utf8(mdvd.data).unwrap(), "{25}{50}{y:i}all|half plain\n");
+        assert_eq!(
+            mdvd.losses,
+            vec!["styling of parts of a line (MicroDVD can only style whole lines)"]
+        );
+    }
+
+    #[test]
+    fn reported_losses() {
+        let ssa_data = format!(
+            "{}Dialogue: 0,0:00:01.00,0:00:02.00,Default,,0,0,0,,{{\\pos(10,10)}}Hello\n",
+            SSA_HEADER
+        );
+        let ssa_file = parse(SubtitleFormat::SubStationAlpha, &ssa_data);
+        let srt = convert(
+            &ssa_file,
+            SubtitleFileFormat::Subparse(SubtitleFormat::SubStationAlpha),
+            TargetFormat::SubRip,
+        );
+        assert!(has_loss(&srt, "styles, layers, actor names"));
+        assert!(has_loss(&srt, "override tags"));
+
+        let srt_file = parse(
+            SubtitleFormat::SubRip,
+            "1\n00:00:01,000 --> 00:00:02,000\n<font color=\"red\">Hi</font>\n\n",
+        );
+        let vtt = convert(
+            &srt_file,
+            SubtitleFileFormat::Subparse(SubtitleFormat::SubRip),
+            TargetFormat::WebVtt,
+        );
+        assert_eq!(vtt.losses, vec!["font colors and other HTML tags"]);
+
+        let vtt_data = "WEBVTT\n\nNOTE a comment\n\n00:01.000 --> 00:02.000 align:start\nHi\n";
+        let vtt_file = ParsedSubtitleFile::WebVtt(VttFile::parse(vtt_data).unwrap());
+        let srt = convert(&vtt_file, SubtitleFileFormat::WebVtt, TargetFormat::SubRip);
+        assert!(has_loss(&srt, "cue settings"));
+        assert!(has_loss(&srt, "NOTE, STYLE and REGION blocks"));
+
+        let negative = TimeSpan::new(TimePoint::from_msecs(-500), TimePoint::from_msecs(1000));
+        let vtt_file = ParsedSubtitleFile::WebVtt(VttFile::create(vec![(negative, "Hi".to_string())]));
+        let ssa = convert(&vtt_file, SubtitleFileFormat::WebVtt, TargetFormat::SubStationAlpha);
+        assert_eq!(ssa.losses, vec!["negative timestamps (moved to zero)"]);
+        assert!(
+            String::from_utf8(ssa.data)
+                .unwrap()
+                .ends_with("Dialogue: 0,0:00:00.00,0:00:01.00,Default,,0,0,0,,Hi\n")
+        );
+    }
+}
@@ -3,7 +3,6 @@
 use failure::{Backtrace, Context, Fail};
 use std::fmt;
 use std::path::PathBuf;
-use subparse::SubtitleFormat;
 
 #[macro_export]
 macro_rules! define_error {
@@ -71,6 +70,7 @@ define_error!(InputFileError, InputFileErrorKind);
 pub enum InputFileErrorKind {
     VideoFile(PathBuf),
     SubtitleFile(PathBuf),
+    ReferenceFile(PathBuf),
 }
 
 impl fmt::Display for InputFileErrorKind {
@@ -78,6 +78,7 @@ impl fmt::Display for InputFileErrorKind {
         match self {
             InputFileErrorKind::VideoFile(p) => write!(f, "processing video file '{}' failed", p.display()),
             InputFileErrorKind::SubtitleFile(p) => write!(f, "processing subtitle file '{}' failed", p.display()),
+            InputFileErrorKind::ReferenceFile(p) => write!(f, "processing reference file '{}' failed", p.display()),
         }
     }
 }
@@ -106,7 +107,9 @@ define_error!(InputVideoError, InputVideoErrorKind);
 #[derive(Clone, Eq, PartialEq, Debug, Fail)]
 pub enum InputVideoErrorKind {
     FailedToDecode { path: PathBuf },
+    FingerprintingFailed { path: PathBuf },
     VadAnalysisFailed,
+    VadNotAvailable { name: String },
 }
 
 impl fmt::Display for InputVideoErrorKind {
@@ -115,7 +118,19 @@ impl fmt::Display for InputVideoErrorKind {
             InputVideoErrorKind::FailedToDecode { path } => {
                 write!(f, "failed to extract voice segments from file '{}'", path.display())
             }
+            InputVideoErrorKind::FingerprintingFailed { path } => {
+                write!(
+                    f,
+                    "failed to compute the audio fingerprint of file '{}'",
+                    path.display()
+                )
+            }
             InputVideoErrorKind::VadAnalysisFailed => write!(f, "failed to analyse audio segment for voice activity"),
+            InputVideoErrorKind::VadNotAvailable { name } => write!(
+                f,
+                "voice activity detector '{}' is not available in this build (compile with the feature '{}')",
+                name, name
+            ),
         }
     }
 }
@@ -128,6 +143,18 @@ pub enum InputSubtitleErrorKind {
     UnknownSubtitleFormat(PathBuf),
     ParsingSubtitleFailed(PathBuf),
     RetrievingSubtitleLinesFailed(PathBuf),
+    ReadingSubtitleStreamsFailed(PathBuf),
+    SubtitleStreamNotFound {
+        path: PathBuf,
+        subtitle_index: usize,
+        available_streams: String,
+    },
+    ImageBasedSubtitleStream {
+        path: PathBuf,
+        stream_description: String,
+    },
+    ExtractingSubtitleStreamFailed(PathBuf),
+    NoTextSubtitleStream(PathBuf),
 }
 
 impl fmt::Display for InputSubtitleErrorKind {
@@ -145,6 +172,167 @@ impl fmt::Display for InputSubtitleErrorKind {
             InputSubtitleErrorKind::RetrievingSubtitleLinesFailed(path) => {
                 write!(f, "retreiving subtitle file '{}' failed", path.display())
             }
+            InputSubtitleErrorKind::ReadingSubtitleStreamsFailed(path) => {
+                write!(f, "listing the subtitle streams of '{}' failed", path.display())
+            }
+            InputSubtitleErrorKind::SubtitleStreamNotFound {
+                path,
+                subtitle_index,
+                available_streams,
+            } => write!(
+                f,
+                "'{}' has no subtitle stream {} (available streams: {})",
+                path.display(),
+                subtitle_index,
+                available_streams
+            ),
+            InputSubtitleErrorKind::ImageBasedSubtitleStream {
+                path,
+                stream_description,
+            } => write!(
+                f,
+                "subtitle stream {} of '{}' contains images and can not be used as text",
+                stream_description,
+                path.display()
+            ),
+            InputSubtitleErrorKind::ExtractingSubtitleStreamFailed(path) => {
+                write!(f, "extracting a subtitle stream from '{}' failed", path.display())
+            }
+            InputSubtitleErrorKind::NoTextSubtitleStream(path) => {
+                write!(f, "'{}' has no text-based subtitle stream", path.display())
+            }
+        }
+    }
+}
+
+define_error!(WebVttError, WebVttErrorKind);
+
+#[derive(Clone, Eq, PartialEq, Debug, Fail)]
+pub enum WebVttErrorKind {
+    MissingHeader,
+    InvalidTimingLine { block_nr: usize, line: String },
+    EntryCountMismatch { cue_count: usize, entry_count: usize },
+}
+
+impl fmt::Display for WebVttErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WebVttErrorKind::MissingHeader => write!(f, "WebVTT file does not start with 'WEBVTT'"),
+            WebVttErrorKind::InvalidTimingLine { block_nr, line } => {
+                write!(f, "invalid cue timing line '{}' in block {}", line, block_nr)
+            }
+            WebVttErrorKind::EntryCountMismatch { cue_count, entry_count } => write!(
+                f,
+                "WebVTT file has {} cues, but {} subtitle entries were given",
+                cue_count, entry_count
+            ),
+        }
+    }
+}
+
+define_error!(VadCacheError, VadCacheErrorKind);
+
+#[derive(Clone, Eq, PartialEq, Debug, Fail)]
+pub enum VadCacheErrorKind {
+    NoCacheDirectory,
+    ReadingFileMetadataFailed(PathBuf),
+    CreatingDirectoryFailed(PathBuf),
+    SerializingFailed,
+    WritingFailed(PathBuf),
+    ClearingFailed(PathBuf),
+}
+
+impl fmt::Display for VadCacheErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VadCacheErrorKind::NoCacheDirectory => write!(
+                f,
+                "no cache directory found (set the environment variable 'ILASS_CACHE_DIR')"
+            ),
+            VadCacheErrorKind::ReadingFileMetadataFailed(path) => {
+                write!(f, "reading size and modification time of '{}' failed", path.display())
+            }
+            VadCacheErrorKind::CreatingDirectoryFailed(path) => {
+                write!(f, "creating cache directory '{}' failed", path.display())
+            }
+            VadCacheErrorKind::SerializingFailed => write!(f, "serializing cache entry failed"),
+            VadCacheErrorKind::WritingFailed(path) => write!(f, "writing cache file '{}' failed", path.display()),
+            VadCacheErrorKind::ClearingFailed(path) => write!(f, "clearing cache '{}' failed", path.display()),
+        }
+    }
+}
+
+define_error!(ReferenceFileError, ReferenceFileErrorKind);
+
+#[derive(Clone, Eq, PartialEq, Debug, Fail)]
+pub enum ReferenceFileErrorKind {
+    SourceNotAVideo(PathBuf),
+    AnalysingVideoFailed(PathBuf),
+    ReadingFileMetadataFailed(PathBuf),
+    ReadingFailed(PathBuf),
+    ParsingFailed(PathBuf),
+    NotAReferenceFile(PathBuf),
+    InvalidWeights(PathBuf),
+    InvalidTimespans(PathBuf),
+    UnsupportedVersion {
+        path: PathBuf,
+        version: u32,
+        supported_version: u32,
+    },
+    SerializingFailed,
+    WritingFailed(PathBuf),
+}
+
+impl fmt::Display for ReferenceFileErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReferenceFileErrorKind::SourceNotAVideo(path) => write!(
+                f,
+                "reference files can only be extracted from video files, not from '{}'",
+                path.display()
+            ),
+            ReferenceFileErrorKind::AnalysingVideoFailed(path) => {
+                write!(f, "extracting voice activity from '{}' failed", path.display())
+            }
+            ReferenceFileErrorKind::ReadingFileMetadataFailed(path) => {
+                write!(f, "reading size of '{}' failed", path.display())
+            }
+            ReferenceFileErrorKind::ReadingFailed(path) => {
+                write!(f, "reading reference file '{}' failed", path.display())
+            }
+            ReferenceFileErrorKind::ParsingFailed(path) => {
+                write!(f, "parsing reference file '{}' failed", path.display())
+            }
+            ReferenceFileErrorKind::NotAReferenceFile(path) => write!(
+                f,
+                "'{}' is not a reference file created by 'ilass extract-reference'",
+                path.display()
+            ),
+            ReferenceFileErrorKind::InvalidWeights(path) => write!(
+                f,
+                "reference file '{}' needs one weight from 0 to 1 for every time span",
+                path.display()
+            ),
+            ReferenceFileErrorKind::InvalidTimespans(path) => write!(
+                f,
+                "reference file '{}' contains a time span which starts before zero or ends before it starts",
+                path.display()
+            ),
+            ReferenceFileErrorKind::UnsupportedVersion {
+                path,
+                version,
+                supported_version,
+            } => write!(
+                f,
+                "reference file '{}' has version {}, but only versions up to {} are supported (update ilass)",
+                path.display(),
+                version,
+                supported_version
+            ),
+            ReferenceFileErrorKind::SerializingFailed => write!(f, "serializing reference file failed"),
+            ReferenceFileErrorKind::WritingFailed(path) => {
+                write!(f, "writing reference file '{}' failed", path.display())
+            }
         }
     }
 }
@@ -171,38 +359,95 @@ pub enum InputArgumentsErrorKind {
 
     #[fail(display = "argument '{}' with value '{}' could not be parsed", argument_name, value)]
     ArgumentParseError { argument_name: String, value: String },
+
+    #[fail(
+        display = "expected value for '{}' in the form '{}', found '{}'",
+        argument_name, expected_format, value
+    )]
+    InvalidFormat {
+        argument_name: String,
+        expected_format: String,
+        value: String,
+    },
+}
+
+define_error!(ConversionError, ConversionErrorKind);
+
+#[derive(Clone, Eq, PartialEq, Debug, Fail)]
+pub enum ConversionErrorKind {
+    UnknownOutputFormat(PathBuf),
+    ImageBasedSubtitle { input_format_name: String },
+    FailedToReadSubtitleLines,
+    FailedToCreateSubtitle,
+}
+
+impl fmt::Display for ConversionErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConversionErrorKind::UnknownOutputFormat(path) => write!(
+                f,
+                "output file '{}' has an unknown subtitle extension (conversion is possible to .srt, .ass/.ssa, .sub and .vtt files)",
+                path.display()
+            ),
+            ConversionErrorKind::ImageBasedSubtitle { input_format_name } => write!(
+                f,
+                "incorrect file with format '{}' contains images which can not be converted to a text-based format",
+                input_format_name
+            ),
+            ConversionErrorKind::FailedToReadSubtitleLines => {
+                write!(f, "failed to read the lines of the subtitle file")
+            }
+            ConversionErrorKind::FailedToCreateSubtitle => write!(f, "failed to create the converted subtitle file"),
+        }
+    }
 }
 
 define_error!(TopLevelError, TopLevelErrorKind);
 
 pub enum TopLevelErrorKind {
-    FileFormatMismatch {
-        input_file_path: PathBuf,
-        output_file_path: PathBuf,
-        input_file_format: SubtitleFormat,
-    },
     FailedToUpdateSubtitle,
     FailedToGenerateSubtitleData,
     FailedToInstantiateSubtitleFile,
+    AnchorLineOutOfRange { line: usize, line_count: usize },
+    MuxRequiresVideo { path: PathBuf },
+    PrealignRequiresVideo { path: PathBuf },
+    UseTextRequiresSubtitle { path: PathBuf },
+    MuxOutputOverwritesInput { path: PathBuf },
+    MuxingFailed { output_path: PathBuf },
 }
 
 impl fmt::Display for TopLevelErrorKind {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            TopLevelErrorKind::FileFormatMismatch {
-                input_file_path,
-                output_file_path,
-                input_file_format,
-            } => write!(
-                f,
-                "output file '{}' seems to have a different format than input file '{}' with format '{}' (this program does not perform conversions)",
-                output_file_path.display(),
-                input_file_path.display(),
-                input_file_format.get_name()
-            ),
             TopLevelErrorKind::FailedToUpdateSubtitle => write!(f, "failed to change lines in the subtitle"),
             TopLevelErrorKind::FailedToGenerateSubtitleData => write!(f, "failed to generate data for subtitle"),
             TopLevelErrorKind::FailedToInstantiateSubtitleFile => write!(f, "failed to instantiate subtitle file"),
+            TopLevelErrorKind::AnchorLineOutOfRange { line, line_count } => write!(
+                f,
+                "anchor refers to line {}, but the incorrect subtitle file only has {} lines",
+                line, line_count
+            ),
+            TopLevelErrorKind::MuxRequiresVideo { path } => write!(
+                f,
+                "can not write the subtitle into '{}' because it is not a video file",
+                path.display()
+            ),
+            TopLevelErrorKind::PrealignRequiresVideo { path } => write!(
+                f,
+                "can not compute the audio fingerprint of '{}' because it is not a video file",
+                path.display()
+            ),
+            TopLevelErrorKind::UseTextRequiresSubtitle { path } => write!(
+                f,
+                "can not compare the text of the lines because the reference file '{}' is not a subtitle",
+                path.display()
+            ),
+            TopLevelErrorKind::MuxOutputOverwritesInput { path } => {
+                write!(f, "the video file '{}' can not be overwritten in place", path.display())
+            }
+            TopLevelErrorKind::MuxingFailed { output_path } => {
+                write!(f, "failed to write video file '{}'", output_path.display())
+            }
         }
     }
 }
@@ -0,0 +1,411 @@
+//! Coarse alignment of two soundtracks: the onsets in a few frequency bands form a fingerprint of
+//! the audio, and the cross-correlation of two fingerprints finds their most likely offset.
+//!
+//! The result is far less precise than the alignment of the voice spans, but it is not fooled by
+//! long passages without speech, so it is used to narrow the offset window of the fine alignment.
+
+use crate::errors::*;
+use crate::video_decoder::{self, AudioReceiver, VideoDecoder};
+use failure::ResultExt;
+use std::f64::consts::PI;
+use std::path::{Path, PathBuf};
+
+/// Sample rate of the decoded audio.
+const SAMPLE_RATE: f64 = 16000.0;
+
+/// Number of samples per fingerprint frame.
+const FRAME_SIZE: usize = 512;
+
+/// Length of one fingerprint frame in milliseconds.
+pub const FRAME_MS: i64 = 32;
+
+/// Number of frequency bands (log-spaced between `MIN_FREQUENCY` and `MAX_FREQUENCY`).
+const BAND_COUNT: usize = 12;
+const MIN_FREQUENCY: f64 = 300.0;
+const MAX_FREQUENCY: f64 = 3000.0;
+
+/// The peak of the cross-correlation has to be this many standard deviations above the mean of
+/// all offsets, otherwise the fingerprints do not match.
+const MIN_PEAK_SCORE: f64 = 8.0;
+
+/// A biquad band-pass filter (from the "Audio EQ Cookbook").
+#[derive(Debug, Clone)]
+struct BandPass {
+    b0: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+    x1: f64,
+    x2: f64,
+    y1: f64,
+    y2: f64,
+}
+
+impl BandPass {
+    fn new(center_frequency: f64, q: f64) -> BandPass {
+        let w0 = 2.0 * PI * center_frequency / SAMPLE_RATE;
+        let alpha = w0.sin() / (2.0 * q);
+        let a0 = 1.0 + alpha;
+
+        BandPass {
+            b0: alpha / a0,
+            b2: -alpha / a0,
+            a1: -2.0 * w0.cos() / a0,
+            a2: (1.0 - alpha) / a0,
+            x1: 0.0,
+            x2: 0.0,
+            y1: 0.0,
+            y2: 0.0,
+        }
+    }
+
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b0 * x + self.b2 * self.x2 - self.a1 * self.y1 - self.a2 * self.y2;
+        self.x2 = self.x1;
+        self.x1 = x;
+        self.y2 = self.y1;
+        self.y1 = y;
+        y
+    }
+}
+
+/// The onset strength of every band in every frame (normalized to mean 0 and variance 1).
+#[derive(Debug, Clone)]
+pub struct Fingerprint {
+    bands: Vec<Vec<f64>>,
+}
+
+impl Fingerprint {
+    /// Decodes an audio stream of a video and computes its fingerprint.
+    pub fn extract(
+        file_path: &Path,
+        audio_index: Option<usize>,
+        video_decode_progress: impl video_decoder::ProgressHandler,
+    ) -> Result<Fingerprint, InputVideoError> {
+        let receiver = FingerprintReceiver::new();
+
+        Ok(
+            VideoDecoder::decode(file_path, audio_index, receiver, video_decode_progress).with_context(|_| {
+                InputVideoErrorKind::FingerprintingFailed {
+                    path: PathBuf::from(file_path),
+                }
+            })?,
+        )
+    }
+
+    fn frame_count(&self) -> usize {
+        self.bands.first().map_or(0, Vec::len)
+    }
+}
+
+/// Computes the fingerprint of the samples (see `Fingerprint`).
+pub struct FingerprintReceiver {
+    filters: Vec<BandPass>,
+    energies: Vec<f64>,
+    sample_count: usize,
+
+    /// log energy of every band in every frame
+    frames: Vec<[f64; BAND_COUNT]>,
+}
+
+impl FingerprintReceiver {
+    pub fn new() -> FingerprintReceiver {
+        // neighboring bands touch each other
+        let ratio = (MAX_FREQUENCY / MIN_FREQUENCY).powf(1.0 / (BAND_COUNT - 1) as f64);
+        let q = ratio.sqrt() / (ratio - 1.0);
+
+        FingerprintReceiver {
+            filters: (0..BAND_COUNT)
+                .map(|band| BandPass::new(MIN_FREQUENCY * ratio.powi(band as i32), q))
+                .collect(),
+            energies: vec![0.0; BAND_COUNT],
+            sample_count: 0,
+            frames: Vec::new(),
+        }
+    }
+}
+
+impl Default for FingerprintReceiver {
+    fn default() -> FingerprintReceiver {
+        FingerprintReceiver::new()
+    }
+}
+
+impl AudioReceiver for FingerprintReceiver {
+    type Output = Fingerprint;
+    type Error = InputVideoError;
+
+    fn push_samples(&mut self, samples: &[i16]) -> Result<(), InputVideoError> {
+        for &sample in samples {
+            let x = f64::from(sample) / 32768.0;
+            for (filter, energy) in self.filters.iter_mut().zip(&mut self.energies) {
+                *energy += filter.process(x).powi(2);
+            }
+
+            self.sample_count += 1;
+            if self.sample_count == FRAME_SIZE {
+                let mut frame = [0.0; BAND_COUNT];
+                for (band, energy) in self.energies.iter_mut().enumerate() {
+                    frame[band] = (*energy / FRAME_SIZE as f64 + 1e-10).log10();
+                    *energy = 0.0;
+                }
+                self.frames.push(frame);
+                self.sample_count = 0;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Fingerprint, InputVideoError> {
+        let bands = (0..BAND_COUNT)
+            .map(|band| {
+                // only rising energy (an onset) counts
+                let onsets: Vec<f64> = std::iter::once(0.0)
+                    .chain(
+                        self.frames
+                            .windows(2)
+                            .map(|frames| (frames[1][band] - frames[0][band]).max(0.0)),
+                    )
+                    .take(self.frames.len())
+                    .collect();
+                normalized(onsets)
+            })
+            .collect();
+
+        Ok(Fingerprint { bands })
+    }
+}
+
+/// Shifts and scales the values to mean 0 and variance 1 (constant values become 0).
+fn normalized(mut values: Vec<f64>) -> Vec<f64> {
+    if values.is_empty() {
+        return values;
+    }
+
+    let mean = values.iter().sum::<f64>() / values.len() as f64;
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / values.len() as f64;
+    let std_dev = variance.sqrt();
+
+    for value in &mut values {
+        *value = if std_dev > 0.0 { (*value - mean) / std_dev } else { 0.0 };
+    }
+    values
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Complex {
+    re: f64,
+    im: f64,
+}
+
+impl Complex {
+    fn mul(self, other: Complex) -> Complex {
+        Complex {
+            re: self.re * other.re - self.im * other.im,
+            im: self.re * other.im + self.im * other.re,
+        }
+    }
+
+    fn conj(self) -> Complex {
+        Complex {
+            re: self.re,
+            im: -self.im,
+        }
+    }
+}
+
+/// In-place radix-2 FFT (the length has to be a power of two). The inverse transform is not
+/// scaled.
+fn fft(data: &mut [Complex], inverse: bool) {
+    let n = data.len();
+    assert!(n.is_power_of_two());
+
+    // bit-reversal permutation
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            data.swap(i, j);
+        }
+    }
+
+    let sign = if inverse { 1.0 } else { -1.0 };
+    let mut len = 2;
+    while len <= n {
+        let angle = sign * 2.0 * PI / len as f64;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let twiddle = Complex {
+                    re: (angle * k as f64).cos(),
+                    im: (angle * k as f64).sin(),
+                };
+                let a = data[start + k];
+                let b = data[start + k + len / 2].mul(twiddle);
+                data[start + k] = Complex {
+                    re: a.re + b.re,
+                    im: a.im + b.im,
+                };
+                data[start + k + len / 2] = Complex {
+                    re: a.re - b.re,
+                    im: a.im - b.im,
+                };
+            }
+        }
+        len <<= 1;
+    }
+}
+
+/// Finds the offset (in milliseconds, added to the incorrect audio) at which both fingerprints
+/// match best. With `max_offset_ms`, only offsets in `-max_offset_ms..=max_offset_ms` are
+/// searched. Returns `None` if no offset matches clearly (e.g. for different films or audio
+/// with a different speed).
+pub fn coarse_offset(reference: &Fingerprint, incorrect: &Fingerprint, max_offset_ms: Option<i64>) -> Option<i64> {
+    let (ref_len, inc_len) = (reference.frame_count(), incorrect.frame_count());
+    if ref_len == 0 || inc_len == 0 {
+        return None;
+    }
+
+    // the cross-correlation of all bands is summed up in the frequency domain
+    let n = (ref_len + inc_len).next_power_of_two();
+    let mut spectrum = vec![Complex::default(); n];
+    for (ref_band, inc_band) in reference.bands.iter().zip(&incorrect.bands) {
+        let transform = |band: &[f64]| {
+            let mut data = vec![Complex::default(); n];
+            for (value, &re) in data.iter_mut().zip(band) {
+                value.re = re;
+            }
+            fft(&mut data, false);
+            data
+        };
+        let (ref_spectrum, inc_spectrum) = (transform(ref_band), transform(inc_band));
+
+        for ((sum, r), i) in spectrum.iter_mut().zip(ref_spectrum).zip(inc_spectrum) {
+            let product = r.mul(i.conj());
+            sum.re += product.re;
+            sum.im += product.im;
+        }
+    }
+    fft(&mut spectrum, true);
+
+    // index `k` holds the correlation for an offset of `k` frames, negative offsets wrap around
+    let max_offset_frames = max_offset_ms.map_or(i64::MAX, |max_offset_ms| max_offset_ms / FRAME_MS);
+    let correlations: Vec<(i64, f64)> = (-(inc_len as i64 - 1)..ref_len as i64)
+        .filter(|offset| offset.abs() <= max_offset_frames)
+        .map(|offset| (offset, spectrum[offset.rem_euclid(n as i64) as usize].re))
+        .collect();
+    if correlations.len() < 2 {
+        return None;
+    }
+
+    let count = correlations.len() as f64;
+    let mean = correlations.iter().map(|&(_, c)| c).sum::<f64>() / count;
+    let std_dev = (correlations.iter().map(|&(_, c)| (c - mean).powi(2)).sum::<f64>() / count).sqrt();
+    let &(best_offset, best_correlation) = correlations.iter().max_by(|(_, a), (_, b)| a.total_cmp(b))?;
+
+    if std_dev <= 0.0 || (best_correlation - mean) / std_dev < MIN_PEAK_SCORE {
+        return None;
+    }
+
+    Some(best_offset * FRAME_MS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A simple linear congruential generator (values in `0.0..1.0`).
+    struct Random(u64);
+
+    impl Random {
+        fn next(&mut self) -> f64 {
+            self.0 = self
+                .0
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (self.0 >> 11) as f64 / (1u64 << 53) as f64
+        }
+    }
+
+    #[test]
+    fn fft_matches_naive_dft() {
+        let mut random = Random(1);
+        let input: Vec<Complex> = (0..64)
+            .map(|_| Complex {
+                re: random.next() - 0.5,
+                im: random.next() - 0.5,
+            })
+            .collect();
+
+        for inverse in [false, true] {
+            let mut data = input.clone();
+            fft(&mut data, inverse);
+
+            let sign = if inverse { 1.0 } else { -1.0 };
+            for (k, value) in data.iter().enumerate() {
+                let expected = input.iter().enumerate().fold(Complex::default(), |sum, (t, x)| {
+                    let angle = sign * 2.0 * PI * (k * t) as f64 / input.len() as f64;
+                    let product = x.mul(Complex {
+                        re: angle.cos(),
+                        im: angle.sin(),
+                    });
+                    Complex {
+                        re: sum.re + product.re,
+                        im: sum.im + product.im,
+                    }
+                });
+                assert!((value.re - expected.re).abs() < 1e-9 && (value.im - expected.im).abs() < 1e-9);
+            }
+        }
+    }
+
+    /// Bursts of noise with random lengths, gaps and loudness.
+    fn bursts(seed: u64, secs: f64) -> Vec<i16> {
+        let mut random = Random(seed);
+        let mut samples = Vec::new();
+        while (samples.len() as f64) < secs * SAMPLE_RATE {
+            let gap = ((0.2 + random.next()) * SAMPLE_RATE) as usize;
+            samples.extend(std::iter::repeat_n(0, gap));
+
+            let length = ((0.1 + 0.4 * random.next()) * SAMPLE_RATE) as usize;
+            let amplitude = 2000.0 + 10000.0 * random.next();
+            samples.extend((0..length).map(|_| ((random.next() - 0.5) * amplitude) as i16));
+        }
+        samples
+    }
+
+    fn fingerprint(samples: &[i16]) -> Fingerprint {
+        let mut receiver = FingerprintReceiver::new();
+        for piece in samples.chunks(1000) {
+            receiver.push_samples(piece).unwrap();
+        }
+        receiver.finish().unwrap()
+    }
+
+    #[test]
+    fn coarse_offset_of_shifted_audio() {
+        let reference = bursts(1, 60.0);
+        let offset_samples = 100 * FRAME_SIZE;
+
+        // the incorrect audio starts 3.2 seconds later and is a little shorter
+        let incorrect = &reference[offset_samples..reference.len() - 5000];
+        let (reference, incorrect) = (fingerprint(&reference), fingerprint(incorrect));
+        assert_eq!(coarse_offset(&reference, &incorrect, None), Some(100 * FRAME_MS));
+        assert_eq!(coarse_offset(&incorrect, &reference, Some(5000)), Some(-100 * FRAME_MS));
+
+        // the best offset is outside of the window
+        assert_eq!(coarse_offset(&reference, &incorrect, Some(1000)), None);
+    }
+
+    #[test]
+    fn coarse_offset_of_unrelated_audio() {
+        let (reference, incorrect) = (fingerprint(&bursts(1, 60.0)), fingerprint(&bursts(2, 60.0)));
+        assert_eq!(coarse_offset(&reference, &incorrect, None), None);
+        assert_eq!(coarse_offset(&reference, &fingerprint(&[]), None), None);
+    }
+}
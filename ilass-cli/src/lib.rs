@@ -10,11 +10,21 @@ use std::result::Result;
 
 use errors::*;
 
+pub mod convert;
 pub mod errors;
+pub mod fingerprint;
+pub mod reference_file;
+pub mod vad;
+pub mod vad_cache;
 pub mod video_decoder;
+pub mod webvtt;
 
+use reference_file::{ReferenceFile, is_reference_file_path};
 use subparse::timetypes::*;
-use subparse::{SubtitleFile, get_subtitle_format_err, parse_bytes};
+use subparse::{SubtitleEntry, SubtitleFile, get_subtitle_format_err, parse_bytes};
+use vad::{VadConfig, VadReceiver};
+use vad_cache::{VadCache, VadCacheKey};
+use webvtt::VttFile;
 
 pub struct NoProgressInfo {}
 
@@ -120,6 +130,11 @@ pub fn timing_to_alg_timepoint(t: TimePoint, interval: i64) -> AlgTimePoint {
     AlgTimePoint::from(t.msecs() / interval)
 }
 
+pub fn delta_to_alg_delta(t: TimeDelta, interval: i64) -> AlgTimeDelta {
+    assert!(interval > 0);
+    AlgTimeDelta::from_i64(t.msecs() / interval)
+}
+
 pub fn alg_delta_to_delta(t: AlgTimeDelta, interval: i64) -> TimeDelta {
     assert!(interval > 0);
     let time_int: i64 = t.into();
@@ -142,42 +157,115 @@ pub fn alg_deltas_to_timing_deltas(v: &[AlgTimeDelta], interval: i64) -> Vec<Tim
     v.iter().cloned().map(|x| alg_delta_to_delta(x, interval)).collect()
 }
 
-/// Groups consecutive timespans with the same delta together.
-pub fn get_subtitle_delta_groups(mut v: Vec<(AlgTimeDelta, TimeSpan)>) -> Vec<(AlgTimeDelta, Vec<TimeSpan>)> {
-    v.sort_by_key(|t| min((t.1).start, (t.1).end));
+/// Transfers the alignment of `source_spans` (e.g. the voice spans of the video which belongs to
+/// the incorrect subtitle) to the `lines` of the subtitle: every line gets the delta of the
+/// segment whose time range contains the start of the line (or of the nearest segment).
+///
+/// Like the source spans, the lines have to be scaled by `alignment.scale_factor` before the
+/// deltas are added. Only works for alignments without drift correction.
+pub fn transfer_alignment_to_lines(
+    source_spans: &[AlgTimeSpan],
+    alignment: &ilass::AlignmentResult,
+    lines: &[AlgTimeSpan],
+) -> Vec<AlgTimeDelta> {
+    // the segments cover consecutive (sorted) source spans
+    let segment_ranges: Vec<(AlgTimePoint, AlgTimePoint, AlgTimeDelta)> = alignment
+        .segments
+        .iter()
+        .map(|segment| {
+            (
+                source_spans[segment.start_index].start(),
+                source_spans[segment.end_index - 1].end(),
+                alignment.deltas[segment.start_index],
+            )
+        })
+        .collect();
 
-    let mut result: Vec<(AlgTimeDelta, Vec<TimeSpan>)> = Vec::new();
+    lines
+        .iter()
+        .map(|line| {
+            let distance = |&(range_start, range_end, _): &(AlgTimePoint, AlgTimePoint, AlgTimeDelta)| {
+                if line.start() < range_start {
+                    range_start - line.start()
+                } else if line.start() > range_end {
+                    line.start() - range_end
+                } else {
+                    AlgTimeDelta::zero()
+                }
+            };
+
+            segment_ranges
+                .iter()
+                .min_by_key(|range| distance(range))
+                .map(|&(_, _, delta)| delta)
+                .unwrap_or_else(AlgTimeDelta::zero)
+        })
+        .collect()
+}
 
-    for (delta, original_timespan) in v {
-        let mut new_block = false;
+pub enum InputFileHandler {
+    Subtitle(SubtitleFileHandler),
+    Video(VideoFileHandler),
+}
 
-        if let Some(last_tuple_ref) = result.last_mut() {
-            if delta == last_tuple_ref.0 {
-                last_tuple_ref.1.push(original_timespan);
-            } else {
-                new_block = true;
-            }
-        } else {
-            new_block = true;
-        }
+/// The format of a subtitle file: one of the formats supported by `subparse` or WebVTT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubtitleFileFormat {
+    Subparse(subparse::SubtitleFormat),
+    WebVtt,
+}
 
-        if new_block {
-            result.push((delta, vec![original_timespan]));
+impl SubtitleFileFormat {
+    pub fn get_name(self) -> &'static str {
+        match self {
+            SubtitleFileFormat::Subparse(format) => format.get_name(),
+            SubtitleFileFormat::WebVtt => ".vtt (WebVTT)",
         }
     }
+}
 
-    result
+/// A parsed subtitle file in any supported format.
+pub enum ParsedSubtitleFile {
+    Subparse(SubtitleFile),
+    WebVtt(VttFile),
 }
 
-pub enum InputFileHandler {
-    Subtitle(SubtitleFileHandler),
-    Video(VideoFileHandler),
+impl ParsedSubtitleFile {
+    pub fn get_subtitle_entries(&self) -> Result<Vec<SubtitleEntry>, failure::Error> {
+        match self {
+            ParsedSubtitleFile::Subparse(file) => Ok(file.get_subtitle_entries()?),
+            ParsedSubtitleFile::WebVtt(file) => Ok(file.get_subtitle_entries()),
+        }
+    }
+
+    pub fn update_subtitle_entries(&mut self, entries: &[SubtitleEntry]) -> Result<(), failure::Error> {
+        match self {
+            ParsedSubtitleFile::Subparse(file) => Ok(file.update_subtitle_entries(entries)?),
+            ParsedSubtitleFile::WebVtt(file) => Ok(file.update_subtitle_entries(entries)?),
+        }
+    }
+
+    pub fn to_data(&self) -> Result<Vec<u8>, failure::Error> {
+        match self {
+            ParsedSubtitleFile::Subparse(file) => Ok(file.to_data()?),
+            ParsedSubtitleFile::WebVtt(file) => Ok(file.to_data()),
+        }
+    }
 }
 
 pub struct SubtitleFileHandler {
-    file_format: subparse::SubtitleFormat,
-    subtitle_file: SubtitleFile,
+    file_format: SubtitleFileFormat,
+    subtitle_file: ParsedSubtitleFile,
     subparse_timespans: Vec<TimeSpan>,
+
+    /// the text of every line (in the same order as the timespans)
+    texts: Vec<String>,
+}
+
+fn is_webvtt_path(file_path: &Path) -> bool {
+    file_path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("vtt"))
 }
 
 impl SubtitleFileHandler {
@@ -189,30 +277,111 @@ impl SubtitleFileHandler {
         let sub_data = read_file_to_bytes(file_path)
             .with_context(|_| InputSubtitleErrorKind::ReadingSubtitleFileFailed(file_path.to_path_buf()))?;
 
-        let file_format = get_subtitle_format_err(file_path.extension(), &sub_data)
-            .with_context(|_| InputSubtitleErrorKind::UnknownSubtitleFormat(file_path.to_path_buf()))?;
+        let (file_format, parsed_subtitle_data) = if is_webvtt_path(file_path) {
+            // WebVTT files are always UTF-8, but an explicit encoding is still respected
+            let text = match sub_encoding {
+                Some(encoding) => encoding.decode(&sub_data).0.into_owned(),
+                None => String::from_utf8_lossy(&sub_data).into_owned(),
+            };
+            let vtt_file = VttFile::parse(&text)
+                .with_context(|_| InputSubtitleErrorKind::ParsingSubtitleFailed(file_path.to_path_buf()))?;
+
+            (SubtitleFileFormat::WebVtt, ParsedSubtitleFile::WebVtt(vtt_file))
+        } else {
+            let file_format = get_subtitle_format_err(file_path.extension(), &sub_data)
+                .with_context(|_| InputSubtitleErrorKind::UnknownSubtitleFormat(file_path.to_path_buf()))?;
+
+            let parsed_subtitle_data: SubtitleFile = parse_bytes(file_format, &sub_data, sub_encoding, sub_fps)
+                .with_context(|_| InputSubtitleErrorKind::ParsingSubtitleFailed(file_path.to_path_buf()))?;
 
-        let parsed_subtitle_data: SubtitleFile = parse_bytes(file_format, &sub_data, sub_encoding, sub_fps)
+            (
+                SubtitleFileFormat::Subparse(file_format),
+                ParsedSubtitleFile::Subparse(parsed_subtitle_data),
+            )
+        };
+
+        Self::from_parsed_file(file_path, file_format, parsed_subtitle_data)
+    }
+
+    /// Extracts the text-based subtitle stream `0:s:<subtitle_index>` of a video file.
+    ///
+    /// Returns `None` if the video has no text-based subtitle stream at all.
+    pub fn open_embedded_stream(
+        file_path: &Path,
+        subtitle_index: usize,
+        sub_fps: f64,
+    ) -> Result<Option<SubtitleFileHandler>, InputSubtitleError> {
+        let streams = video_decoder::VideoDecoder::subtitle_streams(file_path)
+            .with_context(|_| InputSubtitleErrorKind::ReadingSubtitleStreamsFailed(file_path.to_path_buf()))?;
+
+        if !streams.iter().any(|stream| stream.is_text_based()) {
+            return Ok(None);
+        }
+
+        let stream = streams
+            .iter()
+            .find(|stream| stream.subtitle_index == subtitle_index)
+            .ok_or_else(|| InputSubtitleErrorKind::SubtitleStreamNotFound {
+                path: file_path.to_path_buf(),
+                subtitle_index,
+                available_streams: streams
+                    .iter()
+                    .map(|stream| stream.description())
+                    .collect::<Vec<String>>()
+                    .join(", "),
+            })?;
+
+        if !stream.is_text_based() {
+            return Err(InputSubtitleErrorKind::ImageBasedSubtitleStream {
+                path: file_path.to_path_buf(),
+                stream_description: stream.description(),
+            }
+            .into());
+        }
+
+        let sub_data = video_decoder::VideoDecoder::extract_subtitle_stream(file_path, subtitle_index)
+            .with_context(|_| InputSubtitleErrorKind::ExtractingSubtitleStreamFailed(file_path.to_path_buf()))?;
+
+        // ffmpeg always writes UTF-8
+        let file_format = subparse::SubtitleFormat::SubRip;
+        let parsed_subtitle_data: SubtitleFile = parse_bytes(file_format, &sub_data, Some(encoding_rs::UTF_8), sub_fps)
             .with_context(|_| InputSubtitleErrorKind::ParsingSubtitleFailed(file_path.to_path_buf()))?;
 
-        let subparse_timespans: Vec<TimeSpan> = parsed_subtitle_data
+        Self::from_parsed_file(
+            file_path,
+            SubtitleFileFormat::Subparse(file_format),
+            ParsedSubtitleFile::Subparse(parsed_subtitle_data),
+        )
+        .map(Some)
+    }
+
+    fn from_parsed_file(
+        file_path: &Path,
+        file_format: SubtitleFileFormat,
+        parsed_subtitle_data: ParsedSubtitleFile,
+    ) -> Result<SubtitleFileHandler, InputSubtitleError> {
+        let (subparse_timespans, texts): (Vec<TimeSpan>, Vec<String>) = parsed_subtitle_data
             .get_subtitle_entries()
             .with_context(|_| InputSubtitleErrorKind::RetrievingSubtitleLinesFailed(file_path.to_path_buf()))?
             .into_iter()
-            .map(|subentry| subentry.timespan)
-            .map(|timespan: TimeSpan| {
-                TimeSpan::new(min(timespan.start, timespan.end), max(timespan.start, timespan.end))
+            .map(|subentry| {
+                let timespan = subentry.timespan;
+                (
+                    TimeSpan::new(min(timespan.start, timespan.end), max(timespan.start, timespan.end)),
+                    subentry.line.unwrap_or_default(),
+                )
             })
-            .collect();
+            .unzip();
 
         Ok(SubtitleFileHandler {
             file_format,
             subparse_timespans,
+            texts,
             subtitle_file: parsed_subtitle_data,
         })
     }
 
-    pub fn file_format(&self) -> subparse::SubtitleFormat {
+    pub fn file_format(&self) -> SubtitleFileFormat {
         self.file_format
     }
 
@@ -220,7 +389,12 @@ impl SubtitleFileHandler {
         self.subparse_timespans.as_slice()
     }
 
-    pub fn into_subtitle_file(self) -> subparse::SubtitleFile {
+    /// The text of every line (empty for image-based lines).
+    pub fn texts(&self) -> &[String] {
+        self.texts.as_slice()
+    }
+
+    pub fn into_subtitle_file(self) -> ParsedSubtitleFile {
         self.subtitle_file
     }
 }
@@ -229,72 +403,25 @@ pub struct VideoFileHandler {
     //video_file_format: VideoFileFormat,
     subparse_timespans: Vec<TimeSpan>,
     //aligner_timespans: Vec<ilass::TimeSpan>,
+    /// Either empty or the weight (mean speech probability) of every span.
+    weights: Vec<f64>,
 }
 
 impl VideoFileHandler {
-    pub fn from_cache(timespans: Vec<TimeSpan>) -> VideoFileHandler {
+    pub fn from_cache(timespans: Vec<TimeSpan>, weights: Vec<f64>) -> VideoFileHandler {
         VideoFileHandler {
             subparse_timespans: timespans,
+            weights,
         }
     }
 
     pub fn open_video_file(
         file_path: &Path,
         audio_index: Option<usize>,
+        vad_config: &VadConfig,
         video_decode_progress: impl video_decoder::ProgressHandler,
     ) -> Result<VideoFileHandler, InputVideoError> {
-        //video_decoder::VideoDecoder::decode(file_path, );
-        use voice_activity_detector::VoiceActivityDetector;
-
-        struct SileroVad {
-            detector: VoiceActivityDetector,
-            vad_buffer: Vec<bool>,
-            sample_buffer: Vec<i16>,
-        }
-
-        impl video_decoder::AudioReceiver for SileroVad {
-            type Output = Vec<bool>;
-            type Error = InputVideoError;
-
-            fn push_samples(&mut self, samples: &[i16]) -> Result<(), InputVideoError> {
-                // Accumulate i16 samples
-                self.sample_buffer.extend_from_slice(samples);
-
-                // Silero expects 512 samples (32ms at 16kHz), process when we have enough
-                while self.sample_buffer.len() >= 512 {
-                    let chunk: Vec<i16> = self.sample_buffer.drain(..512).collect();
-                    
-                    let speech_prob = self.detector.predict(chunk);
-
-                    // Use 0.5 threshold for speech detection
-                    let is_voice = speech_prob > 0.5;
-                    self.vad_buffer.push(is_voice);
-                }
-
-                Ok(())
-            }
-
-            fn finish(mut self) -> Result<Vec<bool>, InputVideoError> {
-                // Process any remaining samples
-                if !self.sample_buffer.is_empty() {
-                    // Pad to 512 samples if needed
-                    self.sample_buffer.resize(512, 0);
-                    let speech_prob = self.detector.predict(self.sample_buffer);
-                    self.vad_buffer.push(speech_prob > 0.5);
-                }
-                Ok(self.vad_buffer)
-            }
-        }
-
-        let vad_processor = SileroVad {
-            detector: VoiceActivityDetector::builder()
-                .sample_rate(16000)
-                .chunk_size(512usize)
-                .build()
-                .map_err(|_| InputVideoErrorKind::VadAnalysisFailed)?,
-            vad_buffer: Vec::new(),
-            sample_buffer: Vec::new(),
-        };
+        let vad_processor = VadReceiver::new(vad_config.detector.create_detector()?);
 
         let chunk_processor = video_decoder::ChunkedAudioReceiver::new(160, vad_processor);
 
@@ -304,83 +431,150 @@ impl VideoFileHandler {
                     path: PathBuf::from(file_path),
                 })?;
 
-        let mut voice_segments: Vec<(i64, i64)> = Vec::new();
-        let mut voice_segment_start = 0;
-
-        let combine_with_distance_lower_than = 0;
-
-        let mut last_segment_end = 0;
-        let mut already_saved_span = true;
-
-        for (i, is_voice_segment) in vad_buffer.into_iter().chain(std::iter::once(false)).enumerate() {
-            let i = i as i64;
-
-            if is_voice_segment {
-                last_segment_end = i;
-                if already_saved_span {
-                    voice_segment_start = i;
-                    already_saved_span = false;
-                }
-            } else {
-                // not a voice segment
-                if i - last_segment_end >= combine_with_distance_lower_than && !already_saved_span {
-                    voice_segments.push((voice_segment_start, last_segment_end));
-                    already_saved_span = true;
-                }
-            }
-        }
-
-        let subparse_timespans: Vec<TimeSpan> = voice_segments
-            .into_iter()
-            .map(|(start, end)| TimeSpan::new(TimePoint::from_msecs(start * 32), TimePoint::from_msecs(end * 32)))
-            .collect();
+        let (subparse_timespans, weights) = vad_config.voice_spans(&vad_buffer);
 
         Ok(VideoFileHandler {
             //video_file_format: VideoFileFormat::NotImplemented,
             subparse_timespans,
+            weights,
         })
     }
 
-    pub fn filter_with_min_span_length_ms(&mut self, min_vad_span_length_ms: i64) {
-        self.subparse_timespans = self
-            .subparse_timespans
-            .iter()
-            .filter(|ts| ts.len() >= TimeDelta::from_msecs(min_vad_span_length_ms))
-            .cloned()
-            .collect();
+    /// Like `open_video_file`, but reuses the spans of an earlier run from `vad_cache` (and stores
+    /// newly computed spans there). Problems with the cache only produce warnings.
+    pub fn open_video_file_cached(
+        file_path: &Path,
+        audio_index: Option<usize>,
+        vad_config: &VadConfig,
+        vad_cache: &VadCache,
+        video_decode_progress: impl video_decoder::ProgressHandler,
+    ) -> Result<VideoFileHandler, InputVideoError> {
+        let key = match VadCacheKey::for_file(file_path, audio_index, vad_config.description()) {
+            Ok(key) => key,
+            Err(e) => {
+                println!("warn: voice activity cache disabled: {}", e);
+                return Self::open_video_file(file_path, audio_index, vad_config, video_decode_progress);
+            }
+        };
+
+        if let Some((timespans, weights)) = vad_cache.load(&key) {
+            println!(
+                "info: using cached voice activity of '{}' (pass '--no-cache' to analyse the audio again)",
+                file_path.display()
+            );
+            return Ok(Self::from_cache(timespans, weights));
+        }
+
+        let video_file_handler = Self::open_video_file(file_path, audio_index, vad_config, video_decode_progress)?;
+
+        if let Err(e) = vad_cache.store(&key, video_file_handler.timespans(), video_file_handler.weights()) {
+            println!("warn: failed to cache voice activity: {}", e);
+        }
+
+        Ok(video_file_handler)
     }
 
     pub fn timespans(&self) -> &[TimeSpan] {
         self.subparse_timespans.as_slice()
     }
+
+    /// Either empty or the weight of every span (see `timespans()`).
+    pub fn weights(&self) -> &[f64] {
+        self.weights.as_slice()
+    }
+}
+
+/// Splits a path like `video.mkv#s:2` into the video path and the subtitle stream (`0:s:2` in
+/// ffmpeg). Paths without this suffix (or existing files which contain it) are returned as is.
+pub fn split_subtitle_stream_path(path: &str) -> (PathBuf, Option<usize>) {
+    if let Some((video_path, stream)) = path.rsplit_once("#s:") {
+        if let Ok(subtitle_index) = stream.parse::<usize>() {
+            if !Path::new(path).exists() {
+                return (PathBuf::from(video_path), Some(subtitle_index));
+            }
+        }
+    }
+    (PathBuf::from(path), None)
+}
+
+/// Returns `true` if both paths point to the same file.
+pub fn is_same_file(a: &Path, b: &Path) -> bool {
+    match (std::fs::canonicalize(a), std::fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+/// Returns `true` if the extension belongs to a supported subtitle format (all other files are treated as videos).
+pub fn is_subtitle_file_path(file_path: &Path) -> bool {
+    let known_extensions = ["srt", "vob", "idx", "ass", "ssa", "sub", "vtt"];
+    file_path
+        .extension()
+        .map(|os_str| os_str.to_string_lossy())
+        .is_some_and(|extension| known_extensions.contains(&extension.as_ref()))
 }
 
 impl InputFileHandler {
+    /// Opens a subtitle file, a reference file (created by `ilass extract-reference`) or a video
+    /// file (using its voice activity as reference). With `subtitle_stream`, the subtitle stream
+    /// `0:s:<subtitle_stream>` of a video file is used instead (unless the video has no
+    /// text-based subtitle stream). The voice activity is cached in `vad_cache` if given.
+    #[allow(clippy::too_many_arguments)]
     pub fn open(
         file_path: &Path,
         audio_index: Option<usize>,
+        subtitle_stream: Option<usize>,
+        vad_config: &VadConfig,
+        vad_cache: Option<&VadCache>,
         sub_encoding: Option<&'static Encoding>,
         sub_fps: f64,
         video_decode_progress: impl video_decoder::ProgressHandler,
     ) -> Result<InputFileHandler, InputFileError> {
-        if let Some(extension) = file_path.extension().map(|os_str| os_str.to_string_lossy()) {
-            let known_extensions = ["srt", "vob", "idx", "ass", "ssa", "sub"];
-            if known_extensions.contains(&extension.as_ref()) {
-                return Ok(SubtitleFileHandler::open_sub_file(file_path, sub_encoding, sub_fps)
-                    .map(InputFileHandler::Subtitle)
-                    .with_context(|_| InputFileErrorKind::SubtitleFile(file_path.to_path_buf()))?);
-            }
+        if is_subtitle_file_path(file_path) {
+            return Ok(SubtitleFileHandler::open_sub_file(file_path, sub_encoding, sub_fps)
+                .map(InputFileHandler::Subtitle)
+                .with_context(|_| InputFileErrorKind::SubtitleFile(file_path.to_path_buf()))?);
+        }
+
+        if is_reference_file_path(file_path) {
+            let reference_file = ReferenceFile::read(file_path)
+                .with_context(|_| InputFileErrorKind::ReferenceFile(file_path.to_path_buf()))?;
+            return Ok(InputFileHandler::Video(VideoFileHandler::from_cache(
+                reference_file.timespans(),
+                reference_file.weights().to_vec(),
+            )));
         }
 
         // Did not match any subtitle extensions we support, assume it's a video file.
-        Ok(
-            VideoFileHandler::open_video_file(file_path, audio_index, video_decode_progress)
-                .map(InputFileHandler::Video)
-                .with_context(|_| InputFileErrorKind::VideoFile(file_path.to_path_buf()))?,
-        )
+        if let Some(subtitle_index) = subtitle_stream {
+            match SubtitleFileHandler::open_embedded_stream(file_path, subtitle_index, sub_fps)
+                .with_context(|_| InputFileErrorKind::VideoFile(file_path.to_path_buf()))?
+            {
+                Some(sub_handler) => return Ok(InputFileHandler::Subtitle(sub_handler)),
+                None => println!(
+                    "warn: '{}' has no text-based subtitle stream, falling back to voice activity detection",
+                    file_path.display()
+                ),
+            }
+        }
+
+        let video_file_handler = match vad_cache {
+            Some(vad_cache) => VideoFileHandler::open_video_file_cached(
+                file_path,
+                audio_index,
+                vad_config,
+                vad_cache,
+                video_decode_progress,
+            ),
+            None => VideoFileHandler::open_video_file(file_path, audio_index, vad_config, video_decode_progress),
+        };
+
+        Ok(video_file_handler
+            .map(InputFileHandler::Video)
+            .with_context(|_| InputFileErrorKind::VideoFile(file_path.to_path_buf()))?)
     }
 
-    pub fn into_subtitle_file(self) -> Option<SubtitleFile> {
+    pub fn into_subtitle_file(self) -> Option<ParsedSubtitleFile> {
         match self {
             InputFileHandler::Video(_) => None,
             InputFileHandler::Subtitle(sub_handler) => Some(sub_handler.subtitle_file),
@@ -394,108 +588,116 @@ impl InputFileHandler {
         }
     }
 
-    pub fn filter_video_with_min_span_length_ms(&mut self, min_vad_span_length_ms: i64) {
-        if let InputFileHandler::Video(video_handler) = self {
-            video_handler.filter_with_min_span_length_ms(min_vad_span_length_ms);
+    /// The weight (from 0 to 1) of every span in `timespans()` or an empty slice if all spans are
+    /// equally reliable (e.g. the lines of a subtitle).
+    pub fn weights(&self) -> &[f64] {
+        match self {
+            InputFileHandler::Video(video_handler) => video_handler.weights(),
+            InputFileHandler::Subtitle(_) => &[],
         }
     }
-}
-
-pub fn guess_fps_ratio(
-    ref_spans: &[ilass::TimeSpan],
-    in_spans: &[ilass::TimeSpan],
-    ratios: &[f64],
-    mut progress_handler: impl ilass::ProgressHandler,
-) -> (Option<usize>, ilass::TimeDelta) {
-    progress_handler.init(ratios.len() as i64);
-    let (delta, score) = ilass::align_nosplit(ref_spans, in_spans, ilass::overlap_scoring, ilass::NoProgressHandler);
-    progress_handler.inc();
-
-    //let desc = ["25/24", "25/23.976", "24/25", "24/23.976", "23.976/25", "23.976/24"];
-    //println!("score 1: {}", score);
-
-    let (mut opt_idx, mut opt_delta, mut opt_score) = (None, delta, score);
-
-    for (scale_factor_idx, scaling_factor) in ratios.iter().cloned().enumerate() {
-        let stretched_in_spans: Vec<ilass::TimeSpan> = in_spans.iter().map(|ts| ts.scaled(scaling_factor)).collect();
-
-        let (delta, score) = ilass::align_nosplit(
-            ref_spans,
-            &stretched_in_spans,
-            ilass::overlap_scoring,
-            ilass::NoProgressHandler,
-        );
-        progress_handler.inc();
 
-        //println!("score {}: {}", desc[scale_factor_idx], score);
+    /// The text of every span in `timespans()` or `None` for the voice activity of a video.
+    pub fn texts(&self) -> Option<&[String]> {
+        match self {
+            InputFileHandler::Video(_) => None,
+            InputFileHandler::Subtitle(sub_handler) => Some(sub_handler.texts()),
+        }
+    }
+}
 
-        if score > opt_score {
-            opt_score = score;
-            opt_idx = Some(scale_factor_idx);
-            opt_delta = delta;
+/// Writes a copy of `video_path` with the subtitle file as an additional stream to `output_path`
+/// (without re-encoding audio or video). With `replaced_stream`, the subtitle stream
+/// `0:s:<replaced_stream>` of the video is replaced instead.
+pub fn mux_subtitle_into_video(
+    video_path: &Path,
+    subtitle_path: &Path,
+    output_path: &Path,
+    replaced_stream: Option<usize>,
+    language: Option<&str>,
+    title: Option<&str>,
+) -> Result<(), TopLevelError> {
+    if is_subtitle_file_path(video_path) {
+        return Err(TopLevelErrorKind::MuxRequiresVideo {
+            path: video_path.to_path_buf(),
         }
+        .into());
+    }
+    // ffmpeg would overwrite its own input (also through a different spelling of the path or a symlink)
+    if is_same_file(video_path, output_path) {
+        return Err(TopLevelErrorKind::MuxOutputOverwritesInput {
+            path: output_path.to_path_buf(),
+        }
+        .into());
     }
 
-    progress_handler.finish();
+    video_decoder::VideoDecoder::mux_subtitle(video_path, subtitle_path, output_path, replaced_stream, language, title)
+        .with_context(|_| TopLevelErrorKind::MuxingFailed {
+            output_path: output_path.to_path_buf(),
+        })?;
 
-    (opt_idx, opt_delta)
+    Ok(())
 }
 
 pub fn print_speech_timeline_analysis(ref_timespans: &[TimeSpan], subtitle_timespans: &[TimeSpan]) {
     println!("=== SPEECH TIMELINE ANALYSIS ===");
-    
+
     // Analyze first 20 minutes of both
     let analysis_duration = TimeDelta::from_secs(20 * 60); // 20 minutes
-    
+
     let ref_in_window: Vec<_> = ref_timespans
         .iter()
         .filter(|ts| ts.start <= TimePoint::from_secs(0) + analysis_duration)
         .collect();
-    
+
     let sub_in_window: Vec<_> = subtitle_timespans
         .iter()
         .filter(|ts| ts.start <= TimePoint::from_secs(0) + analysis_duration)
         .collect();
-    
+
     println!("First 20 minutes analysis:");
     println!("  Audio speech segments: {}", ref_in_window.len());
     println!("  Subtitle lines: {}", sub_in_window.len());
-    
+
     // Calculate speech density (% of time with speech)
     let total_ref_speech: i64 = ref_in_window.iter().map(|ts| ts.len().msecs()).sum();
     let total_sub_time: i64 = sub_in_window.iter().map(|ts| ts.len().msecs()).sum();
-    
+
     let ref_density = (total_ref_speech as f64 / (20.0 * 60.0 * 1000.0)) * 100.0;
     let sub_density = (total_sub_time as f64 / (20.0 * 60.0 * 1000.0)) * 100.0;
-    
+
     println!("  Audio speech density: {:.1}% of first 20 minutes", ref_density);
     println!("  Subtitle time density: {:.1}% of first 20 minutes", sub_density);
-    
+
     // Show ALL audio segments in first 20 minutes
     println!("\nAll audio speech segments in first 20 minutes:");
     for (i, ts) in ref_in_window.iter().enumerate() {
-        println!("  {:3}: {:>8} - {:>8} ({})", 
-                 i+1,
-                 format_timepoint(ts.start),
-                 format_timepoint(ts.end), 
-                 format_duration(ts.len()));
+        println!(
+            "  {:3}: {:>8} - {:>8} ({})",
+            i + 1,
+            format_timepoint(ts.start),
+            format_timepoint(ts.end),
+            format_duration(ts.len())
+        );
     }
-    
+
     // Show ALL subtitle lines in first 20 minutes
     println!("\nAll subtitle timings in first 20 minutes:");
     for (i, ts) in sub_in_window.iter().enumerate() {
-        println!("  {:3}: {:>8} - {:>8} ({})", 
-                 i+1,
-                 format_timepoint(ts.start),
-                 format_timepoint(ts.end),
-                 format_duration(ts.len()));
-    }
-    
-    // Find gaps in audio vs subtitles  
+        println!(
+            "  {:3}: {:>8} - {:>8} ({})",
+            i + 1,
+            format_timepoint(ts.start),
+            format_timepoint(ts.end),
+            format_duration(ts.len())
+        );
+    }
+
+    // Find gaps in audio vs subtitles
     println!("\nLargest gaps in first 20 minutes:");
     print_largest_gaps("Audio", &ref_in_window, 5);
     print_largest_gaps("Subtitle", &sub_in_window, 5);
-    
+
     println!("=== END SPEECH TIMELINE ANALYSIS ===\n");
 }
 
@@ -514,20 +716,23 @@ fn format_duration(td: TimeDelta) -> String {
 fn print_largest_gaps(label: &str, timespans: &[&TimeSpan], count: usize) {
     let mut gaps = Vec::new();
     for i in 1..timespans.len() {
-        let gap = timespans[i].start - timespans[i-1].end;
-        if gap > TimeDelta::from_secs(1) { // Only gaps > 1 second
-            gaps.push((gap, timespans[i-1].end, timespans[i].start));
+        let gap = timespans[i].start - timespans[i - 1].end;
+        if gap > TimeDelta::from_secs(1) {
+            // Only gaps > 1 second
+            gaps.push((gap, timespans[i - 1].end, timespans[i].start));
         }
     }
     gaps.sort_by_key(|&(gap, _, _)| -gap.msecs()); // Sort by gap size, descending
-    
+
     for (i, (gap, start, end)) in gaps.iter().take(count).enumerate() {
-        println!("  {} gap #{}: {} ({} - {})", 
-                 label, 
-                 i+1, 
-                 format_duration(*gap),
-                 format_timepoint(*start), 
-                 format_timepoint(*end));
+        println!(
+            "  {} gap #{}: {} ({} - {})",
+            label,
+            i + 1,
+            format_duration(*gap),
+            format_timepoint(*start),
+            format_timepoint(*end)
+        );
     }
 }
 
@@ -548,44 +753,43 @@ pub fn validate_fps_ratio_on_split_groups(
         .max_by_key(|(_, timespans)| timespans.len())
         .expect("should have at least one split group");
 
-    println!("info: validating framerate detection on largest split group ({} subtitles)", longest_group.1.len());
+    println!(
+        "info: validating framerate detection on largest split group ({} subtitles)",
+        longest_group.1.len()
+    );
 
     // Convert the longest group's timespans to algorithm format (without current scaling)
     let group_alg_timespans: Vec<ilass::TimeSpan> = timings_to_alg_timespans(&longest_group.1, interval);
 
     // Test framerate ratios on this split group
-    let ratios = [25./24., 25./23.976, 24./25., 24./23.976, 23.976/25., 23.976/24.];
+    let ratios = [
+        25. / 24.,
+        25. / 23.976,
+        24. / 25.,
+        24. / 23.976,
+        23.976 / 25.,
+        23.976 / 24.,
+    ];
     let desc = ["25/24", "25/23.976", "24/25", "24/23.976", "23.976/25", "23.976/24"];
 
     println!("info: framerate validation results for largest split group:");
 
-    // Test original (no scaling)
-    let (_, original_score) = ilass::align_nosplit(
-        ref_spans,
-        &group_alg_timespans,
-        ilass::overlap_scoring,
-        ilass::NoProgressHandler,
-    );
+    // Test original (no scaling) and each ratio (these alignments are independent)
+    let candidates: Vec<f64> = std::iter::once(1.).chain(ratios.iter().cloned()).collect();
+    let results = ilass::align_nosplit_scaled(ref_spans, &group_alg_timespans, &candidates);
+
+    let original_score = results[0].1;
     println!("  1.0 (original): {:.6}", original_score);
 
-    // Test each ratio
     let mut best_score = original_score;
     let mut best_ratio_idx = None;
 
-    for (ratio_idx, &scaling_factor) in ratios.iter().enumerate() {
-        let stretched_spans: Vec<ilass::TimeSpan> = group_alg_timespans
-            .iter()
-            .map(|ts| ts.scaled(scaling_factor))
-            .collect();
-
-        let (_, score) = ilass::align_nosplit(
-            ref_spans,
-            &stretched_spans,
-            ilass::overlap_scoring,
-            ilass::NoProgressHandler,
-        );
-
-        let marker = if (scaling_factor - original_fps_scaling_factor).abs() < 0.001 { " <- chosen" } else { "" };
+    for (ratio_idx, (&scaling_factor, &(_, score))) in ratios.iter().zip(&results[1..]).enumerate() {
+        let marker = if (scaling_factor - original_fps_scaling_factor).abs() < 0.001 {
+            " <- chosen"
+        } else {
+            ""
+        };
         println!("  {} ({}): {:.6}{}", desc[ratio_idx], scaling_factor, score, marker);
 
         if score > best_score {
@@ -598,10 +802,16 @@ pub fn validate_fps_ratio_on_split_groups(
     if let Some(best_idx) = best_ratio_idx {
         let best_ratio = ratios[best_idx];
         if (best_ratio - original_fps_scaling_factor).abs() > 0.001 {
-            println!("warning: split-aware analysis suggests {} ({}) might be better than chosen {} ({:.6})",
-                desc[best_idx], best_ratio, original_fps_scaling_factor, original_fps_scaling_factor);
-            println!("  improvement: {:.6} -> {:.6} ({:+.6})", 
-                original_score, best_score, best_score - original_score);
+            println!(
+                "warning: split-aware analysis suggests {} ({}) might be better than chosen {} ({:.6})",
+                desc[best_idx], best_ratio, original_fps_scaling_factor, original_fps_scaling_factor
+            );
+            println!(
+                "  improvement: {:.6} -> {:.6} ({:+.6})",
+                original_score,
+                best_score,
+                best_score - original_score
+            );
         } else {
             println!("info: split-aware analysis confirms chosen framerate ratio is optimal for largest group");
         }
@@ -635,3 +845,26 @@ pub fn print_error_chain(error: failure::Error) {
         println!("not: run with environment variable 'RUST_BACKTRACE=1' for detailed stack traces");
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    /// A new empty directory which is deleted when the value is dropped.
+    pub struct TempDir(pub PathBuf);
+
+    impl TempDir {
+        pub fn new(name: &str) -> TempDir {
+            let dir = std::env::temp_dir().join(format!("ilass-test-{}-{}", name, std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            TempDir(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+}
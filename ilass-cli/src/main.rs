@@ -11,12 +11,12 @@ extern crate subparse;
 
 use crate::subparse::SubtitleFileInterface;
 
-use clap::{Arg, ArgAction, command};
+use clap::{Arg, ArgAction, Command, command};
 use encoding_rs::Encoding;
 use failure::ResultExt;
-use ilass::{TimeDelta as AlgTimeDelta, align};
+use ilass::{AlignMode, AlignmentConfig, Anchor, SplitPenaltyCurve, TimeDelta as AlgTimeDelta, align_with_config};
 use std::ffi::OsStr;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::result::Result;
 use std::str::FromStr;
 
@@ -24,6 +24,10 @@ use subparse::timetypes::*;
 use subparse::{SubtitleEntry, SubtitleFormat};
 
 use ilass_cli::errors::*;
+use ilass_cli::fingerprint::{self, Fingerprint};
+use ilass_cli::reference_file::{ReferenceFile, is_reference_file_path};
+use ilass_cli::vad::{VadConfig, VadKind};
+use ilass_cli::vad_cache::VadCache;
 use ilass_cli::*;
 
 /// Does reading, parsing and nice error handling for a f64 clap parameter.
@@ -54,6 +58,22 @@ fn unpack_clap_number_i64(
         .map_err(InputArgumentsError::from)
 }
 
+fn unpack_optional_clap_number_f64(
+    matches: &clap::ArgMatches,
+    parameter_name: &'static str,
+) -> Result<Option<f64>, InputArgumentsError> {
+    match matches.get_one::<String>(parameter_name) {
+        None => Ok(None),
+        Some(parameter_value_str) => f64::from_str(parameter_value_str)
+            .with_context(|_| InputArgumentsErrorKind::ArgumentParseError {
+                argument_name: parameter_name.to_string(),
+                value: parameter_value_str.to_string(),
+            })
+            .map(Some)
+            .map_err(InputArgumentsError::from),
+    }
+}
+
 fn unpack_optional_clap_number_usize(
     matches: &clap::ArgMatches,
     parameter_name: &'static str,
@@ -70,6 +90,136 @@ fn unpack_optional_clap_number_usize(
     }
 }
 
+/// Parses a time like "01:02:03.456" (hours and milliseconds are optional).
+fn parse_time_point(s: &str) -> Option<TimePoint> {
+    let (hms, msecs) = match s.split_once('.') {
+        Some((hms, msecs)) if !msecs.is_empty() && msecs.len() <= 3 => (
+            hms,
+            u32::from_str(msecs).ok()? as i64 * 10i64.pow(3 - msecs.len() as u32),
+        ),
+        Some(_) => return None,
+        None => (s, 0),
+    };
+
+    let parts: Vec<i64> = hms
+        .split(':')
+        .map(|part| u32::from_str(part).ok().map(i64::from))
+        .collect::<Option<Vec<i64>>>()?;
+    let (hours, mins, secs) = match parts.as_slice() {
+        [mins, secs] => (0, *mins, *secs),
+        [hours, mins, secs] => (*hours, *mins, *secs),
+        _ => return None,
+    };
+    if mins >= 60 || secs >= 60 {
+        return None;
+    }
+
+    Some(TimePoint::from_msecs(((hours * 60 + mins) * 60 + secs) * 1000 + msecs))
+}
+
+/// Reads all "LINE=HH:MM:SS.mmm" values of the anchor parameter (line numbers start at 1).
+fn unpack_anchors(
+    matches: &clap::ArgMatches,
+    parameter_name: &'static str,
+) -> Result<Vec<(usize, TimePoint)>, InputArgumentsError> {
+    let parameter_values = match matches.get_many::<String>(parameter_name) {
+        None => return Ok(Vec::new()),
+        Some(parameter_values) => parameter_values,
+    };
+
+    parameter_values
+        .map(|parameter_value_str| {
+            let anchor_opt = parameter_value_str.split_once('=').and_then(|(line_str, time_str)| {
+                let line = usize::from_str(line_str.trim()).ok().filter(|&line| line > 0)?;
+                Some((line, parse_time_point(time_str.trim())?))
+            });
+
+            anchor_opt.ok_or_else(|| {
+                InputArgumentsError::from(InputArgumentsErrorKind::InvalidFormat {
+                    argument_name: parameter_name.to_string(),
+                    expected_format: "LINE=HH:MM:SS.mmm".to_string(),
+                    value: parameter_value_str.to_string(),
+                })
+            })
+        })
+        .collect()
+}
+
+/// Reads a curve like "0.5:4,5:1" (gaps in seconds and factors of the split penalty, see
+/// `SplitPenaltyCurve`). Returns an empty vector if the parameter is missing.
+fn unpack_split_gap_curve(
+    matches: &clap::ArgMatches,
+    parameter_name: &'static str,
+) -> Result<Vec<(TimeDelta, f64)>, InputArgumentsError> {
+    let parameter_value_str = match matches.get_one::<String>(parameter_name) {
+        None => return Ok(Vec::new()),
+        Some(parameter_value_str) => parameter_value_str,
+    };
+
+    let points_opt: Option<Vec<(TimeDelta, f64)>> = parameter_value_str
+        .split(',')
+        .map(|point_str| {
+            let (gap_str, factor_str) = point_str.split_once(':')?;
+            let gap_secs = f64::from_str(gap_str.trim()).ok().filter(|gap| *gap >= 0.0)?;
+            let factor = f64::from_str(factor_str.trim())
+                .ok()
+                .filter(|factor| factor.is_finite() && *factor >= 0.0)?;
+            Some((TimeDelta::from_msecs((gap_secs * 1000.0).round() as i64), factor))
+        })
+        .collect();
+
+    points_opt
+        .filter(|points| points.windows(2).all(|pair| pair[0].0 < pair[1].0))
+        .ok_or_else(|| {
+            InputArgumentsErrorKind::InvalidFormat {
+                argument_name: parameter_name.to_string(),
+                expected_format: "SECONDS:FACTOR,SECONDS:FACTOR,... (with increasing gaps)".to_string(),
+                value: parameter_value_str.to_string(),
+            }
+            .into()
+        })
+}
+
+/// Reads the `--vad-*` parameters (see `vad_args`).
+fn unpack_vad_config(matches: &clap::ArgMatches) -> Result<VadConfig, InputArgumentsError> {
+    let threshold = unpack_clap_number_f64(matches, "vad-threshold")?;
+    if !(0.0..=1.0).contains(&threshold) {
+        return Err(InputArgumentsErrorKind::ValueNotInRange {
+            argument_name: "vad-threshold".to_string(),
+            value: threshold,
+            min: 0.0,
+            max: 1.0,
+        }
+        .into());
+    }
+
+    let unpack_msecs = |parameter_name: &'static str| -> Result<i64, InputArgumentsError> {
+        let value = unpack_clap_number_i64(matches, parameter_name)?;
+        if value < 0 {
+            return Err(InputArgumentsErrorKind::ExpectedNonNegativeNumber {
+                argument_name: parameter_name.to_string(),
+                value: value as f64,
+            }
+            .into());
+        }
+        Ok(value)
+    };
+
+    let detector = matches
+        .get_one::<String>("vad")
+        .and_then(|name| VadKind::from_name(name))
+        .unwrap_or_default();
+
+    Ok(VadConfig {
+        detector,
+        threshold: threshold as f32,
+        min_speech_ms: unpack_msecs("vad-min-speech")?,
+        min_silence_ms: unpack_msecs("vad-min-silence")?,
+        pre_padding_ms: unpack_msecs("vad-pad-before")?,
+        post_padding_ms: unpack_msecs("vad-pad-after")?,
+    })
+}
+
 pub fn get_encoding(opt: Option<&str>) -> Option<&'static Encoding> {
     match opt {
         None | Some("auto") => {
@@ -90,14 +240,41 @@ pub fn get_encoding(opt: Option<&str>) -> Option<&'static Encoding> {
 
 // //////////////////////////////////////////////////////////////////////////////////////////////////
 
+/// Range of the framerate ratios for `--fps-search=continuous`.
+const MIN_FPS_RATIO: f64 = 0.9;
+const MAX_FPS_RATIO: f64 = 1.1;
+
+/// The split penalties which are tried for `--split-penalty auto`.
+const AUTO_SPLIT_PENALTIES: [f64; 11] = [1., 2., 3., 5., 7., 10., 14., 20., 30., 50., 100.];
+
 struct Arguments {
     reference_file_path: PathBuf,
     incorrect_file_path: PathBuf,
     output_file_path: PathBuf,
 
+    /// subtitle stream (`0:s:N`) if the incorrect file was given as `video.mkv#s:N`
+    incorrect_subtitle_stream: Option<usize>,
+
+    /// video which belongs to the incorrect subtitle; its voice activity is aligned instead of the lines
+    incorrect_source_path: Option<PathBuf>,
+
+    /// the offsets are restricted to this distance around the offset of the audio fingerprints
+    /// (requires `incorrect_source_path`)
+    prealign_window: Option<TimeDelta>,
+
     interval: i64,
 
-    split_penalty: f64,
+    /// `None` if the split penalty is chosen from `AUTO_SPLIT_PENALTIES` (see `ilass::choose_split_penalty`)
+    split_penalty: Option<f64>,
+
+    /// factors of the split penalty at these gaps between lines (empty: the same penalty for all gaps)
+    split_gap_curve: Vec<(TimeDelta, f64)>,
+
+    /// added to the split penalty for every second the offset changes
+    split_jump_penalty: f64,
+
+    /// splits which change the offset by less than this are not allowed
+    min_split_jump: TimeDelta,
 
     sub_fps_inc: f64,
     sub_fps_ref: f64,
@@ -109,29 +286,160 @@ struct Arguments {
     encoding_inc: Option<&'static Encoding>,
 
     guess_fps_ratio: bool,
+    continuous_fps_search: bool,
     no_split_mode: bool,
+    drift_mode: bool,
+
+    /// the text similarity of the lines is rated too (requires a subtitle as reference)
+    use_text: bool,
+
     speed_optimization: Option<f64>,
 
     audio_index: Option<usize>,
+    vad_config: VadConfig,
+
+    /// subtitle stream (`0:s:N`) of the reference video which is used instead of the voice activity
+    ref_subtitle_stream: Option<usize>,
+
+    no_cache: bool,
+    clear_cache: bool,
+
+    /// line number (starting at 1) and start time of anchored lines
+    anchors: Vec<(usize, TimePoint)>,
+
+    /// having a value of `None` means that the offsets are unbounded
+    max_offset: Option<TimeDelta>,
+
+    /// offsets near this offset are preferred, together with the width and the weight of the bonus
+    expected_offset: Option<(TimeDelta, TimeDelta, f64)>,
+
+    /// copy of the reference video with the corrected subtitle as additional stream
+    mux_into: Option<PathBuf>,
+    mux_language: Option<String>,
+    mux_title: Option<String>,
 }
 
-fn parse_args() -> Result<Arguments, InputArgumentsError> {
+/// Arguments of `ilass extract-reference`.
+struct ExtractReferenceArguments {
+    video_file_path: PathBuf,
+    output_file_path: PathBuf,
+    audio_index: Option<usize>,
+    vad_config: VadConfig,
+    no_cache: bool,
+}
+
+enum CliCommand {
+    Align(Box<Arguments>),
+    ExtractReference(ExtractReferenceArguments),
+}
+
+/// Settings of the voice activity detection (for the reference video and `extract-reference`).
+fn vad_args() -> [Arg; 6] {
+    [
+        Arg::new("vad")
+            .help("Voice activity detector: 'silero' (neural network, accurate) or 'energy' (fast, needs no ONNX runtime, but mistakes more music and noise for speech)")
+            .long("vad")
+            .value_name("silero|energy")
+            .value_parser(VadKind::ALL.map(VadKind::name))
+            .default_value(VadKind::default().name()),
+        Arg::new("vad-threshold")
+            .help("Speech probability (from 0 to 1) above which audio counts as voice. Lower values detect more (quiet or noisy) speech, higher values ignore more music and noise.")
+            .long("vad-threshold")
+            .value_name("PROBABILITY")
+            .default_value("0.5"),
+        Arg::new("vad-min-speech")
+            .help("Drops detected voice spans which are shorter than this")
+            .long("vad-min-speech")
+            .value_name("MILLISECONDS")
+            .default_value("500"),
+        Arg::new("vad-min-silence")
+            .help("Merges voice spans which are separated by a shorter silence")
+            .long("vad-min-silence")
+            .value_name("MILLISECONDS")
+            .default_value("0"),
+        Arg::new("vad-pad-before")
+            .help("Extends every voice span by this time at the start")
+            .long("vad-pad-before")
+            .value_name("MILLISECONDS")
+            .default_value("0"),
+        Arg::new("vad-pad-after")
+            .help("Extends every voice span by this time at the end")
+            .long("vad-pad-after")
+            .value_name("MILLISECONDS")
+            .default_value("0"),
+    ]
+}
+
+fn parse_args() -> Result<CliCommand, InputArgumentsError> {
     let matches = command!()
+        .subcommand_negates_reqs(true)
+        .args_conflicts_with_subcommands(true)
+        .subcommand(Command::new("extract-reference")
+            .about("Runs the voice activity detection on a video once and stores the result in a reference file (.json), which can be used instead of the video as reference file")
+            .arg(Arg::new("video-file")
+                .help("Path to the video file")
+                .required(true))
+            .arg(Arg::new("output-file-path")
+                .help("Path to the reference file (.json)")
+                .required(true))
+            .arg(Arg::new("audio-index")
+                .help("Specifies the audio index in the video file")
+                .long("index")
+                .value_name("audio-index")
+                .required(false))
+            .args(vad_args())
+            .arg(Arg::new("no-cache")
+                .help("Neither reads nor writes the cached voice activity of the video")
+                .long("no-cache")
+                .action(ArgAction::SetTrue)))
         .arg(Arg::new("reference-file")
-            .help("Path to the reference subtitle or video file")
+            .help("Path to the reference subtitle file, video file or reference file (.json, see 'ilass extract-reference')")
             .required(true))
         .arg(Arg::new("incorrect-sub-file")
-            .help("Path to the incorrect subtitle file. Entering \"_\" here creates debug subtitles, which can later be used as a reference file.")
+            .help("Path to the incorrect subtitle file. A subtitle stream of a video can be given as \"video.mkv#s:N\" (counted from 0 among the subtitle streams, like ffmpeg's '0:s:N'). Entering \"_\" here creates debug subtitles, which can later be used as a reference file.")
             .required(true))
         .arg(Arg::new("output-file-path")
             .help("Path to corrected subtitle file")
             .required(true))
+        .arg(Arg::new("incorrect-source")
+            .help("Video (or reference file) which the incorrect subtitle belongs to, e.g. another cut or dub of the film. Its voice activity is aligned to the reference file and the resulting offsets are applied to the subtitle lines.")
+            .long("incorrect-source")
+            .value_name("VIDEO")
+            .conflicts_with_all(["anchor", "drift"])
+            .required(false))
+        .arg(Arg::new("prealign")
+            .help("Compares the audio fingerprints of the reference video and the incorrect source first and only searches offsets near their best match. Avoids wrong offsets in films with long music passages, but fails for audio with a different speed. Decodes the audio of both videos a second time.")
+            .long("prealign")
+            .requires("incorrect-source")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("prealign-window")
+            .help("Only searches offsets within this distance around the offset found by '--prealign'")
+            .long("prealign-window")
+            .value_name("SECONDS")
+            .default_value("60"))
         .arg(Arg::new("split-penalty")
             .short('p')
             .long("split-penalty")
-            .value_name("floating point number from 0 to 1000")
-            .help("Determines how eager the algorithm is to avoid splitting of the subtitles. 1000 means that all lines will be shifted by the same offset, while 0.01 will produce MANY segments with different offsets. Values from 1 to 20 are the most useful.")
+            .value_name("floating point number from 0 to 1000 or 'auto'")
+            .help("Determines how eager the algorithm is to avoid splitting of the subtitles. 1000 means that all lines will be shifted by the same offset, while 0.01 will produce MANY segments with different offsets. Values from 1 to 20 are the most useful. 'auto' tries several values and chooses the one after which more splits barely improve the score (falls back to 7).")
             .default_value("7"))
+        .arg(Arg::new("split-gap-curve")
+            .long("split-gap-curve")
+            .value_name("SECONDS:FACTOR,...")
+            .help("Scales the split penalty by the gap between two lines: e.g. \"0.5:4,5:1\" makes splits at gaps up to 0.5s four times as expensive and splits at gaps from 5s as expensive as the split penalty (interpolated in between). Avoids splits in the middle of dense dialogue.")
+            .conflicts_with("no-split"))
+        .arg(Arg::new("split-jump-penalty")
+            .long("split-jump-penalty")
+            .value_name("floating point number from 0 to 1000")
+            .help("Added to the split penalty for every second the offset changes at a split, so small corrections are cheaper than large jumps.")
+            .conflicts_with_all(["no-split", "drift"])
+            .default_value("0"))
+        .arg(Arg::new("min-split-jump")
+            .long("min-split-jump")
+            .value_name("SECONDS")
+            .help("Forbids splits which change the offset by less than this, so the output does not contain blocks which are only shifted by a few milliseconds.")
+            .conflicts_with_all(["no-split", "drift"])
+            .default_value("0"))
         .arg(Arg::new("interval")
             .short('i')
             .long("interval")
@@ -181,22 +489,110 @@ fn parse_args() -> Result<Arguments, InputArgumentsError> {
             .short('l')
             .long("no-split")
             .action(ArgAction::SetTrue))
+        .arg(Arg::new("drift")
+            .help("Allows each block of subtitles to get its own small framerate correction in addition to its offset (for drift that changes over time) - this mode is slower")
+            .long("drift")
+            .conflicts_with("no-split")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("use-text")
+            .help("Also compares the text of the lines (the reference has to be a subtitle in the same language, e.g. of another release with identical dialogue)")
+            .long("use-text")
+            .conflicts_with("incorrect-source")
+            .action(ArgAction::SetTrue))
         .arg(Arg::new("disable-fps-guessing")
             .help("Disables guessing and correcting of framerate differences between reference file and input file")
             .short('g')
             .long("disable-fps-guessing")
             .alias("disable-framerate-guessing")
             .action(ArgAction::SetTrue))
+        .arg(Arg::new("fps-search")
+            .help("'ratios' only tries the common framerate ratios (like 25/24), 'continuous' searches an arbitrary ratio between 0.9 and 1.1 (slower, but also fixes unusual drift)")
+            .long("fps-search")
+            .value_name("ratios|continuous")
+            .value_parser(["ratios", "continuous"])
+            .default_value("ratios"))
         .arg(Arg::new("audio-index")
             .help("Specifies the audio index in the reference video file")
             .long("index")
             .value_name("audio-index")
             .required(false))
-        .after_help("This program works with .srt, .ass/.ssa, .idx and .sub files. The corrected file will have the same format as the incorrect file.")
+        .args(vad_args())
+        .arg(Arg::new("ref-subtitle-stream")
+            .help("Uses the subtitle stream N (counted from 0 among the subtitle streams, like ffmpeg's '0:s:N') of the reference video instead of its voice activity. Falls back to voice activity detection if the video has no text-based subtitle stream.")
+            .long("ref-subtitle-stream")
+            .value_name("N")
+            .required(false))
+        .arg(Arg::new("no-cache")
+            .help("Neither reads nor writes the cached voice activity of the reference video")
+            .long("no-cache")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("clear-cache")
+            .help("Deletes all cached voice activity before running (the cache directory can be set with the environment variable ILASS_CACHE_DIR)")
+            .long("clear-cache")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("anchor")
+            .help("Fixes the start time of a line in the incorrect subtitle file (line numbers start at 1). Can be given multiple times.")
+            .long("anchor")
+            .value_name("LINE=HH:MM:SS.mmm")
+            .action(ArgAction::Append)
+            .required(false))
+        .arg(Arg::new("max-offset")
+            .help("Only searches for offsets between -SECONDS and +SECONDS. This is faster and avoids matches far away from the original position.")
+            .long("max-offset")
+            .value_name("SECONDS")
+            .required(false))
+        .arg(Arg::new("expected-offset")
+            .help("Prefers offsets near this offset (e.g. known from a previous run), which breaks ties on repetitive dialogue. Cannot be combined with '--drift'.")
+            .long("expected-offset")
+            .value_name("SECONDS")
+            .allow_negative_numbers(true)
+            .conflicts_with("drift")
+            .required(false))
+        .arg(Arg::new("expected-offset-width")
+            .help("The bonus of '--expected-offset' falls off linearly to zero at this distance")
+            .long("expected-offset-width")
+            .value_name("SECONDS")
+            .requires("expected-offset")
+            .default_value("10"))
+        .arg(Arg::new("expected-offset-weight")
+            .help("The bonus of '--expected-offset' for every line at the expected offset (a perfectly matching line is rated 1)")
+            .long("expected-offset-weight")
+            .value_name("floating point number from 0 to 1")
+            .requires("expected-offset")
+            .default_value("0.1"))
+        .arg(Arg::new("mux-into")
+            .help("Additionally writes a copy of the reference video with the corrected subtitle as a new subtitle stream to this file. Audio and video are not re-encoded.")
+            .long("mux-into")
+            .value_name("VIDEO")
+            .required(false))
+        .arg(Arg::new("mux-language")
+            .help("Language of the new subtitle stream (e.g. 'eng')")
+            .long("mux-language")
+            .value_name("LANGUAGE")
+            .requires("mux-into")
+            .required(false))
+        .arg(Arg::new("mux-title")
+            .help("Title of the new subtitle stream")
+            .long("mux-title")
+            .value_name("TITLE")
+            .requires("mux-into")
+            .required(false))
+        .after_help("This program works with .srt, .vtt, .ass/.ssa, .idx and .sub files. The corrected file will have the same format as the incorrect file, unless the extension of the output file selects another format (.srt, .vtt, .ass/.ssa or .sub) - then the text and basic styling are converted.")
         .get_matches();
 
+    if let Some(matches) = matches.subcommand_matches("extract-reference") {
+        return Ok(CliCommand::ExtractReference(ExtractReferenceArguments {
+            video_file_path: matches.get_one::<String>("video-file").unwrap().into(),
+            output_file_path: matches.get_one::<String>("output-file-path").unwrap().into(),
+            audio_index: unpack_optional_clap_number_usize(matches, "audio-index")?,
+            vad_config: unpack_vad_config(matches)?,
+            no_cache: matches.get_flag("no-cache"),
+        }));
+    }
+
     let reference_file_path: PathBuf = matches.get_one::<String>("reference-file").unwrap().into();
-    let incorrect_file_path: PathBuf = matches.get_one::<String>("incorrect-sub-file").unwrap().into();
+    let (incorrect_file_path, incorrect_subtitle_stream) =
+        split_subtitle_stream_path(matches.get_one::<String>("incorrect-sub-file").unwrap());
     let output_file_path: PathBuf = matches.get_one::<String>("output-file-path").unwrap().into();
 
     let interval: i64 = unpack_clap_number_i64(&matches, "interval")?;
@@ -208,18 +604,43 @@ fn parse_args() -> Result<Arguments, InputArgumentsError> {
         .into());
     }
 
-    let split_penalty: f64 = unpack_clap_number_f64(&matches, "split-penalty")?;
     let split_penalty_range = 0.0..=1000.0;
-    if !split_penalty_range.contains(&split_penalty) {
+    let split_penalty: Option<f64> = if matches.get_one::<String>("split-penalty").unwrap() == "auto" {
+        None
+    } else {
+        let split_penalty: f64 = unpack_clap_number_f64(&matches, "split-penalty")?;
+        if !split_penalty_range.contains(&split_penalty) {
+            return Err(InputArgumentsErrorKind::ValueNotInRange {
+                argument_name: "split-penalty".to_string(),
+                value: split_penalty,
+                min: *split_penalty_range.start(),
+                max: *split_penalty_range.end(),
+            }
+            .into());
+        }
+        Some(split_penalty)
+    };
+
+    let split_jump_penalty: f64 = unpack_clap_number_f64(&matches, "split-jump-penalty")?;
+    if !split_penalty_range.contains(&split_jump_penalty) {
         return Err(InputArgumentsErrorKind::ValueNotInRange {
-            argument_name: "split-penalty".to_string(),
-            value: split_penalty,
+            argument_name: "split-jump-penalty".to_string(),
+            value: split_jump_penalty,
             min: *split_penalty_range.start(),
             max: *split_penalty_range.end(),
         }
         .into());
     }
 
+    let min_split_jump_secs: f64 = unpack_clap_number_f64(&matches, "min-split-jump")?;
+    if min_split_jump_secs < 0.0 {
+        return Err(InputArgumentsErrorKind::ExpectedNonNegativeNumber {
+            argument_name: "min-split-jump".to_string(),
+            value: min_split_jump_secs,
+        }
+        .into());
+    }
+
     let speed_optimization: f64 = unpack_clap_number_f64(&matches, "speed-optimization")?;
     if speed_optimization < 0.0 {
         return Err(InputArgumentsErrorKind::ExpectedNonNegativeNumber {
@@ -229,34 +650,128 @@ fn parse_args() -> Result<Arguments, InputArgumentsError> {
         .into());
     }
 
+    let max_offset_secs: Option<f64> = unpack_optional_clap_number_f64(&matches, "max-offset")?;
+    if let Some(max_offset_secs) = max_offset_secs {
+        if max_offset_secs < 0.0 {
+            return Err(InputArgumentsErrorKind::ExpectedNonNegativeNumber {
+                argument_name: "max-offset".to_string(),
+                value: max_offset_secs,
+            }
+            .into());
+        }
+    }
+
+    let expected_offset = match unpack_optional_clap_number_f64(&matches, "expected-offset")? {
+        None => None,
+        Some(offset_secs) => {
+            let width_secs: f64 = unpack_clap_number_f64(&matches, "expected-offset-width")?;
+            if width_secs < 0.0 {
+                return Err(InputArgumentsErrorKind::ExpectedNonNegativeNumber {
+                    argument_name: "expected-offset-width".to_string(),
+                    value: width_secs,
+                }
+                .into());
+            }
+
+            let weight: f64 = unpack_clap_number_f64(&matches, "expected-offset-weight")?;
+            if !(0.0..=1.0).contains(&weight) {
+                return Err(InputArgumentsErrorKind::ValueNotInRange {
+                    argument_name: "expected-offset-weight".to_string(),
+                    value: weight,
+                    min: 0.0,
+                    max: 1.0,
+                }
+                .into());
+            }
+
+            Some((
+                TimeDelta::from_msecs((offset_secs * 1000.0).round() as i64),
+                TimeDelta::from_msecs((width_secs * 1000.0).round() as i64),
+                weight,
+            ))
+        }
+    };
+
+    let prealign_window_secs: f64 = unpack_clap_number_f64(&matches, "prealign-window")?;
+    if prealign_window_secs < 0.0 {
+        return Err(InputArgumentsErrorKind::ExpectedNonNegativeNumber {
+            argument_name: "prealign-window".to_string(),
+            value: prealign_window_secs,
+        }
+        .into());
+    }
+
     let no_split_mode: bool = matches.get_flag("no-split");
 
-    Ok(Arguments {
+    Ok(CliCommand::Align(Box::new(Arguments {
         reference_file_path,
         incorrect_file_path,
+        incorrect_subtitle_stream,
+        incorrect_source_path: matches.get_one::<String>("incorrect-source").map(PathBuf::from),
+        prealign_window: if matches.get_flag("prealign") {
+            Some(TimeDelta::from_msecs((prealign_window_secs * 1000.0).round() as i64))
+        } else {
+            None
+        },
         output_file_path,
         interval,
         split_penalty,
+        split_gap_curve: unpack_split_gap_curve(&matches, "split-gap-curve")?,
+        split_jump_penalty,
+        min_split_jump: TimeDelta::from_msecs((min_split_jump_secs * 1000.0).round() as i64),
         sub_fps_ref: unpack_clap_number_f64(&matches, "sub-fps-ref")?,
         sub_fps_inc: unpack_clap_number_f64(&matches, "sub-fps-inc")?,
         allow_negative_timestamps: matches.get_flag("allow-negative-timestamps"),
         encoding_ref: get_encoding(matches.get_one::<String>("encoding-ref").map(|s| s.as_str())),
         encoding_inc: get_encoding(matches.get_one::<String>("encoding-inc").map(|s| s.as_str())),
         no_split_mode,
+        drift_mode: matches.get_flag("drift"),
+        use_text: matches.get_flag("use-text"),
         guess_fps_ratio: !matches.get_flag("disable-fps-guessing"),
+        continuous_fps_search: matches.get_one::<String>("fps-search").map(String::as_str) == Some("continuous"),
         speed_optimization: if speed_optimization <= 0. {
             None
         } else {
             Some(speed_optimization)
         },
         audio_index: unpack_optional_clap_number_usize(&matches, "audio-index")?,
-    })
+        vad_config: unpack_vad_config(&matches)?,
+        ref_subtitle_stream: unpack_optional_clap_number_usize(&matches, "ref-subtitle-stream")?,
+        no_cache: matches.get_flag("no-cache"),
+        clear_cache: matches.get_flag("clear-cache"),
+        anchors: unpack_anchors(&matches, "anchor")?,
+        max_offset: max_offset_secs.map(|secs| TimeDelta::from_msecs((secs * 1000.0).round() as i64)),
+        expected_offset,
+        mux_into: matches.get_one::<String>("mux-into").map(PathBuf::from),
+        mux_language: matches.get_one::<String>("mux-language").cloned(),
+        mux_title: matches.get_one::<String>("mux-title").cloned(),
+    })))
+}
+
+/// Returns `None` (with a warning) if there is no usable cache directory.
+fn open_vad_cache(no_cache: bool) -> Option<VadCache> {
+    if no_cache {
+        return None;
+    }
+
+    match VadCache::open_default() {
+        Ok(vad_cache) => Some(vad_cache),
+        Err(e) => {
+            println!("warn: voice activity cache disabled: {}", e);
+            None
+        }
+    }
 }
 
 fn prepare_reference_file(args: &Arguments) -> Result<InputFileHandler, failure::Error> {
-    let mut ref_file = InputFileHandler::open(
+    let vad_cache = open_vad_cache(args.no_cache);
+
+    let ref_file = InputFileHandler::open(
         &args.reference_file_path,
         args.audio_index,
+        args.ref_subtitle_stream,
+        &args.vad_config,
+        vad_cache.as_ref(),
         args.encoding_ref,
         args.sub_fps_ref,
         ProgressInfo::new(
@@ -268,15 +783,110 @@ fn prepare_reference_file(args: &Arguments) -> Result<InputFileHandler, failure:
         ),
     )?;
 
-    ref_file.filter_video_with_min_span_length_ms(500);
-
     Ok(ref_file)
 }
 
+/// Opens the video which belongs to the incorrect subtitle (see `--incorrect-source`) with the
+/// same voice activity settings as the reference video.
+fn prepare_incorrect_source(args: &Arguments, source_path: &Path) -> Result<InputFileHandler, failure::Error> {
+    let vad_cache = open_vad_cache(args.no_cache);
+
+    let source_file = InputFileHandler::open(
+        source_path,
+        None,
+        None,
+        &args.vad_config,
+        vad_cache.as_ref(),
+        args.encoding_inc,
+        args.sub_fps_inc,
+        ProgressInfo::new(
+            500,
+            Some(format!(
+                "extracting audio from incorrect source '{}'...",
+                source_path.display()
+            )),
+        ),
+    )?;
+
+    Ok(source_file)
+}
+
+/// Finds the offset between the audio of the reference video and the incorrect source with their
+/// fingerprints (see `--prealign`). Returns `None` if the fingerprints do not match clearly.
+///
+/// Both audio streams are decoded again for this (also when their voice activity came from the
+/// VAD cache), so `--prealign` roughly doubles the decoding time.
+fn prealign_offset(args: &Arguments, source_path: &Path) -> Result<Option<TimeDelta>, failure::Error> {
+    let extract = |file_path: &Path, audio_index: Option<usize>| {
+        Fingerprint::extract(
+            file_path,
+            audio_index,
+            ProgressInfo::new(
+                500,
+                Some(format!("computing audio fingerprint of '{}'...", file_path.display())),
+            ),
+        )
+    };
+    let ref_fingerprint = extract(args.reference_file_path.as_path(), args.audio_index)?;
+    let source_fingerprint = extract(source_path, None)?;
+
+    let max_offset_ms = args.max_offset.map(|max_offset| max_offset.msecs());
+    let offset_ms = fingerprint::coarse_offset(&ref_fingerprint, &source_fingerprint, max_offset_ms);
+
+    Ok(offset_ms.map(TimeDelta::from_msecs))
+}
+
 // //////////////////////////////////////////////////////////////////////////////////////////////////
 
+fn run_extract_reference(args: ExtractReferenceArguments) -> Result<(), failure::Error> {
+    if is_subtitle_file_path(&args.video_file_path) || is_reference_file_path(&args.video_file_path) {
+        return Err(ReferenceFileErrorKind::SourceNotAVideo(args.video_file_path)
+            .into_error()
+            .into());
+    }
+
+    let vad_cache = open_vad_cache(args.no_cache);
+
+    let reference_file = ReferenceFile::extract(
+        &args.video_file_path,
+        args.audio_index,
+        &args.vad_config,
+        vad_cache.as_ref(),
+        ProgressInfo::new(
+            500,
+            Some(format!(
+                "extracting audio from video file '{}'...",
+                args.video_file_path.display()
+            )),
+        ),
+    )?;
+
+    reference_file.write(&args.output_file_path)?;
+
+    println!(
+        "info: wrote voice activity of '{}' to reference file '{}'",
+        args.video_file_path.display(),
+        args.output_file_path.display()
+    );
+
+    Ok(())
+}
+
 fn run() -> Result<(), failure::Error> {
-    let args = parse_args()?;
+    let args = match parse_args()? {
+        CliCommand::Align(args) => *args,
+        CliCommand::ExtractReference(args) => return run_extract_reference(args),
+    };
+
+    if args.clear_cache {
+        let vad_cache = VadCache::open_default()?;
+        let removed_entries = vad_cache.clear()?;
+        println!(
+            "info: removed {} entries from the voice activity cache '{}'",
+            removed_entries,
+            vad_cache.dir().display()
+        );
+    }
 
     if args.incorrect_file_path.eq(OsStr::new("_")) {
         // DEBUG MODE FOR REFERENCE FILE WAS ACTIVATED
@@ -307,100 +917,253 @@ fn run() -> Result<(), failure::Error> {
     }
 
     // open incorrect file before reference file before so that incorrect-file-not-found-errors are not displayed after the long audio extraction
-    let inc_file =
-        SubtitleFileHandler::open_sub_file(args.incorrect_file_path.as_path(), args.encoding_inc, args.sub_fps_inc)?;
+    let inc_file = match args.incorrect_subtitle_stream {
+        None => {
+            SubtitleFileHandler::open_sub_file(args.incorrect_file_path.as_path(), args.encoding_inc, args.sub_fps_inc)?
+        }
+        Some(subtitle_index) => {
+            SubtitleFileHandler::open_embedded_stream(&args.incorrect_file_path, subtitle_index, args.sub_fps_inc)?
+                .ok_or_else(|| InputSubtitleErrorKind::NoTextSubtitleStream(args.incorrect_file_path.clone()))?
+        }
+    };
+    let inc_file_format = inc_file.file_format();
 
-    let ref_file = prepare_reference_file(&args)?;
+    // the file is only converted if the extension of the output file does not fit the
+    // incorrect file (otherwise all formatting is preserved)
+    let output_conversion = convert::conversion_target(inc_file_format, &args.output_file_path)?;
 
-    // Print speech timeline analysis to verify audio structure assumptions
-    print_speech_timeline_analysis(ref_file.timespans(), inc_file.timespans());
+    // fail before the (long) alignment
+    if let (Some(_), Some(source_path)) = (args.prealign_window, &args.incorrect_source_path) {
+        let is_video = |path: &Path| !is_subtitle_file_path(path) && !is_reference_file_path(path);
+        if let Some(path) = [&args.reference_file_path, source_path]
+            .into_iter()
+            .find(|path| !is_video(path))
+        {
+            return Err(TopLevelErrorKind::PrealignRequiresVideo { path: path.clone() }
+                .into_error()
+                .into());
+        }
+    }
 
-    let output_file_format = inc_file.file_format();
+    // a video is only a subtitle if it has a text-based subtitle stream (checked below)
+    if args.use_text && !is_subtitle_file_path(&args.reference_file_path) && args.ref_subtitle_stream.is_none() {
+        return Err(TopLevelErrorKind::UseTextRequiresSubtitle {
+            path: args.reference_file_path,
+        }
+        .into_error()
+        .into());
+    }
 
-    // this program internally stores the files in a non-destructable way (so
-    // formatting is preserved) but has no abilty to convert between formats
-    if !subparse::is_valid_extension_for_subtitle_format(args.output_file_path.extension(), output_file_format) {
-        return Err(TopLevelErrorKind::FileFormatMismatch {
-            input_file_path: args.incorrect_file_path,
-            output_file_path: args.output_file_path,
-            input_file_format: inc_file.file_format(),
+    if args.mux_into.is_some()
+        && (is_subtitle_file_path(&args.reference_file_path) || is_reference_file_path(&args.reference_file_path))
+    {
+        return Err(TopLevelErrorKind::MuxRequiresVideo {
+            path: args.reference_file_path,
         }
         .into_error()
         .into());
     }
 
-    let mut inc_aligner_timespans: Vec<ilass::TimeSpan> = timings_to_alg_timespans(inc_file.timespans(), args.interval);
+    let ref_file = prepare_reference_file(&args)?;
+
+    let ref_texts: Option<&[String]> = match (args.use_text, ref_file.texts()) {
+        (false, _) => None,
+        (true, Some(texts)) => Some(texts),
+        (true, None) => {
+            return Err(TopLevelErrorKind::UseTextRequiresSubtitle {
+                path: args.reference_file_path,
+            }
+            .into_error()
+            .into());
+        }
+    };
+
+    // with an incorrect source, its voice activity is aligned instead of the lines (the lines
+    // follow the spans of their video afterwards)
+    let inc_source = match &args.incorrect_source_path {
+        Some(source_path) => Some(prepare_incorrect_source(&args, source_path)?),
+        None => None,
+    };
+    // the coarse offset of the audio fingerprints narrows the offset window
+    let prealigned_offset = match (args.prealign_window, &args.incorrect_source_path) {
+        (Some(_), Some(source_path)) => {
+            let offset = prealign_offset(&args, source_path)?;
+            match offset {
+                Some(offset) => println!("info: the audio fingerprints match best at an offset of {}", offset),
+                None => println!("warn: the audio fingerprints do not match clearly, searching all offsets"),
+            }
+            offset
+        }
+        _ => None,
+    };
+
+    let (aligned_timespans, aligned_unit) = match &inc_source {
+        Some(source_file) => (source_file.timespans(), "voice spans"),
+        None => (inc_file.timespans(), "subtitles"),
+    };
+
+    // Print speech timeline analysis to verify audio structure assumptions
+    print_speech_timeline_analysis(ref_file.timespans(), aligned_timespans);
+
+    let inc_aligner_timespans: Vec<ilass::TimeSpan> = timings_to_alg_timespans(aligned_timespans, args.interval);
     let ref_aligner_timespans: Vec<ilass::TimeSpan> = timings_to_alg_timespans(ref_file.timespans(), args.interval);
 
-    let mut fps_scaling_factor = 1.;
-    if args.guess_fps_ratio {
-        let a = 25.;
-        let b = 24.;
-        let c = 23.976;
-        let ratios = [a / b, a / c, b / a, b / c, c / a, c / b];
-        let desc = ["25/24", "25/23.976", "24/25", "24/23.976", "23.976/25", "23.976/24"];
+    let a = 25.;
+    let b = 24.;
+    let c = 23.976;
+    let ratios = [a / b, a / c, b / a, b / c, c / a, c / b];
+    let desc = ["25/24", "25/23.976", "24/25", "24/23.976", "23.976/25", "23.976/24"];
+
+    let mut config = AlignmentConfig::new()
+        .mode(if args.no_split_mode {
+            AlignMode::NoSplit
+        } else if args.drift_mode {
+            AlignMode::PiecewiseLinear
+        } else {
+            AlignMode::Split
+        })
+        .split_penalty(args.split_penalty.unwrap_or(7.0))
+        // the penalty is given per second, the algorithm counts in intervals
+        .split_jump_penalty(args.split_jump_penalty * args.interval as f64 / 1000.0)
+        .min_split_jump(delta_to_alg_delta(args.min_split_jump, args.interval))
+        .speed_optimization(args.speed_optimization)
+        .score_fn(ilass::standard_scoring)
+        .framerate_candidates(if args.guess_fps_ratio {
+            ratios.to_vec()
+        } else {
+            Vec::new()
+        })
+        .reference_weights(ref_file.weights().to_vec());
 
-        let (opt_ratio_idx, _) = guess_fps_ratio(
-            &ref_aligner_timespans,
-            &inc_aligner_timespans,
-            &ratios,
-            ProgressInfo::new(1, Some("Guessing framerate ratio...".to_string())),
+    if !args.split_gap_curve.is_empty() {
+        let mut points: Vec<(AlgTimeDelta, f64)> = args
+            .split_gap_curve
+            .iter()
+            .map(|&(gap, factor)| (delta_to_alg_delta(gap, args.interval), factor))
+            .collect();
+        // gaps shorter than the interval may fall together
+        points.dedup_by_key(|&mut (gap, _)| gap);
+        config = config.split_penalty_curve(SplitPenaltyCurve::new(points));
+    }
+
+    if let Some(ref_texts) = ref_texts {
+        config = config.line_texts(ref_texts.to_vec(), inc_file.texts().to_vec());
+    }
+
+    if args.guess_fps_ratio && args.continuous_fps_search {
+        config = config.continuous_scale_search(MIN_FPS_RATIO, MAX_FPS_RATIO);
+    }
+
+    if let Some(max_offset) = args.max_offset {
+        config = config.max_offset(delta_to_alg_delta(max_offset, args.interval));
+    }
+
+    if let Some((offset, width, weight)) = args.expected_offset {
+        // the width has to cover at least one interval (a width of zero only rewards the expected offset)
+        let width = delta_to_alg_delta(width, args.interval).max(AlgTimeDelta::one());
+        config = config.expected_offset(delta_to_alg_delta(offset, args.interval), width, weight);
+    }
+
+    if let (Some(offset), Some(window)) = (prealigned_offset, args.prealign_window) {
+        // the offset is within the maximum offset, so the bounds are never empty
+        let max_offset_ms = args.max_offset.map_or(i64::MAX, |max_offset| max_offset.msecs());
+        let min_ms = (offset.msecs() - window.msecs()).max(-max_offset_ms);
+        let max_ms = (offset.msecs() + window.msecs()).min(max_offset_ms);
+        config = config.offset_bounds(
+            delta_to_alg_delta(TimeDelta::from_msecs(min_ms), args.interval),
+            delta_to_alg_delta(TimeDelta::from_msecs(max_ms), args.interval),
         );
+    }
+
+    for &(line, time_point) in &args.anchors {
+        if line > inc_aligner_timespans.len() {
+            return Err(TopLevelErrorKind::AnchorLineOutOfRange {
+                line,
+                line_count: inc_aligner_timespans.len(),
+            }
+            .into_error()
+            .into());
+        }
 
-        fps_scaling_factor = if let Some(idx) = opt_ratio_idx { ratios[idx] } else { 1. };
+        let anchor = Anchor::Time(timing_to_alg_timepoint(time_point, args.interval));
+        config = config.anchor(line - 1, anchor);
+    }
 
-        println!(
-            "info: 'reference file FPS/input file FPS' ratio is {}",
-            if let Some(idx) = opt_ratio_idx { desc[idx] } else { "1" }
+    if args.split_penalty.is_none() && !args.no_split_mode {
+        println!("choosing the split penalty...");
+        let (split_penalty, curve) = ilass::choose_split_penalty(
+            &ref_aligner_timespans,
+            &inc_aligner_timespans,
+            &config,
+            &AUTO_SPLIT_PENALTIES,
         );
+        for candidate in &curve {
+            println!(
+                "info: split penalty {:>5}: {:>3} splits, score {:.3}",
+                candidate.split_penalty, candidate.split_count, candidate.score
+            );
+        }
+        println!("info: chose split penalty {}", split_penalty);
         println!();
-
-        inc_aligner_timespans = inc_aligner_timespans
-            .into_iter()
-            .map(|x| x.scaled(fps_scaling_factor))
-            .collect();
+        config = config.split_penalty(split_penalty);
     }
 
     let align_start_msg = format!(
         "synchronizing '{}' to reference file '{}'...",
-        args.incorrect_file_path.display(),
+        args.incorrect_source_path
+            .as_ref()
+            .unwrap_or(&args.incorrect_file_path)
+            .display(),
         args.reference_file_path.display()
     );
-    let alg_deltas = if args.no_split_mode {
-        let num_inc_timespans = inc_aligner_timespans.len();
+    let alignment = align_with_config(
+        &ref_aligner_timespans,
+        &inc_aligner_timespans,
+        &config,
+        ProgressInfo::new(1, Some(align_start_msg)),
+    );
 
-        let alg_delta = ilass::align_nosplit(
-            &ref_aligner_timespans,
-            &inc_aligner_timespans,
-            ilass::standard_scoring,
-            ProgressInfo::new(1, Some(align_start_msg)),
-        )
-        .0;
+    let fps_scaling_factor = alignment.scale_factor;
+    if args.guess_fps_ratio && args.continuous_fps_search {
+        println!(
+            "info: 'reference file FPS/input file FPS' ratio is {:.6}",
+            fps_scaling_factor
+        );
+        println!();
+    } else if args.guess_fps_ratio {
+        println!(
+            "info: 'reference file FPS/input file FPS' ratio is {}",
+            ratios
+                .iter()
+                .position(|&ratio| ratio == fps_scaling_factor)
+                .map(|idx| desc[idx])
+                .unwrap_or("1")
+        );
+        println!();
+    }
 
-        std::vec::from_elem(alg_delta, num_inc_timespans)
-    } else {
-        align(
-            &ref_aligner_timespans,
-            &inc_aligner_timespans,
-            args.split_penalty,
-            args.speed_optimization,
-            ilass::standard_scoring,
-            ProgressInfo::new(1, Some(align_start_msg)),
-        )
-        .0
+    let deltas = match &inc_source {
+        None => alg_deltas_to_timing_deltas(&alignment.deltas, args.interval),
+        Some(_) => {
+            let lines = timings_to_alg_timespans(inc_file.timespans(), args.interval);
+            let line_deltas = transfer_alignment_to_lines(&inc_aligner_timespans, &alignment, &lines);
+            alg_deltas_to_timing_deltas(&line_deltas, args.interval)
+        }
     };
-    let deltas = alg_deltas_to_timing_deltas(&alg_deltas, args.interval);
 
-    // group subtitles lines which have the same offset
-    let shift_groups: Vec<(AlgTimeDelta, Vec<TimeSpan>)> = get_subtitle_delta_groups(
-        alg_deltas
-            .iter()
-            .cloned()
-            .zip(inc_file.timespans().iter().cloned())
-            .collect(),
-    );
+    // group subtitles lines (or voice spans) which have the same offset
+    let shift_groups: Vec<(AlgTimeDelta, Vec<TimeSpan>)> = alignment
+        .segments
+        .iter()
+        .map(|segment| {
+            (
+                segment.delta,
+                aligned_timespans[segment.start_index..segment.end_index].to_vec(),
+            )
+        })
+        .collect();
 
-    for (shift_group_delta, shift_group_lines) in &shift_groups {
+    for (segment, (_, shift_group_lines)) in alignment.segments.iter().zip(shift_groups.iter()) {
         // computes the first and last timestamp for all lines with that delta
         // -> that way we can provide the user with an information like
         //     "100 subtitles with 10min length"
@@ -415,46 +1178,37 @@ fn run() -> Result<(), failure::Error> {
             .max()
             .expect("a subtitle group should have at least one subtitle line");
 
-        // Calculate alignment score for this specific block
-        let block_alg_timespans: Vec<ilass::TimeSpan> = timings_to_alg_timespans(&shift_group_lines, args.interval);
-        let shifted_block_spans: Vec<ilass::TimeSpan> = block_alg_timespans
-            .iter()
-            .map(|ts| *ts + *shift_group_delta)
-            .collect();
-        
-        let block_score = ilass::get_nosplit_score(
-            ref_aligner_timespans.iter().cloned(),
-            shifted_block_spans.iter().cloned(),
-            ilass::standard_scoring,
-        );
-
-        // Calculate score per subtitle for comparison
-        let score_per_subtitle = if shift_group_lines.len() > 0 {
-            block_score / shift_group_lines.len() as f64
+        // in drift mode the lines of a block have different deltas
+        let scale_info = if segment.scale_factor == 1. {
+            String::new()
         } else {
-            0.0
+            format!(" and scaled by {:.4}", segment.scale_factor)
         };
 
         println!(
-            "shifted block of {} subtitles from {} to {} with length {} by {} (score: {:.3}, per subtitle: {:.3})",
+            "shifted block of {} {} from {} to {} with length {} by {}{} (score: {:.3}, per span: {:.3})",
             shift_group_lines.len(),
+            aligned_unit,
             min,
             max,
             max - min,
-            alg_delta_to_delta(*shift_group_delta, args.interval),
-            block_score,
-            score_per_subtitle
+            alg_delta_to_delta(alignment.deltas[segment.start_index], args.interval),
+            scale_info,
+            segment.score,
+            segment.score / shift_group_lines.len() as f64
+        );
+    }
+
+    if alignment.split_penalty > 0. {
+        println!(
+            "total score: {:.3} (raw score {:.3} minus split penalty {:.3})",
+            alignment.score, alignment.raw_score, alignment.split_penalty
         );
     }
 
     // Validate framerate detection using split groups if we used framerate correction
     if args.guess_fps_ratio {
-        validate_fps_ratio_on_split_groups(
-            &ref_aligner_timespans,
-            &shift_groups,
-            args.interval,
-            fps_scaling_factor,
-        );
+        validate_fps_ratio_on_split_groups(&ref_aligner_timespans, &shift_groups, args.interval, fps_scaling_factor);
     }
 
     println!();
@@ -506,7 +1260,8 @@ fn run() -> Result<(), failure::Error> {
 
     // .idx only has start timepoints (the subtitle is shown until the next subtitle starts) - so retiming with gaps might
     // produce errors
-    if output_file_format == SubtitleFormat::VobSubIdx {
+    let is_idx_file = inc_file_format == SubtitleFileFormat::Subparse(SubtitleFormat::VobSubIdx);
+    if output_conversion.is_none() && is_idx_file {
         println!("warn: writing to an '.idx' file can lead to unexpected results due to restrictions of this format");
     }
 
@@ -519,12 +1274,52 @@ fn run() -> Result<(), failure::Error> {
         .update_subtitle_entries(&shifted_timespans)
         .with_context(|_| TopLevelErrorKind::FailedToUpdateSubtitle)?;
 
-    write_data_to_file(
-        &args.output_file_path,
-        correct_file
+    let output_data = match output_conversion {
+        None => correct_file
             .to_data()
             .with_context(|_| TopLevelErrorKind::FailedToGenerateSubtitleData)?,
-    )?;
+        Some(target_format) => {
+            let converted =
+                convert::convert_subtitle_file(&correct_file, inc_file_format, target_format, args.sub_fps_inc)?;
+
+            println!(
+                "info: converting subtitle from '{}' to '{}'",
+                inc_file_format.get_name(),
+                target_format.get_name()
+            );
+            if !converted.losses.is_empty() {
+                println!("warn: the following could not be carried over to the output file:");
+                for loss in &converted.losses {
+                    println!("warn:   - {}", loss);
+                }
+            }
+            println!();
+
+            converted.data
+        }
+    };
+
+    write_data_to_file(&args.output_file_path, output_data)?;
+
+    if let Some(mux_path) = &args.mux_into {
+        println!(
+            "info: writing '{}' with the corrected subtitle as new stream...",
+            mux_path.display()
+        );
+        // an embedded stream of the reference video gets replaced by its corrected version
+        let replaced_stream = args
+            .incorrect_subtitle_stream
+            .filter(|_| is_same_file(&args.incorrect_file_path, &args.reference_file_path));
+
+        mux_subtitle_into_video(
+            &args.reference_file_path,
+            &args.output_file_path,
+            mux_path,
+            replaced_stream,
+            args.mux_language.as_deref(),
+            args.mux_title.as_deref(),
+        )?;
+    }
 
     Ok(())
 }
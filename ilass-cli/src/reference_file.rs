@@ -0,0 +1,302 @@
+//! Stores the voice activity of a video in a standalone (versioned JSON) file, which can be used
+//! as reference instead of the video.
+//!
+//! ```json
+//! {
+//!   "format": "ilass-reference",
+//!   "version": 1,
+//!   "ilass_version": "...",
+//!   "source": { "file_name": "movie.mkv", "size": 123456789, "audio_index": null },
+//!   "vad": "silero threshold=0.5 chunk=512",
+//!   "timespans": [[1200, 3400], [5000, 6100]],
+//!   "weights": [0.93, 0.71]
+//! }
+//! ```
+//!
+//! `weights` (the mean speech probability of every span) is optional; without it all spans are
+//! equally weighted.
+
+use crate::errors::*;
+use crate::vad::VadConfig;
+use crate::vad_cache::VadCache;
+use crate::video_decoder::ProgressHandler;
+use crate::{VideoFileHandler, read_file_to_bytes, write_data_to_file};
+use failure::ResultExt;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use subparse::timetypes::{TimePoint, TimeSpan};
+
+const FORMAT_NAME: &str = "ilass-reference";
+
+/// Files with a higher version can not be read.
+const FORMAT_VERSION: u32 = 1;
+
+/// Where the reference spans come from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReferenceSource {
+    pub file_name: String,
+    pub size: u64,
+    pub audio_index: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReferenceFile {
+    format: String,
+    version: u32,
+    ilass_version: String,
+    pub source: ReferenceSource,
+
+    /// The voice activity detection settings.
+    pub vad: String,
+
+    /// start and end of every voice span in milliseconds
+    timespans: Vec<(i64, i64)>,
+
+    /// the weight (mean speech probability) of every voice span (empty in older files)
+    #[serde(default)]
+    weights: Vec<f64>,
+}
+
+/// Returns `true` for paths which are treated as reference files (`.json`).
+pub fn is_reference_file_path(file_path: &Path) -> bool {
+    file_path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("json"))
+}
+
+impl ReferenceFile {
+    pub fn new(source: ReferenceSource, vad: String, timespans: &[TimeSpan], weights: &[f64]) -> ReferenceFile {
+        ReferenceFile {
+            format: FORMAT_NAME.to_string(),
+            version: FORMAT_VERSION,
+            ilass_version: env!("CARGO_PKG_VERSION").to_string(),
+            source,
+            vad,
+            timespans: timespans.iter().map(|ts| (ts.start.msecs(), ts.end.msecs())).collect(),
+            weights: weights.to_vec(),
+        }
+    }
+
+    /// Runs the voice activity detection on an audio stream of a video (reusing the spans in
+    /// `vad_cache` if given).
+    pub fn extract(
+        video_file_path: &Path,
+        audio_index: Option<usize>,
+        vad_config: &VadConfig,
+        vad_cache: Option<&VadCache>,
+        video_decode_progress: impl ProgressHandler,
+    ) -> Result<ReferenceFile, ReferenceFileError> {
+        let video_file_handler = match vad_cache {
+            Some(vad_cache) => VideoFileHandler::open_video_file_cached(
+                video_file_path,
+                audio_index,
+                vad_config,
+                vad_cache,
+                video_decode_progress,
+            ),
+            None => VideoFileHandler::open_video_file(video_file_path, audio_index, vad_config, video_decode_progress),
+        }
+        .with_context(|_| ReferenceFileErrorKind::AnalysingVideoFailed(video_file_path.to_path_buf()))?;
+
+        let size = std::fs::metadata(video_file_path)
+            .with_context(|_| ReferenceFileErrorKind::ReadingFileMetadataFailed(video_file_path.to_path_buf()))?
+            .len();
+        let source = ReferenceSource {
+            file_name: video_file_path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            size,
+            audio_index,
+        };
+
+        Ok(ReferenceFile::new(
+            source,
+            vad_config.description(),
+            video_file_handler.timespans(),
+            video_file_handler.weights(),
+        ))
+    }
+
+    pub fn timespans(&self) -> Vec<TimeSpan> {
+        self.timespans
+            .iter()
+            .map(|&(start, end)| TimeSpan::new(TimePoint::from_msecs(start), TimePoint::from_msecs(end)))
+            .collect()
+    }
+
+    /// Either empty or the weight of every span (see `timespans()`).
+    pub fn weights(&self) -> &[f64] {
+        &self.weights
+    }
+
+    pub fn read(file_path: &Path) -> Result<ReferenceFile, ReferenceFileError> {
+        let data = read_file_to_bytes(file_path)
+            .with_context(|_| ReferenceFileErrorKind::ReadingFailed(file_path.to_path_buf()))?;
+
+        let reference_file: ReferenceFile = serde_json::from_slice(&data)
+            .with_context(|_| ReferenceFileErrorKind::ParsingFailed(file_path.to_path_buf()))?;
+
+        if reference_file.format != FORMAT_NAME {
+            return Err(ReferenceFileErrorKind::NotAReferenceFile(file_path.to_path_buf()).into());
+        }
+        if reference_file.version > FORMAT_VERSION {
+            return Err(ReferenceFileErrorKind::UnsupportedVersion {
+                path: file_path.to_path_buf(),
+                version: reference_file.version,
+                supported_version: FORMAT_VERSION,
+            }
+            .into());
+        }
+
+        let has_valid_weights = reference_file.weights.is_empty()
+            || (reference_file.weights.len() == reference_file.timespans.len()
+                && reference_file.weights.iter().all(|weight| (0.0..=1.0).contains(weight)));
+        if !has_valid_weights {
+            return Err(ReferenceFileErrorKind::InvalidWeights(file_path.to_path_buf()).into());
+        }
+
+        let has_valid_timespans = reference_file
+            .timespans
+            .iter()
+            .all(|&(start, end)| 0 <= start && start <= end);
+        if !has_valid_timespans {
+            return Err(ReferenceFileErrorKind::InvalidTimespans(file_path.to_path_buf()).into());
+        }
+
+        Ok(reference_file)
+    }
+
+    pub fn write(&self, file_path: &Path) -> Result<(), ReferenceFileError> {
+        let data = serde_json::to_vec_pretty(self).with_context(|_| ReferenceFileErrorKind::SerializingFailed)?;
+
+        write_data_to_file(file_path, data)
+            .with_context(|_| ReferenceFileErrorKind::WritingFailed(file_path.to_path_buf()))?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NoProgressInfo;
+    use crate::tests::TempDir;
+    use crate::vad_cache::VadCacheKey;
+
+    fn span(start: i64, end: i64) -> TimeSpan {
+        TimeSpan::new(TimePoint::from_msecs(start), TimePoint::from_msecs(end))
+    }
+
+    fn reference_json(version: u32, timespans: &str, weights: &str) -> String {
+        format!(
+            r#"{{
+                "format": "ilass-reference",
+                "version": {},
+                "ilass_version": "2.1.0",
+                "source": {{ "file_name": "movie.mkv", "size": 5, "audio_index": null }},
+                "vad": "silero",
+                "timespans": {},
+                "weights": {}
+            }}"#,
+            version, timespans, weights
+        )
+    }
+
+    fn read_json(dir: &TempDir, data: &str) -> Result<ReferenceFile, ReferenceFileError> {
+        let path = dir.0.join("reference.json");
+        std::fs::write(&path, data).unwrap();
+        ReferenceFile::read(&path)
+    }
+
+    #[test]
+    fn write_and_read() {
+        let dir = TempDir::new("reference-file-write");
+        let source = ReferenceSource {
+            file_name: "movie.mkv".to_string(),
+            size: 5,
+            audio_index: Some(1),
+        };
+        let timespans = vec![span(1200, 3400), span(5000, 6100)];
+        let reference_file = ReferenceFile::new(source, "silero".to_string(), &timespans, &[0.93, 0.71]);
+
+        let path = dir.0.join("reference.json");
+        reference_file.write(&path).unwrap();
+        let read_file = ReferenceFile::read(&path).unwrap();
+        assert_eq!(read_file.timespans(), timespans);
+        assert_eq!(read_file.weights(), &[0.93, 0.71]);
+        assert_eq!(read_file.source.file_name, "movie.mkv");
+        assert_eq!(read_file.source.audio_index, Some(1));
+        assert_eq!(read_file.vad, "silero");
+
+        // the weights are optional
+        let data = reference_json(1, "[[1200, 3400]]", "[]").replace(r#""weights": []"#, r#""unknown": 1"#);
+        let read_file = read_json(&dir, &data).unwrap();
+        assert_eq!(read_file.timespans(), vec![span(1200, 3400)]);
+        assert!(read_file.weights().is_empty());
+    }
+
+    #[test]
+    fn reject_invalid_files() {
+        let dir = TempDir::new("reference-file-invalid");
+        let kind = |data: &str| read_json(&dir, data).err().map(|e| e.kind().clone());
+        let path = dir.0.join("reference.json");
+
+        assert!(kind(&reference_json(1, "[[0, 10]]", "[1.0]")).is_none());
+        assert_eq!(
+            kind(&reference_json(2, "[[0, 10]]", "[1.0]")),
+            Some(ReferenceFileErrorKind::UnsupportedVersion {
+                path: path.clone(),
+                version: 2,
+                supported_version: FORMAT_VERSION,
+            })
+        );
+        assert_eq!(
+            kind(&reference_json(1, "[[0, 10]]", "[1.0]").replace("ilass-reference", "other")),
+            Some(ReferenceFileErrorKind::NotAReferenceFile(path.clone()))
+        );
+        assert_eq!(
+            kind("{ \"format\": "),
+            Some(ReferenceFileErrorKind::ParsingFailed(path.clone()))
+        );
+
+        for weights in ["[1.0, 1.0]", "[1.5]", "[-0.1]"] {
+            assert_eq!(
+                kind(&reference_json(1, "[[0, 10]]", weights)),
+                Some(ReferenceFileErrorKind::InvalidWeights(path.clone()))
+            );
+        }
+        for timespans in ["[[10, 0]]", "[[-10, 0]]"] {
+            assert_eq!(
+                kind(&reference_json(1, timespans, "[]")),
+                Some(ReferenceFileErrorKind::InvalidTimespans(path.clone()))
+            );
+        }
+    }
+
+    #[test]
+    fn extract_write_and_read() {
+        let dir = TempDir::new("reference-file-extract");
+        let video_path = dir.0.join("movie.mkv");
+        std::fs::write(&video_path, b"video").unwrap();
+
+        // the cached voice activity is used instead of decoding the (fake) video
+        let vad_config = VadConfig::default();
+        let vad_cache = VadCache::new(dir.0.join("cache"));
+        let key = VadCacheKey::for_file(&video_path, None, vad_config.description()).unwrap();
+        let timespans = vec![span(1200, 3400), span(5000, 6100)];
+        vad_cache.store(&key, &timespans, &[0.93, 0.71]).unwrap();
+
+        let reference_file =
+            ReferenceFile::extract(&video_path, None, &vad_config, Some(&vad_cache), NoProgressInfo {}).unwrap();
+        assert_eq!(reference_file.source.file_name, "movie.mkv");
+        assert_eq!(reference_file.source.size, 5);
+        assert_eq!(reference_file.vad, vad_config.description());
+
+        let path = dir.0.join("movie.json");
+        reference_file.write(&path).unwrap();
+        let read_file = ReferenceFile::read(&path).unwrap();
+        assert_eq!(read_file.timespans(), timespans);
+        assert_eq!(read_file.weights(), &[0.93, 0.71]);
+    }
+}
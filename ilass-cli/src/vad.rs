@@ -0,0 +1,455 @@
+//! Voice activity detectors, their settings and the conversion of their per-chunk speech
+//! probabilities into (weighted) voice spans.
+
+use crate::errors::*;
+use crate::video_decoder::AudioReceiver;
+use subparse::timetypes::{TimePoint, TimeSpan};
+
+/// Number of samples (at 16kHz) which are classified at once.
+pub const VAD_CHUNK_SIZE: usize = 512;
+
+/// Length of one chunk in milliseconds.
+pub const VAD_CHUNK_MS: i64 = 32;
+
+/// Classifies chunks of `VAD_CHUNK_SIZE` samples (mono, 16kHz).
+pub trait VoiceDetector {
+    /// Returns the probability (from 0 to 1) that the chunk contains speech.
+    fn speech_probability(&mut self, chunk: &[i16]) -> f32;
+}
+
+/// The neural network of the Silero project (needs the ONNX runtime).
+#[cfg(feature = "silero")]
+pub struct SileroDetector {
+    detector: voice_activity_detector::VoiceActivityDetector,
+}
+
+#[cfg(feature = "silero")]
+impl SileroDetector {
+    pub fn new() -> Result<SileroDetector, InputVideoError> {
+        let detector = voice_activity_detector::VoiceActivityDetector::builder()
+            .sample_rate(16000)
+            .chunk_size(VAD_CHUNK_SIZE)
+            .build()
+            .map_err(|_| InputVideoErrorKind::VadAnalysisFailed)?;
+
+        Ok(SileroDetector { detector })
+    }
+}
+
+#[cfg(feature = "silero")]
+impl VoiceDetector for SileroDetector {
+    fn speech_probability(&mut self, chunk: &[i16]) -> f32 {
+        self.detector.predict(chunk.iter().copied())
+    }
+}
+
+/// Smoothing factor of a one-pole high-pass filter at 200Hz (for 16kHz).
+const HIGH_PASS_ALPHA: f32 = 0.927;
+
+/// Smoothing factor of a one-pole low-pass filter at 4kHz (for 16kHz).
+const LOW_PASS_ALPHA: f32 = 0.611;
+
+/// Chunks quieter than this are never speech.
+const SILENCE_DB: f32 = -60.0;
+
+/// How fast the noise floor follows louder audio (it follows quieter audio immediately).
+const NOISE_FLOOR_RISE: f32 = 0.005;
+
+/// A chunk with this level above the noise floor has a speech probability of 0.5.
+const SPEECH_MARGIN_DB: f32 = 10.0;
+
+/// Compares the energy in the speech band (200Hz to 4kHz) with an adaptive noise floor. Much
+/// faster than Silero, but music and noise are often mistaken for speech.
+#[derive(Debug, Clone, Default)]
+pub struct EnergyDetector {
+    high_pass_prev_input: f32,
+    high_pass_prev_output: f32,
+    low_pass_prev_output: f32,
+    noise_floor_db: Option<f32>,
+}
+
+impl EnergyDetector {
+    pub fn new() -> EnergyDetector {
+        EnergyDetector::default()
+    }
+}
+
+impl VoiceDetector for EnergyDetector {
+    fn speech_probability(&mut self, chunk: &[i16]) -> f32 {
+        if chunk.is_empty() {
+            return 0.0;
+        }
+
+        let mut energy = 0.0f64;
+        for &sample in chunk {
+            let x = f32::from(sample) / 32768.0;
+
+            let high_passed = HIGH_PASS_ALPHA * (self.high_pass_prev_output + x - self.high_pass_prev_input);
+            self.high_pass_prev_input = x;
+            self.high_pass_prev_output = high_passed;
+            self.low_pass_prev_output += LOW_PASS_ALPHA * (high_passed - self.low_pass_prev_output);
+
+            energy += f64::from(self.low_pass_prev_output).powi(2);
+        }
+        let level_db = (10.0 * (energy / chunk.len() as f64 + 1e-10).log10()) as f32;
+
+        let noise_floor_db = match self.noise_floor_db {
+            Some(noise_floor_db) if level_db > noise_floor_db => {
+                noise_floor_db + NOISE_FLOOR_RISE * (level_db - noise_floor_db)
+            }
+            _ => level_db,
+        };
+        self.noise_floor_db = Some(noise_floor_db);
+
+        if level_db < SILENCE_DB {
+            return 0.0;
+        }
+
+        let snr_db = level_db - noise_floor_db;
+        1.0 / (1.0 + (-(snr_db - SPEECH_MARGIN_DB) / 2.0).exp())
+    }
+}
+
+/// The available voice activity detectors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VadKind {
+    Silero,
+    Energy,
+}
+
+impl Default for VadKind {
+    /// Silero if it is compiled in.
+    fn default() -> VadKind {
+        if cfg!(feature = "silero") {
+            VadKind::Silero
+        } else {
+            VadKind::Energy
+        }
+    }
+}
+
+impl VadKind {
+    pub const ALL: [VadKind; 2] = [VadKind::Silero, VadKind::Energy];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            VadKind::Silero => "silero",
+            VadKind::Energy => "energy",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<VadKind> {
+        VadKind::ALL.into_iter().find(|kind| kind.name() == name)
+    }
+
+    pub fn create_detector(self) -> Result<Box<dyn VoiceDetector>, InputVideoError> {
+        match self {
+            #[cfg(feature = "silero")]
+            VadKind::Silero => Ok(Box::new(SileroDetector::new()?)),
+            #[cfg(not(feature = "silero"))]
+            VadKind::Silero => Err(InputVideoErrorKind::VadNotAvailable {
+                name: self.name().to_string(),
+            }
+            .into()),
+            VadKind::Energy => Ok(Box::new(EnergyDetector::new())),
+        }
+    }
+}
+
+/// Runs a detector on every chunk and returns the speech probability of every chunk.
+pub struct VadReceiver {
+    detector: Box<dyn VoiceDetector>,
+    vad_buffer: Vec<f32>,
+    sample_buffer: Vec<i16>,
+}
+
+impl VadReceiver {
+    pub fn new(detector: Box<dyn VoiceDetector>) -> VadReceiver {
+        VadReceiver {
+            detector,
+            vad_buffer: Vec::new(),
+            sample_buffer: Vec::new(),
+        }
+    }
+}
+
+impl AudioReceiver for VadReceiver {
+    type Output = Vec<f32>;
+    type Error = InputVideoError;
+
+    fn push_samples(&mut self, samples: &[i16]) -> Result<(), InputVideoError> {
+        self.sample_buffer.extend_from_slice(samples);
+
+        while self.sample_buffer.len() >= VAD_CHUNK_SIZE {
+            let speech_prob = self.detector.speech_probability(&self.sample_buffer[..VAD_CHUNK_SIZE]);
+            self.vad_buffer.push(speech_prob);
+            self.sample_buffer.drain(..VAD_CHUNK_SIZE);
+        }
+
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<Vec<f32>, InputVideoError> {
+        // pad the last incomplete chunk with silence
+        if !self.sample_buffer.is_empty() {
+            self.sample_buffer.resize(VAD_CHUNK_SIZE, 0);
+            let speech_prob = self.detector.speech_probability(&self.sample_buffer);
+            self.vad_buffer.push(speech_prob);
+        }
+        Ok(self.vad_buffer)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VadConfig {
+    pub detector: VadKind,
+
+    /// Chunks with a speech probability above this value (from 0 to 1) are voice.
+    pub threshold: f32,
+
+    /// Shorter voice spans are dropped (after merging).
+    pub min_speech_ms: i64,
+
+    /// Voice spans separated by a shorter silence are merged.
+    pub min_silence_ms: i64,
+
+    /// Added before the start of every voice span.
+    pub pre_padding_ms: i64,
+
+    /// Added after the end of every voice span.
+    pub post_padding_ms: i64,
+}
+
+impl Default for VadConfig {
+    /// The unfiltered spans of the detector.
+    fn default() -> VadConfig {
+        VadConfig {
+            detector: VadKind::default(),
+            threshold: 0.5,
+            min_speech_ms: 0,
+            min_silence_ms: 0,
+            pre_padding_ms: 0,
+            post_padding_ms: 0,
+        }
+    }
+}
+
+impl VadConfig {
+    /// Describes all settings which influence the detected spans (cached spans are only reused
+    /// if this matches).
+    pub fn description(&self) -> String {
+        format!(
+            "{} threshold={} chunk={} min-speech={} min-silence={} padding={}/{}",
+            self.detector.name(),
+            self.threshold,
+            VAD_CHUNK_SIZE,
+            self.min_speech_ms,
+            self.min_silence_ms,
+            self.pre_padding_ms,
+            self.post_padding_ms
+        )
+    }
+
+    /// Converts the speech probability of every chunk into voice spans (the chunks above the
+    /// threshold). Also returns the weight of every span: the mean speech probability of its
+    /// voice chunks.
+    pub fn voice_spans(&self, chunks: &[f32]) -> (Vec<TimeSpan>, Vec<f64>) {
+        // start and end in milliseconds, sum of the probabilities, number of voice chunks
+        let mut spans: Vec<(i64, i64, f64, usize)> = Vec::new();
+        let mut span_start: Option<usize> = None;
+
+        for (i, &speech_prob) in chunks.iter().chain(std::iter::once(&0.0)).enumerate() {
+            match (speech_prob > self.threshold, span_start) {
+                (true, None) => span_start = Some(i),
+                (false, Some(start)) => {
+                    // the span ends at the start of its last chunk
+                    let start_ms = start as i64 * VAD_CHUNK_MS;
+                    let end_ms = (i as i64 - 1) * VAD_CHUNK_MS;
+                    let prob_sum: f64 = chunks[start..i].iter().map(|&p| f64::from(p)).sum();
+                    let chunk_count = i - start;
+
+                    match spans.last_mut() {
+                        Some(last) if start_ms - last.1 < self.min_silence_ms => {
+                            last.1 = end_ms;
+                            last.2 += prob_sum;
+                            last.3 += chunk_count;
+                        }
+                        _ => spans.push((start_ms, end_ms, prob_sum, chunk_count)),
+                    }
+                    span_start = None;
+                }
+                _ => {}
+            }
+        }
+
+        let mut result: Vec<(i64, i64, f64, usize)> = Vec::new();
+        for (start, end, prob_sum, chunk_count) in spans {
+            if end - start < self.min_speech_ms {
+                continue;
+            }
+
+            let start = (start - self.pre_padding_ms).max(0);
+            let end = end + self.post_padding_ms;
+
+            // padded spans may overlap
+            match result.last_mut() {
+                Some(last) if start <= last.1 => {
+                    last.1 = last.1.max(end);
+                    last.2 += prob_sum;
+                    last.3 += chunk_count;
+                }
+                _ => result.push((start, end, prob_sum, chunk_count)),
+            }
+        }
+
+        result
+            .into_iter()
+            .map(|(start, end, prob_sum, chunk_count)| {
+                let span = TimeSpan::new(TimePoint::from_msecs(start), TimePoint::from_msecs(end));
+                (span, (prob_sum / chunk_count as f64).clamp(0.0, 1.0))
+            })
+            .unzip()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // voice chunks: 1 and 2 (mean probability 0.8) and 5 (0.6)
+    const CHUNKS: [f32; 7] = [0.0, 0.9, 0.7, 0.0, 0.0, 0.6, 0.5];
+
+    fn spans(config: &VadConfig, chunks: &[f32]) -> Vec<(i64, i64, f64)> {
+        let (timespans, weights) = config.voice_spans(chunks);
+        assert_eq!(timespans.len(), weights.len());
+        timespans
+            .iter()
+            .zip(weights)
+            // the probabilities are `f32`
+            .map(|(ts, weight)| (ts.start.msecs(), ts.end.msecs(), (weight * 1000.).round() / 1000.))
+            .collect()
+    }
+
+    #[test]
+    fn voice_spans_unfiltered() {
+        let config = VadConfig::default();
+        assert_eq!(spans(&config, &CHUNKS), vec![(32, 64, 0.8), (160, 160, 0.6)]);
+
+        // a span at the end of the audio is closed
+        assert_eq!(spans(&config, &[0.0, 0.9, 0.9]), vec![(32, 64, 0.9)]);
+        assert_eq!(spans(&config, &[]), vec![]);
+
+        let config = VadConfig {
+            threshold: 0.8,
+            ..VadConfig::default()
+        };
+        assert_eq!(spans(&config, &CHUNKS), vec![(32, 32, 0.9)]);
+    }
+
+    #[test]
+    fn voice_spans_merging() {
+        // the silence between the spans is 96ms
+        let config = VadConfig {
+            min_silence_ms: 97,
+            ..VadConfig::default()
+        };
+        assert_eq!(spans(&config, &CHUNKS), vec![(32, 160, 0.733)]);
+
+        let config = VadConfig {
+            min_silence_ms: 96,
+            ..VadConfig::default()
+        };
+        assert_eq!(spans(&config, &CHUNKS), vec![(32, 64, 0.8), (160, 160, 0.6)]);
+    }
+
+    #[test]
+    fn voice_spans_min_speech() {
+        let config = VadConfig {
+            min_speech_ms: 32,
+            ..VadConfig::default()
+        };
+        assert_eq!(spans(&config, &CHUNKS), vec![(32, 64, 0.8)]);
+
+        let config = VadConfig {
+            min_speech_ms: 33,
+            ..VadConfig::default()
+        };
+        assert_eq!(spans(&config, &CHUNKS), vec![]);
+
+        // short spans are dropped after merging
+        let config = VadConfig {
+            min_speech_ms: 100,
+            min_silence_ms: 100,
+            ..VadConfig::default()
+        };
+        assert_eq!(spans(&config, &CHUNKS), vec![(32, 160, 0.733)]);
+    }
+
+    #[test]
+    fn voice_spans_padding() {
+        let config = VadConfig {
+            pre_padding_ms: 50,
+            post_padding_ms: 20,
+            ..VadConfig::default()
+        };
+        assert_eq!(spans(&config, &CHUNKS), vec![(0, 84, 0.8), (110, 180, 0.6)]);
+
+        // overlapping padded spans are merged
+        let config = VadConfig {
+            pre_padding_ms: 80,
+            post_padding_ms: 20,
+            ..VadConfig::default()
+        };
+        assert_eq!(spans(&config, &CHUNKS), vec![(0, 180, 0.733)]);
+
+        // padding is added after dropping short spans
+        let config = VadConfig {
+            min_speech_ms: 1,
+            pre_padding_ms: 80,
+            post_padding_ms: 20,
+            ..VadConfig::default()
+        };
+        assert_eq!(spans(&config, &CHUNKS), vec![(0, 84, 0.8)]);
+    }
+
+    /// Runs the energy detector on the samples (fed in pieces which do not match the chunks).
+    fn energy_speech_probabilities(samples: &[i16]) -> Vec<f32> {
+        let mut receiver = VadReceiver::new(Box::new(EnergyDetector::new()));
+        for piece in samples.chunks(1000) {
+            receiver.push_samples(piece).unwrap();
+        }
+        receiver.finish().unwrap()
+    }
+
+    fn tone(frequency: f64, secs: f64) -> Vec<i16> {
+        (0..(secs * 16000.) as usize)
+            .map(|i| (0.3 * 32767. * (2. * std::f64::consts::PI * frequency * i as f64 / 16000.).sin()) as i16)
+            .collect()
+    }
+
+    #[test]
+    fn energy_detector_silence_and_tone() {
+        let silence = vec![0i16; 16000];
+        let probabilities = energy_speech_probabilities(&silence);
+        assert_eq!(probabilities.len(), 32);
+        assert!(probabilities.iter().all(|&p| p == 0.0));
+
+        // one second of silence, two seconds of a 1kHz tone, one second of silence
+        let samples: Vec<i16> = [silence.clone(), tone(1000., 2.), silence].concat();
+        let probabilities = energy_speech_probabilities(&samples);
+        assert_eq!(probabilities.len(), 125);
+
+        let (timespans, weights) = VadConfig::default().voice_spans(&probabilities);
+        assert_eq!(timespans.len(), 1);
+        assert!((960..=1024).contains(&timespans[0].start.msecs()));
+        assert!((2944..=3008).contains(&timespans[0].end.msecs()));
+        assert!(weights[0] > 0.9);
+    }
+
+    #[test]
+    fn energy_detector_constant_noise() {
+        // the noise floor starts at the level of the tone
+        let probabilities = energy_speech_probabilities(&tone(1000., 5.));
+        assert!(probabilities.iter().all(|&p| p < 0.5));
+    }
+}
@@ -0,0 +1,281 @@
+//! On-disk cache for the voice activity of video files.
+//!
+//! Every entry is a small JSON file. Its name is a hash of the key (path, size and modification
+//! time of the video, audio stream index and VAD parameters); the key itself is stored in the
+//! file too, so hash collisions and changed videos are detected on load.
+
+use crate::errors::*;
+use failure::ResultExt;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use subparse::timetypes::{TimePoint, TimeSpan};
+
+/// Increase this if the format of the cache files (or the meaning of their content) changes.
+const CACHE_FORMAT_VERSION: u32 = 2;
+
+/// Identifies the voice activity of one audio stream of one video file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VadCacheKey {
+    path: PathBuf,
+    size: u64,
+    mtime_secs: u64,
+    mtime_nanos: u32,
+    audio_index: Option<usize>,
+    vad_params: String,
+}
+
+impl VadCacheKey {
+    /// `vad_params` has to describe all settings which influence the voice activity detection.
+    pub fn for_file(
+        file_path: &Path,
+        audio_index: Option<usize>,
+        vad_params: String,
+    ) -> Result<VadCacheKey, VadCacheError> {
+        let metadata_err = |_: &std::io::Error| VadCacheErrorKind::ReadingFileMetadataFailed(file_path.to_path_buf());
+
+        let path = std::fs::canonicalize(file_path).with_context(metadata_err)?;
+        let metadata = std::fs::metadata(&path).with_context(metadata_err)?;
+        let mtime = metadata
+            .modified()
+            .with_context(metadata_err)?
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+
+        Ok(VadCacheKey {
+            path,
+            size: metadata.len(),
+            mtime_secs: mtime.as_secs(),
+            mtime_nanos: mtime.subsec_nanos(),
+            audio_index,
+            vad_params,
+        })
+    }
+
+    fn file_name(&self) -> String {
+        // FNV-1a (unlike `DefaultHasher` it is stable between Rust versions)
+        let serialized = serde_json::to_string(self).unwrap_or_default();
+        let hash = serialized.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+        });
+        format!("vad-{:016x}.json", hash)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheEntry {
+    version: u32,
+    key: VadCacheKey,
+
+    /// start and end of every span in milliseconds
+    timespans: Vec<(i64, i64)>,
+
+    /// the weight (mean speech probability) of every span
+    weights: Vec<f64>,
+}
+
+/// A directory with cached voice activity spans.
+#[derive(Debug, Clone)]
+pub struct VadCache {
+    dir: PathBuf,
+}
+
+impl VadCache {
+    pub fn new(dir: PathBuf) -> VadCache {
+        VadCache { dir }
+    }
+
+    /// Uses `ILASS_CACHE_DIR` or the user's cache directory (`$XDG_CACHE_HOME/ilass`,
+    /// `~/.cache/ilass` or `%LOCALAPPDATA%\ilass`).
+    pub fn open_default() -> Result<VadCache, VadCacheError> {
+        let env_dir = |name: &str| {
+            std::env::var_os(name)
+                .filter(|value| !value.is_empty())
+                .map(PathBuf::from)
+        };
+
+        let dir = env_dir("ILASS_CACHE_DIR")
+            .or_else(|| env_dir("XDG_CACHE_HOME").map(|dir| dir.join("ilass")))
+            .or_else(|| env_dir("LOCALAPPDATA").map(|dir| dir.join("ilass")))
+            .or_else(|| env_dir("HOME").map(|dir| dir.join(".cache").join("ilass")))
+            .ok_or(VadCacheErrorKind::NoCacheDirectory)?;
+
+        Ok(VadCache::new(dir))
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Returns the cached spans and their weights; a missing, outdated or unreadable entry is a
+    /// cache miss.
+    pub fn load(&self, key: &VadCacheKey) -> Option<(Vec<TimeSpan>, Vec<f64>)> {
+        let data = std::fs::read(self.dir.join(key.file_name())).ok()?;
+        let entry: CacheEntry = serde_json::from_slice(&data).ok()?;
+
+        if entry.version != CACHE_FORMAT_VERSION || entry.key != *key || entry.weights.len() != entry.timespans.len() {
+            return None;
+        }
+
+        let timespans = entry
+            .timespans
+            .into_iter()
+            .map(|(start, end)| TimeSpan::new(TimePoint::from_msecs(start), TimePoint::from_msecs(end)))
+            .collect();
+
+        Some((timespans, entry.weights))
+    }
+
+    pub fn store(&self, key: &VadCacheKey, timespans: &[TimeSpan], weights: &[f64]) -> Result<(), VadCacheError> {
+        std::fs::create_dir_all(&self.dir)
+            .with_context(|_| VadCacheErrorKind::CreatingDirectoryFailed(self.dir.clone()))?;
+
+        let entry = CacheEntry {
+            version: CACHE_FORMAT_VERSION,
+            key: key.clone(),
+            timespans: timespans.iter().map(|ts| (ts.start.msecs(), ts.end.msecs())).collect(),
+            weights: weights.to_vec(),
+        };
+        let data = serde_json::to_vec(&entry).with_context(|_| VadCacheErrorKind::SerializingFailed)?;
+
+        // write to a temporary file first, so that concurrent runs never read half-written entries
+        let path = self.dir.join(key.file_name());
+        let tmp_path = path.with_extension(format!("{}.tmp", std::process::id()));
+        std::fs::write(&tmp_path, data).with_context(|_| VadCacheErrorKind::WritingFailed(tmp_path.clone()))?;
+        std::fs::rename(&tmp_path, &path).with_context(|_| VadCacheErrorKind::WritingFailed(path.clone()))?;
+
+        Ok(())
+    }
+
+    /// Deletes all cache entries and returns their number.
+    pub fn clear(&self) -> Result<usize, VadCacheError> {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => {
+                return Err(e)
+                    .with_context(|_| VadCacheErrorKind::ClearingFailed(self.dir.clone()))
+                    .map_err(VadCacheError::from);
+            }
+        };
+
+        let mut removed = 0;
+        for entry in entries {
+            let path = entry
+                .with_context(|_| VadCacheErrorKind::ClearingFailed(self.dir.clone()))?
+                .path();
+            let is_cache_file = path
+                .file_name()
+                .map(|name| name.to_string_lossy())
+                .is_some_and(|name| name.starts_with("vad-"));
+
+            if is_cache_file {
+                std::fs::remove_file(&path).with_context(|_| VadCacheErrorKind::ClearingFailed(path.clone()))?;
+                removed += 1;
+            }
+        }
+
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::TempDir;
+
+    fn spans() -> (Vec<TimeSpan>, Vec<f64>) {
+        let span = |start: i64, end: i64| TimeSpan::new(TimePoint::from_msecs(start), TimePoint::from_msecs(end));
+        (vec![span(0, 1500), span(2000, 2750)], vec![0.9, 0.55])
+    }
+
+    #[test]
+    fn cache_key() {
+        let dir = TempDir::new("vad-cache-key");
+        let video_path = dir.0.join("video.mkv");
+        std::fs::write(&video_path, b"video").unwrap();
+
+        let key = VadCacheKey::for_file(&video_path, None, "silero".to_string()).unwrap();
+        let same_key = VadCacheKey::for_file(&dir.0.join(".").join("video.mkv"), None, "silero".to_string()).unwrap();
+        assert_eq!(key, same_key);
+        assert_eq!(key.file_name(), same_key.file_name());
+        assert!(key.file_name().starts_with("vad-") && key.file_name().ends_with(".json"));
+
+        let other_stream = VadCacheKey::for_file(&video_path, Some(1), "silero".to_string()).unwrap();
+        let other_params = VadCacheKey::for_file(&video_path, None, "energy".to_string()).unwrap();
+        assert_ne!(key.file_name(), other_stream.file_name());
+        assert_ne!(key.file_name(), other_params.file_name());
+
+        std::fs::write(&video_path, b"changed video").unwrap();
+        let changed_key = VadCacheKey::for_file(&video_path, None, "silero".to_string()).unwrap();
+        assert_ne!(key, changed_key);
+
+        assert!(VadCacheKey::for_file(&dir.0.join("missing.mkv"), None, "silero".to_string()).is_err());
+    }
+
+    #[test]
+    fn store_and_load() {
+        let dir = TempDir::new("vad-cache-store");
+        std::fs::write(dir.0.join("video.mkv"), b"video").unwrap();
+        let key = VadCacheKey::for_file(&dir.0.join("video.mkv"), None, "silero".to_string()).unwrap();
+        let other_key = VadCacheKey::for_file(&dir.0.join("video.mkv"), Some(1), "silero".to_string()).unwrap();
+
+        // the cache directory is created on demand
+        let cache = VadCache::new(dir.0.join("cache"));
+        assert_eq!(cache.load(&key), None);
+
+        let (timespans, weights) = spans();
+        cache.store(&key, &timespans, &weights).unwrap();
+        assert_eq!(cache.load(&key), Some((timespans, weights)));
+        assert_eq!(cache.load(&other_key), None);
+
+        assert_eq!(cache.clear().unwrap(), 1);
+        assert_eq!(cache.load(&key), None);
+        assert_eq!(VadCache::new(dir.0.join("missing")).clear().unwrap(), 0);
+    }
+
+    #[test]
+    fn stale_and_corrupt_entries_are_misses() {
+        let dir = TempDir::new("vad-cache-stale");
+        std::fs::write(dir.0.join("video.mkv"), b"video").unwrap();
+        let key = VadCacheKey::for_file(&dir.0.join("video.mkv"), None, "silero".to_string()).unwrap();
+        let other_key = VadCacheKey::for_file(&dir.0.join("video.mkv"), Some(1), "silero".to_string()).unwrap();
+
+        let cache = VadCache::new(dir.0.clone());
+        let entry_path = dir.0.join(key.file_name());
+        let (timespans, weights) = spans();
+        let write_entry = |version: u32, key: &VadCacheKey, weights: Vec<f64>| {
+            let entry = CacheEntry {
+                version,
+                key: key.clone(),
+                timespans: timespans.iter().map(|ts| (ts.start.msecs(), ts.end.msecs())).collect(),
+                weights,
+            };
+            std::fs::write(&entry_path, serde_json::to_vec(&entry).unwrap()).unwrap();
+        };
+
+        write_entry(CACHE_FORMAT_VERSION, &key, weights.clone());
+        assert!(cache.load(&key).is_some());
+
+        // older format
+        write_entry(CACHE_FORMAT_VERSION - 1, &key, weights.clone());
+        assert_eq!(cache.load(&key), None);
+
+        // hash collision with another key
+        write_entry(CACHE_FORMAT_VERSION, &other_key, weights.clone());
+        assert_eq!(cache.load(&key), None);
+
+        // one weight per span
+        write_entry(CACHE_FORMAT_VERSION, &key, vec![1.]);
+        assert_eq!(cache.load(&key), None);
+
+        std::fs::write(&entry_path, b"{\"version\": 2, \"key\"").unwrap();
+        assert_eq!(cache.load(&key), None);
+
+        // the video changed after the entry was written
+        write_entry(CACHE_FORMAT_VERSION, &key, weights.clone());
+        std::fs::write(dir.0.join("video.mkv"), b"changed video").unwrap();
+        let changed_key = VadCacheKey::for_file(&dir.0.join("video.mkv"), None, "silero".to_string()).unwrap();
+        assert_eq!(cache.load(&changed_key), None);
+    }
+}
@@ -41,6 +41,14 @@ struct Stream {
     /// `.mkv` does not store the duration in the streams; we have to use `format -> duration` instead
     pub duration: Option<String>,
     pub codec_type: CodecType,
+    pub codec_name: Option<String>,
+    pub tags: Option<StreamTags>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StreamTags {
+    pub language: Option<String>,
+    pub title: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -96,6 +104,16 @@ pub enum DecoderErrorKind {
     },
     AudioSegmentProcessingFailed,
     NoDurationInformation,
+    FailedExtractingSubtitle {
+        file_path: PathBuf,
+        cmd_path: PathBuf,
+        args: Vec<OsString>,
+    },
+    FailedMuxing {
+        output_path: PathBuf,
+        cmd_path: PathBuf,
+        args: Vec<OsString>,
+    },
 }
 
 fn format_cmd(cmd_path: &Path, args: &[OsString]) -> String {
@@ -155,6 +173,26 @@ impl fmt::Display for DecoderErrorKind {
             }
             DecoderErrorKind::AudioSegmentProcessingFailed => write!(f, "processing audio segment failed"),
             DecoderErrorKind::NoDurationInformation => write!(f, "no audio duration information found"),
+            DecoderErrorKind::FailedExtractingSubtitle {
+                file_path,
+                cmd_path,
+                args,
+            } => write!(
+                f,
+                "failed to extract subtitle stream from '{}' with '{}' ",
+                file_path.display(),
+                format_cmd(cmd_path, args)
+            ),
+            DecoderErrorKind::FailedMuxing {
+                output_path,
+                cmd_path,
+                args,
+            } => write!(
+                f,
+                "failed to write '{}' with '{}' ",
+                output_path.display(),
+                format_cmd(cmd_path, args)
+            ),
         }
     }
 }
@@ -172,7 +210,93 @@ pub struct VideoDecoderFFmpegBinary {}
 
 static PROGRESS_PRESCALER: i64 = 200;
 
+fn ffprobe_path() -> PathBuf {
+    std::env::var_os("ILASS_FFPROBE_PATH")
+        .unwrap_or(OsString::from("ffprobe"))
+        .into()
+}
+
+fn ffmpeg_path() -> PathBuf {
+    std::env::var_os("ILASS_FFMPEG_PATH")
+        .unwrap_or(OsString::from("ffmpeg"))
+        .into()
+}
+
 impl VideoDecoderFFmpegBinary {
+    /// Lists the subtitle streams of a video file (in the order of ffmpeg's `0:s:N` stream specifiers).
+    pub fn subtitle_streams(file_path: impl AsRef<Path>) -> Result<Vec<super::SubtitleStream>, DecoderError> {
+        let file_path_buf: PathBuf = file_path.as_ref().into();
+
+        let args = vec![
+            OsString::from("-v"),
+            OsString::from("error"),
+            OsString::from("-select_streams"),
+            OsString::from("s"),
+            OsString::from("-show_entries"),
+            OsString::from("stream=index,codec_type,codec_name:stream_tags=language,title"),
+            OsString::from("-of"),
+            OsString::from("json"),
+            OsString::from(file_path.as_ref()),
+        ];
+
+        let ffprobe_path = ffprobe_path();
+
+        let metadata: Metadata =
+            Self::get_metadata(file_path_buf.clone(), ffprobe_path.clone(), &args).with_context(|_| {
+                DecoderErrorKind::ExtractingMetadataFailed {
+                    file_path: file_path_buf.clone(),
+                    cmd_path: ffprobe_path.clone(),
+                    args,
+                }
+            })?;
+
+        Ok(metadata
+            .streams
+            .into_iter()
+            .filter(|s| s.codec_type == CodecType::Subtitle)
+            .enumerate()
+            .map(|(subtitle_index, s)| super::SubtitleStream {
+                subtitle_index,
+                codec_name: s.codec_name.unwrap_or_default(),
+                language: s.tags.as_ref().and_then(|tags| tags.language.clone()),
+                title: s.tags.and_then(|tags| tags.title),
+            })
+            .collect())
+    }
+
+    /// Extracts the text-based subtitle stream `0:s:<subtitle_index>` as `.srt` data.
+    pub fn extract_subtitle_stream(
+        file_path: impl AsRef<Path>,
+        subtitle_index: usize,
+    ) -> Result<Vec<u8>, DecoderError> {
+        let ffmpeg_path = ffmpeg_path();
+
+        let args: Vec<OsString> = vec![
+            // only print errors
+            OsString::from("-v"),
+            OsString::from("error"),
+            // input file
+            OsString::from("-i"),
+            file_path.as_ref().into(),
+            // select stream
+            OsString::from("-map"),
+            format!("0:s:{}", subtitle_index).into(),
+            // ffmpeg converts all text-based subtitle codecs to SubRip
+            OsString::from("-f"),
+            OsString::from("srt"),
+            // output to stdout pipe
+            OsString::from("-"),
+        ];
+
+        Self::run_to_stdout(ffmpeg_path.clone(), &args)
+            .with_context(|_| DecoderErrorKind::FailedExtractingSubtitle {
+                file_path: file_path.as_ref().into(),
+                cmd_path: ffmpeg_path.clone(),
+                args: args.clone(),
+            })?
+            .into_ok()
+    }
+
     /// Samples are pushed in 8kHz mono/single-channel format.
     pub fn decode<T>(
         file_path: impl AsRef<Path>,
@@ -192,9 +316,7 @@ impl VideoDecoderFFmpegBinary {
             OsString::from(file_path.as_ref()),
         ];
 
-        let ffprobe_path: PathBuf = std::env::var_os("ILASS_FFPROBE_PATH")
-            .unwrap_or(OsString::from("ffprobe"))
-            .into();
+        let ffprobe_path: PathBuf = ffprobe_path();
 
         let metadata: Metadata =
             Self::get_metadata(file_path_buf.clone(), ffprobe_path.clone(), &args).with_context(|_| {
@@ -224,9 +346,7 @@ impl VideoDecoderFFmpegBinary {
             }
         };
 
-        let ffmpeg_path: PathBuf = std::env::var_os("ILASS_FFMPEG_PATH")
-            .unwrap_or(OsString::from("ffmpeg"))
-            .into();
+        let ffmpeg_path: PathBuf = ffmpeg_path();
 
         let args: Vec<OsString> = vec![
             // only print errors
@@ -396,27 +516,113 @@ impl VideoDecoderFFmpegBinary {
         }
     }
 
-    fn get_metadata(file_path: PathBuf, ffprobe_path: PathBuf, args: &[OsString]) -> Result<Metadata, DecoderError> {
-        let ffprobe_process: Output = Command::new(ffprobe_path.clone())
+    /// Copies all streams of `video_path` and the subtitle file into `output_path` (without
+    /// re-encoding). The subtitle becomes the last subtitle stream, with optional language
+    /// (e.g. `eng`) and title metadata.
+    ///
+    /// With `replaced_stream`, the subtitle stream `0:s:<replaced_stream>` is left out and its
+    /// language and title are used by default.
+    pub fn mux_subtitle(
+        video_path: impl AsRef<Path>,
+        subtitle_path: impl AsRef<Path>,
+        output_path: impl AsRef<Path>,
+        replaced_stream: Option<usize>,
+        language: Option<&str>,
+        title: Option<&str>,
+    ) -> Result<(), DecoderError> {
+        let existing_streams = Self::subtitle_streams(video_path.as_ref())?;
+        let replaced = replaced_stream.and_then(|index| existing_streams.iter().find(|s| s.subtitle_index == index));
+
+        let language = language.or_else(|| replaced.and_then(|s| s.language.as_deref()));
+        let title = title.or_else(|| replaced.and_then(|s| s.title.as_deref()));
+
+        let kept_stream_count = existing_streams.len() - replaced.map_or(0, |_| 1);
+        let new_stream = format!("s:{}", kept_stream_count);
+
+        // mp4/mov containers only support the `mov_text` subtitle codec
+        let needs_mov_text = output_path
+            .as_ref()
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase())
+            .is_some_and(|extension| ["mp4", "m4v", "mov"].contains(&extension.as_str()));
+
+        let mut args: Vec<OsString> = vec![
+            // only print errors
+            OsString::from("-v"),
+            OsString::from("error"),
+            // "yes" -> disables user interaction
+            OsString::from("-y"),
+            // input files
+            OsString::from("-i"),
+            video_path.as_ref().into(),
+            OsString::from("-i"),
+            subtitle_path.as_ref().into(),
+            // all streams of the video
+            OsString::from("-map"),
+            OsString::from("0"),
+        ];
+
+        if let Some(replaced) = replaced {
+            args.push(OsString::from("-map"));
+            args.push(format!("-0:s:{}", replaced.subtitle_index).into());
+        }
+
+        args.extend(vec![
+            // then the subtitle
+            OsString::from("-map"),
+            OsString::from("1:s"),
+            // no re-encoding
+            OsString::from("-c"),
+            OsString::from("copy"),
+        ]);
+
+        if needs_mov_text {
+            args.push(format!("-c:{}", new_stream).into());
+            args.push(OsString::from("mov_text"));
+        }
+        if let Some(language) = language {
+            args.push(format!("-metadata:s:{}", new_stream).into());
+            args.push(format!("language={}", language).into());
+        }
+        if let Some(title) = title {
+            args.push(format!("-metadata:s:{}", new_stream).into());
+            args.push(format!("title={}", title).into());
+        }
+        args.push(output_path.as_ref().into());
+
+        let ffmpeg_path = ffmpeg_path();
+
+        Self::run_to_stdout(ffmpeg_path.clone(), &args).with_context(|_| DecoderErrorKind::FailedMuxing {
+            output_path: output_path.as_ref().into(),
+            cmd_path: ffmpeg_path.clone(),
+            args: args.clone(),
+        })?;
+
+        Ok(())
+    }
+
+    /// Runs a process to completion and returns its stdout (or its stderr as error).
+    fn run_to_stdout(cmd_path: PathBuf, args: &[OsString]) -> Result<Vec<u8>, DecoderError> {
+        let process: Output = Command::new(cmd_path.clone())
             .args(args)
             .stdin(Stdio::null())
             .stderr(Stdio::piped())
             .stdout(Stdio::piped())
             .output()
             .with_context(|_| DecoderErrorKind::FailedSpawningSubprocess {
-                path: ffprobe_path.clone(),
+                path: cmd_path.clone(),
                 args: args.to_vec(),
             })?;
 
-        if !ffprobe_process.status.success() {
-            let stderr: String = String::from_utf8_lossy(&ffprobe_process.stderr)
+        if !process.status.success() {
+            let stderr: String = String::from_utf8_lossy(&process.stderr)
                 .to_string()
                 .trim_end()
                 .to_string();
 
             let err = DecoderErrorKind::ProcessErrorCode {
-                cmd_path: ffprobe_path.clone(),
-                code: ffprobe_process.status.code(),
+                cmd_path: cmd_path.clone(),
+                code: process.status.code(),
             };
 
             if stderr.is_empty() {
@@ -430,8 +636,13 @@ impl VideoDecoderFFmpegBinary {
             }
         }
 
-        let stdout =
-            from_utf8(&ffprobe_process.stdout).with_context(|_| DecoderErrorKind::FailedToDecodeVideoStreamInfo)?;
+        Ok(process.stdout)
+    }
+
+    fn get_metadata(file_path: PathBuf, ffprobe_path: PathBuf, args: &[OsString]) -> Result<Metadata, DecoderError> {
+        let ffprobe_stdout = Self::run_to_stdout(ffprobe_path, args)?;
+
+        let stdout = from_utf8(&ffprobe_stdout).with_context(|_| DecoderErrorKind::FailedToDecodeVideoStreamInfo)?;
 
         let metadata: Metadata = serde_json::from_str(stdout)
             .with_context(|_| DecoderErrorKind::DeserializingMetadataFailed { path: file_path })?;
@@ -21,7 +21,9 @@ fn av_err2str(errnum: libc::c_int) -> String {
 define_error!(DecoderError, DecoderErrorKind);
 
 #[derive(Debug, Fail)]
-pub(crate) enum DecoderErrorKind {}
+pub(crate) enum DecoderErrorKind {
+    NotSupported { operation: &'static str },
+}
 
 fn format_cmd(cmd_path: &PathBuf, args: &[OsString]) -> String {
     let args_string: String = args
@@ -34,13 +36,48 @@ fn format_cmd(cmd_path: &PathBuf, args: &[OsString]) -> String {
 
 impl fmt::Display for DecoderErrorKind {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        unimplemented!()
+        match self {
+            DecoderErrorKind::NotSupported { operation } => {
+                write!(f, "{} is not supported by the ffmpeg library backend", operation)
+            }
+        }
     }
 }
 
 pub struct VideoDecoderFFmpegLibrary {}
 
 impl VideoDecoderFFmpegLibrary {
+    pub(crate) fn subtitle_streams(_file_path: impl AsRef<Path>) -> Result<Vec<super::SubtitleStream>, DecoderError> {
+        Err(DecoderErrorKind::NotSupported {
+            operation: "listing subtitle streams",
+        }
+        .into())
+    }
+
+    pub(crate) fn extract_subtitle_stream(
+        _file_path: impl AsRef<Path>,
+        _subtitle_index: usize,
+    ) -> Result<Vec<u8>, DecoderError> {
+        Err(DecoderErrorKind::NotSupported {
+            operation: "extracting subtitle streams",
+        }
+        .into())
+    }
+
+    pub(crate) fn mux_subtitle(
+        _video_path: impl AsRef<Path>,
+        _subtitle_path: impl AsRef<Path>,
+        _output_path: impl AsRef<Path>,
+        _replaced_stream: Option<usize>,
+        _language: Option<&str>,
+        _title: Option<&str>,
+    ) -> Result<(), DecoderError> {
+        Err(DecoderErrorKind::NotSupported {
+            operation: "writing video files",
+        }
+        .into())
+    }
+
     /// Samples are pushed in 8kHz mono/single-channel format.
     pub(crate) fn decode<T>(
         file_path: impl AsRef<Path>,
@@ -10,6 +10,36 @@ mod ffmpeg_binary;
 #[cfg(feature = "ffmpeg-binary")]
 pub use ffmpeg_binary::VideoDecoderFFmpegBinary as VideoDecoder;
 
+/// A subtitle stream in a video container.
+#[derive(Debug, Clone)]
+pub struct SubtitleStream {
+    /// The position among all subtitle streams (`N` in ffmpeg's `0:s:N`).
+    pub subtitle_index: usize,
+    pub codec_name: String,
+    pub language: Option<String>,
+    pub title: Option<String>,
+}
+
+impl SubtitleStream {
+    /// Image-based subtitles (e.g. from Blu-rays and DVDs) can not be extracted as text.
+    pub fn is_text_based(&self) -> bool {
+        !["hdmv_pgs_subtitle", "dvd_subtitle", "dvb_subtitle", "xsub"].contains(&self.codec_name.as_str())
+    }
+
+    /// A short description like `0 (subrip, eng, "Full")`.
+    pub fn description(&self) -> String {
+        let mut description = format!("{} ({}", self.subtitle_index, self.codec_name);
+        if let Some(language) = &self.language {
+            description.push_str(&format!(", {}", language));
+        }
+        if let Some(title) = &self.title {
+            description.push_str(&format!(", \"{}\"", title));
+        }
+        description.push(')');
+        description
+    }
+}
+
 pub trait AudioReceiver {
     type Output;
     type Error: failure::Fail;
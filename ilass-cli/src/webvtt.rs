@@ -0,0 +1,416 @@
+//! Parsing and writing of WebVTT files (which `subparse` does not support).
+//!
+//! Only the cue timings are changed when a file is written back: cue identifiers, cue settings,
+//! the header, all NOTE/STYLE/REGION blocks and the line endings (LF or CRLF) are kept as they are.
+
+use crate::errors::*;
+use subparse::SubtitleEntry;
+use subparse::timetypes::{TimePoint, TimeSpan};
+
+#[derive(Debug, Clone)]
+struct VttCue {
+    identifier: Option<String>,
+    timespan: TimeSpan,
+
+    /// Everything after the end time on the timing line (e.g. `align:start line:0`).
+    settings: String,
+    text: String,
+}
+
+#[derive(Debug, Clone)]
+enum VttBlock {
+    Cue(VttCue),
+
+    /// NOTE, STYLE and REGION blocks (and unknown blocks), stored verbatim.
+    Other(String),
+}
+
+/// A parsed WebVTT file.
+#[derive(Debug, Clone)]
+pub struct VttFile {
+    /// The `WEBVTT` line with all following header lines.
+    header: String,
+    blocks: Vec<VttBlock>,
+
+    /// `"\r\n"` if the parsed file used CRLF line endings, otherwise `"\n"`.
+    line_ending: &'static str,
+}
+
+impl VttFile {
+    pub fn parse(data: &str) -> Result<VttFile, WebVttError> {
+        let line_ending = if data.contains("\r\n") { "\r\n" } else { "\n" };
+        let data = data
+            .trim_start_matches('\u{feff}')
+            .replace("\r\n", "\n")
+            .replace('\r', "\n");
+
+        let mut blocks = split_blocks(&data).into_iter();
+        let header = blocks.next().unwrap_or("");
+        let is_header = header
+            .strip_prefix("WEBVTT")
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with([' ', '\t', '\n']));
+        if !is_header {
+            return Err(WebVttErrorKind::MissingHeader.into());
+        }
+
+        let blocks = blocks
+            .enumerate()
+            .map(|(block_nr, block)| parse_block(block, block_nr + 1))
+            .collect::<Result<Vec<VttBlock>, WebVttError>>()?;
+
+        Ok(VttFile {
+            header: header.to_string(),
+            blocks,
+            line_ending,
+        })
+    }
+
+    /// Creates a file with one cue (without identifier or settings) for each line.
+    pub fn create(lines: Vec<(TimeSpan, String)>) -> VttFile {
+        VttFile {
+            header: "WEBVTT".to_string(),
+            blocks: lines
+                .into_iter()
+                .map(|(timespan, text)| {
+                    VttBlock::Cue(VttCue {
+                        identifier: None,
+                        timespan,
+                        settings: String::new(),
+                        text,
+                    })
+                })
+                .collect(),
+            line_ending: "\n",
+        }
+    }
+
+    fn cues(&self) -> impl Iterator<Item = &VttCue> {
+        self.blocks.iter().filter_map(|block| match block {
+            VttBlock::Cue(cue) => Some(cue),
+            VttBlock::Other(_) => None,
+        })
+    }
+
+    pub fn get_subtitle_entries(&self) -> Vec<SubtitleEntry> {
+        self.cues()
+            .map(|cue| SubtitleEntry {
+                timespan: cue.timespan,
+                line: Some(cue.text.clone()),
+            })
+            .collect()
+    }
+
+    /// Returns `true` if any cue has settings like `align:start` (which only WebVTT understands).
+    pub fn has_cue_settings(&self) -> bool {
+        self.cues().any(|cue| !cue.settings.is_empty())
+    }
+
+    /// Returns `true` if the file has NOTE, STYLE or REGION blocks.
+    pub fn has_other_blocks(&self) -> bool {
+        self.blocks.iter().any(|block| matches!(block, VttBlock::Other(_)))
+    }
+
+    /// Sets the timings of all cues (the text of the entries is ignored).
+    pub fn update_subtitle_entries(&mut self, entries: &[SubtitleEntry]) -> Result<(), WebVttError> {
+        let cue_count = self.cues().count();
+        if cue_count != entries.len() {
+            return Err(WebVttErrorKind::EntryCountMismatch {
+                cue_count,
+                entry_count: entries.len(),
+            }
+            .into());
+        }
+
+        let cues = self.blocks.iter_mut().filter_map(|block| match block {
+            VttBlock::Cue(cue) => Some(cue),
+            VttBlock::Other(_) => None,
+        });
+        for (cue, entry) in cues.zip(entries) {
+            cue.timespan = entry.timespan;
+        }
+
+        Ok(())
+    }
+
+    /// Negative timings can not be represented in WebVTT and are written as zero.
+    pub fn to_data(&self) -> Vec<u8> {
+        let mut result = self.header.clone();
+        result.push_str("\n\n");
+
+        for block in &self.blocks {
+            match block {
+                VttBlock::Cue(cue) => {
+                    if let Some(identifier) = &cue.identifier {
+                        result.push_str(identifier);
+                        result.push('\n');
+                    }
+                    result.push_str(&format_timepoint(cue.timespan.start));
+                    result.push_str(" --> ");
+                    result.push_str(&format_timepoint(cue.timespan.end));
+                    if !cue.settings.is_empty() {
+                        result.push(' ');
+                        result.push_str(&cue.settings);
+                    }
+                    result.push('\n');
+                    if !cue.text.is_empty() {
+                        result.push_str(&cue.text);
+                        result.push('\n');
+                    }
+                }
+                VttBlock::Other(text) => {
+                    result.push_str(text);
+                    result.push('\n');
+                }
+            }
+            result.push('\n');
+        }
+
+        if self.line_ending != "\n" {
+            result = result.replace('\n', self.line_ending);
+        }
+        result.into_bytes()
+    }
+}
+
+/// Splits the file at blank lines (lines which are empty or only contain whitespace).
+fn split_blocks(data: &str) -> Vec<&str> {
+    let mut blocks = Vec::new();
+    let mut block_start: Option<usize> = None;
+    let mut line_start = 0;
+
+    for line in data.split('\n') {
+        if line.trim().is_empty() {
+            // the block ends before the line break of its last line
+            if let Some(start) = block_start.take() {
+                blocks.push(&data[start..line_start - 1]);
+            }
+        } else if block_start.is_none() {
+            block_start = Some(line_start);
+        }
+        line_start += line.len() + 1;
+    }
+
+    if let Some(start) = block_start {
+        blocks.push(&data[start..]);
+    }
+    blocks
+}
+
+fn parse_block(block: &str, block_nr: usize) -> Result<VttBlock, WebVttError> {
+    let mut lines = block.lines();
+    let first_line = lines.next().unwrap_or("");
+
+    let keyword = first_line.split([' ', '\t']).next().unwrap_or("");
+    if ["NOTE", "STYLE", "REGION"].contains(&keyword) {
+        return Ok(VttBlock::Other(block.to_string()));
+    }
+
+    let (identifier, timing_line) = if first_line.contains("-->") {
+        (None, first_line)
+    } else {
+        match lines.next() {
+            Some(line) if line.contains("-->") => (Some(first_line.to_string()), line),
+            // blocks which are not cues are ignored by players
+            _ => return Ok(VttBlock::Other(block.to_string())),
+        }
+    };
+
+    let invalid_timing = || WebVttErrorKind::InvalidTimingLine {
+        block_nr,
+        line: timing_line.to_string(),
+    };
+
+    let (start, rest) = timing_line.split_once("-->").ok_or_else(invalid_timing)?;
+    let rest = rest.trim_start();
+    let (end, settings) = rest.split_once([' ', '\t']).unwrap_or((rest, ""));
+
+    let start = parse_timepoint(start.trim()).ok_or_else(invalid_timing)?;
+    let end = parse_timepoint(end).ok_or_else(invalid_timing)?;
+
+    Ok(VttBlock::Cue(VttCue {
+        identifier,
+        timespan: TimeSpan::new(start, end),
+        settings: settings.trim().to_string(),
+        text: lines.collect::<Vec<&str>>().join("\n"),
+    }))
+}
+
+/// Parses `hh:mm:ss.ttt` or `mm:ss.ttt` (minutes and seconds have to be below 60).
+fn parse_timepoint(s: &str) -> Option<TimePoint> {
+    let (seconds_part, millis) = s.split_once('.')?;
+    if millis.len() != 3 || !millis.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    let component_count = seconds_part.split(':').count();
+    if !(2..=3).contains(&component_count) {
+        return None;
+    }
+
+    let mut msecs: i64 = 0;
+    for (i, component) in seconds_part.split(':').enumerate() {
+        if component.is_empty() || !component.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let value = component.parse::<i64>().ok()?;
+        let is_hours = component_count == 3 && i == 0;
+        if !is_hours && value >= 60 {
+            return None;
+        }
+        msecs = msecs * 60 + value;
+    }
+
+    Some(TimePoint::from_msecs(msecs * 1000 + millis.parse::<i64>().ok()?))
+}
+
+fn format_timepoint(t: TimePoint) -> String {
+    let msecs = t.msecs().max(0);
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        msecs / 3600000,
+        msecs / 60000 % 60,
+        msecs / 1000 % 60,
+        msecs % 1000
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VTT_DATA: &str = "WEBVTT - a title
+Kind: captions
+
+STYLE
+::cue { color: yellow }
+
+REGION
+id:fred width:40%
+
+NOTE two
+lines
+
+intro
+00:01.000 --> 00:02.500 align:start line:0
+<v Bob>Hello</v>
+world
+
+01:00:03.000 --> 01:00:04.000
+Bye
+";
+
+    fn timings(file: &VttFile) -> Vec<(i64, i64)> {
+        file.get_subtitle_entries()
+            .iter()
+            .map(|entry| (entry.timespan.start.msecs(), entry.timespan.end.msecs()))
+            .collect()
+    }
+
+    #[test]
+    fn parse_cues_and_blocks() {
+        let file = VttFile::parse(VTT_DATA).unwrap();
+        assert_eq!(file.header, "WEBVTT - a title\nKind: captions");
+        assert_eq!(timings(&file), vec![(1000, 2500), (3603000, 3604000)]);
+
+        let texts: Vec<Option<String>> = file
+            .get_subtitle_entries()
+            .into_iter()
+            .map(|entry| entry.line)
+            .collect();
+        assert_eq!(
+            texts,
+            vec![Some("<v Bob>Hello</v>\nworld".to_string()), Some("Bye".to_string())]
+        );
+
+        let cues: Vec<&VttCue> = file.cues().collect();
+        assert_eq!(cues[0].identifier.as_deref(), Some("intro"));
+        assert_eq!(cues[0].settings, "align:start line:0");
+        assert_eq!(cues[1].identifier, None);
+        assert!(cues[1].settings.is_empty());
+
+        assert!(file.has_cue_settings());
+        assert!(file.has_other_blocks());
+        assert_eq!(file.blocks.len(), 5);
+    }
+
+    #[test]
+    fn whitespace_only_lines_separate_blocks() {
+        let file = VttFile::parse("WEBVTT\n \n00:01.000 --> 00:02.000\nA\n\t\n00:03.000 --> 00:04.000\nB\n").unwrap();
+        assert_eq!(timings(&file), vec![(1000, 2000), (3000, 4000)]);
+        assert!(!file.has_other_blocks());
+    }
+
+    #[test]
+    fn invalid_files() {
+        assert!(VttFile::parse("00:01.000 --> 00:02.000\nA\n").is_err());
+        assert!(VttFile::parse("WEBVTTX\n\n00:01.000 --> 00:02.000\nA\n").is_err());
+        assert!(VttFile::parse("WEBVTT\n\n00:01.000 --> 00:0x.000\nA\n").is_err());
+    }
+
+    #[test]
+    fn timepoint_ranges() {
+        let msecs = |s: &str| parse_timepoint(s).map(|t| t.msecs());
+        assert_eq!(msecs("01:02.003"), Some(62003));
+        assert_eq!(msecs("100:59:59.999"), Some(363599999));
+        assert_eq!(msecs("60:00.000"), None);
+        assert_eq!(msecs("00:60.000"), None);
+        assert_eq!(msecs("00:60:00.000"), None);
+        assert_eq!(msecs("00:00:60.000"), None);
+        assert_eq!(msecs("00:01.00"), None);
+        assert_eq!(msecs("1:2:3:04.000"), None);
+        assert_eq!(msecs("01.000"), None);
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut file = VttFile::parse(VTT_DATA).unwrap();
+        let data = String::from_utf8(file.to_data()).unwrap();
+        let expected = VTT_DATA.replace("00:01.000 --> 00:02.500", "00:00:01.000 --> 00:00:02.500");
+        assert_eq!(data, expected + "\n");
+
+        let mut entries = file.get_subtitle_entries();
+        for entry in &mut entries {
+            let start = TimePoint::from_msecs(entry.timespan.start.msecs() - 500);
+            entry.timespan = TimeSpan::new(start, entry.timespan.end);
+        }
+        file.update_subtitle_entries(&entries).unwrap();
+
+        let reparsed = VttFile::parse(&String::from_utf8(file.to_data()).unwrap()).unwrap();
+        assert_eq!(timings(&reparsed), vec![(500, 2500), (3602500, 3604000)]);
+        assert_eq!(reparsed.header, file.header);
+        let texts = |file: &VttFile| -> Vec<Option<String>> {
+            file.get_subtitle_entries()
+                .into_iter()
+                .map(|entry| entry.line)
+                .collect()
+        };
+        assert_eq!(texts(&reparsed), texts(&file));
+        assert_eq!(reparsed.blocks.len(), file.blocks.len());
+
+        assert!(file.update_subtitle_entries(&entries[..1]).is_err());
+    }
+
+    #[test]
+    fn crlf_line_endings_are_kept() {
+        let crlf_data = VTT_DATA.replace('\n', "\r\n");
+        let file = VttFile::parse(&crlf_data).unwrap();
+        assert_eq!(timings(&file), vec![(1000, 2500), (3603000, 3604000)]);
+
+        let data = String::from_utf8(file.to_data()).unwrap();
+        assert!(data.starts_with("WEBVTT - a title\r\nKind: captions\r\n\r\nSTYLE\r\n"));
+        assert!(!data.replace("\r\n", "").contains('\n'));
+
+        let lf_data = String::from_utf8(VttFile::parse(VTT_DATA).unwrap().to_data()).unwrap();
+        assert!(!lf_data.contains('\r'));
+    }
+
+    #[test]
+    fn create_clamps_negative_timings() {
+        let timespan = TimeSpan::new(TimePoint::from_msecs(-500), TimePoint::from_msecs(1000));
+        let file = VttFile::create(vec![(timespan, "Hi".to_string())]);
+        assert_eq!(
+            String::from_utf8(file.to_data()).unwrap(),
+            "WEBVTT\n\n00:00:00.000 --> 00:00:01.000\nHi\n\n"
+        );
+    }
+}
@@ -0,0 +1,352 @@
+use crate::ilass::OffsetPrior;
+use crate::rating_type::{Rating, RatingExt};
+use crate::time_types::{TimeDelta, TimePoint};
+use crate::{Score, standard_scoring};
+
+/// The signature of a scoring function like `standard_scoring` or `overlap_scoring`.
+pub type ScoreFn = fn(TimeDelta, TimeDelta) -> Score;
+
+/// Decides whether the lines of the incorrect subtitle may get different offsets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AlignMode {
+    /// All lines are shifted by the same offset (this mode is much faster).
+    NoSplit,
+
+    /// The algorithm may introduce/remove breaks between lines.
+    Split,
+
+    /// Like `Split`, but each segment may additionally be scaled by one of the segment
+    /// scale factors (see `AlignmentConfig::segment_scale_factors`) to correct a drift
+    /// which changes over time. This mode is slower than `Split`.
+    PiecewiseLinear,
+}
+
+/// Pins a line of the incorrect subtitle (see `AlignmentConfig::anchor`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Anchor {
+    /// The (scaled) line has to start at this time point.
+    Time(TimePoint),
+
+    /// The offset of the (scaled) line has to be in `min..=max`.
+    OffsetRange(TimeDelta, TimeDelta),
+}
+
+/// Scales the split penalty depending on the gap between two consecutive lines (see
+/// `AlignmentConfig::split_penalty_curve`), e.g. to make splits in the middle of dense dialogue
+/// more expensive than splits at long pauses (where advertisement breaks or cut scenes are).
+///
+/// The curve consists of points `(gap, factor)`. Between two points the factor is interpolated
+/// linearly, before the first and after the last point it is constant. A curve without points
+/// (the default) has the factor `1` for all gaps.
+///
+/// ```
+/// use ilass::{SplitPenaltyCurve, TimeDelta};
+///
+/// // four times the split penalty up to a gap of 500, half of it from a gap of 5000
+/// let curve = SplitPenaltyCurve::new(vec![
+///     (TimeDelta::from_i64(500), 4.),
+///     (TimeDelta::from_i64(5000), 0.5),
+/// ]);
+/// assert_eq!(curve.factor(TimeDelta::from_i64(0)), 4.);
+/// assert_eq!(curve.factor(TimeDelta::from_i64(2750)), 2.25);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SplitPenaltyCurve {
+    points: Vec<(TimeDelta, f64)>,
+}
+
+impl SplitPenaltyCurve {
+    /// Creates a curve from its points.
+    ///
+    /// # Panics
+    ///
+    /// This function asserts that the gaps are strictly increasing and that all factors are
+    /// finite and not negative.
+    pub fn new(points: Vec<(TimeDelta, f64)>) -> SplitPenaltyCurve {
+        assert!(points.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert!(points.iter().all(|&(_, factor)| factor.is_finite() && factor >= 0.));
+        SplitPenaltyCurve { points }
+    }
+
+    /// Returns the factor for the split penalty between two lines with the gap `gap`.
+    pub fn factor(&self, gap: TimeDelta) -> f64 {
+        let next_nr = self.points.partition_point(|&(point_gap, _)| point_gap <= gap);
+        match (
+            next_nr.checked_sub(1).map(|nr| self.points[nr]),
+            self.points.get(next_nr),
+        ) {
+            (None, None) => 1.,
+            (Some((_, factor)), None) | (None, Some(&(_, factor))) => factor,
+            (Some((gap1, factor1)), Some(&(gap2, factor2))) => {
+                let position = (gap - gap1).as_f64() / (gap2 - gap1).as_f64();
+                factor1 + (factor2 - factor1) * position
+            }
+        }
+    }
+}
+
+/// All parameters of an alignment run (see `align_with_config`).
+///
+/// ```
+/// use ilass::{AlignMode, AlignmentConfig};
+///
+/// let config = AlignmentConfig::new()
+///     .mode(AlignMode::Split)
+///     .split_penalty(7.0)
+///     .speed_optimization(Some(1.0))
+///     .framerate_candidates(vec![25. / 24., 24. / 25.]);
+/// ```
+#[derive(Debug, Clone)]
+pub struct AlignmentConfig {
+    pub(crate) mode: AlignMode,
+    pub(crate) split_penalty: f64,
+    pub(crate) split_penalty_curve: SplitPenaltyCurve,
+    pub(crate) split_jump_penalty: f64,
+    pub(crate) min_split_jump: TimeDelta,
+    pub(crate) speed_optimization: Option<f64>,
+    pub(crate) score_fn: ScoreFn,
+    pub(crate) framerate_candidates: Vec<f64>,
+    pub(crate) scale_range: Option<(f64, f64)>,
+    pub(crate) segment_scale_factors: Vec<f64>,
+    pub(crate) offset_bounds: Option<(TimeDelta, TimeDelta)>,
+    pub(crate) expected_offset: Option<ExpectedOffset>,
+    pub(crate) anchors: Vec<(usize, Anchor)>,
+    pub(crate) reference_weights: Vec<f64>,
+    pub(crate) line_texts: Option<(Vec<String>, Vec<String>)>,
+    pub(crate) text_weight: f64,
+}
+
+impl Default for AlignmentConfig {
+    fn default() -> AlignmentConfig {
+        AlignmentConfig {
+            mode: AlignMode::Split,
+            split_penalty: 7.0,
+            split_penalty_curve: SplitPenaltyCurve::default(),
+            split_jump_penalty: 0.0,
+            min_split_jump: TimeDelta::zero(),
+            speed_optimization: None,
+            score_fn: standard_scoring,
+            framerate_candidates: Vec::new(),
+            scale_range: None,
+            segment_scale_factors: vec![0.998, 0.999, 1.001, 1.002],
+            offset_bounds: None,
+            expected_offset: None,
+            anchors: Vec::new(),
+            reference_weights: Vec::new(),
+            line_texts: None,
+            text_weight: 0.8,
+        }
+    }
+}
+
+impl AlignmentConfig {
+    /// Creates a configuration for split mode with a split penalty of 7 (for all gaps), no speed
+    /// optimization, `standard_scoring`, no framerate guessing, unbounded offsets, no anchors,
+    /// equally weighted reference spans and without texts.
+    pub fn new() -> AlignmentConfig {
+        AlignmentConfig::default()
+    }
+
+    /// Sets the alignment mode.
+    pub fn mode(mut self, mode: AlignMode) -> AlignmentConfig {
+        self.mode = mode;
+        self
+    }
+
+    /// Sets the normalized split penalty (see `align`). Not used in no-split mode.
+    pub fn split_penalty(mut self, split_penalty: f64) -> AlignmentConfig {
+        self.split_penalty = split_penalty;
+        self
+    }
+
+    /// Scales the split penalty between two lines by the factor of the `curve` at the gap
+    /// between the lines (see `SplitPenaltyCurve`). Overlapping lines have a gap of zero.
+    pub fn split_penalty_curve(mut self, curve: SplitPenaltyCurve) -> AlignmentConfig {
+        self.split_penalty_curve = curve;
+        self
+    }
+
+    /// Adds `penalty` for every time unit the offset changes to the penalty of a split (in
+    /// the units of the normalized split penalty), so small corrections between neighboring
+    /// lines are cheaper than large jumps. Only used in `AlignMode::Split`.
+    ///
+    /// # Panics
+    ///
+    /// This function asserts that `penalty` is finite and not negative.
+    pub fn split_jump_penalty(mut self, penalty: f64) -> AlignmentConfig {
+        assert!(penalty.is_finite() && penalty >= 0.);
+        self.split_jump_penalty = penalty;
+        self
+    }
+
+    /// Forbids splits which change the offset by less than `min_jump`, so the result does not
+    /// contain blocks which are only shifted by a few milliseconds against their neighbors.
+    /// Only used in `AlignMode::Split`.
+    ///
+    /// # Panics
+    ///
+    /// This function asserts that `min_jump` is not negative.
+    pub fn min_split_jump(mut self, min_jump: TimeDelta) -> AlignmentConfig {
+        assert!(min_jump >= TimeDelta::zero());
+        self.min_split_jump = min_jump;
+        self
+    }
+
+    /// Sets the speed optimization (see `align`). Not used in no-split mode.
+    pub fn speed_optimization(mut self, speed_optimization: Option<f64>) -> AlignmentConfig {
+        self.speed_optimization = speed_optimization;
+        self
+    }
+
+    /// Sets the scoring function for the alignment.
+    pub fn score_fn(mut self, score_fn: ScoreFn) -> AlignmentConfig {
+        self.score_fn = score_fn;
+        self
+    }
+
+    /// Sets the scaling factors (e.g. "25/24") which are tried on the incorrect spans
+    /// before aligning. Scaling by `1.0` is always tried, so an empty list disables
+    /// framerate guessing.
+    pub fn framerate_candidates(mut self, framerate_candidates: Vec<f64>) -> AlignmentConfig {
+        self.framerate_candidates = framerate_candidates;
+        self
+    }
+
+    /// Searches an arbitrary scaling factor in `min_scale..=max_scale` (see
+    /// `estimate_scale_factor`) instead of trying the framerate candidates.
+    ///
+    /// # Panics
+    ///
+    /// This function asserts that `min_scale` is positive and less or equal `max_scale`.
+    pub fn continuous_scale_search(mut self, min_scale: f64, max_scale: f64) -> AlignmentConfig {
+        assert!(0. < min_scale && min_scale <= max_scale);
+        self.scale_range = Some((min_scale, max_scale));
+        self
+    }
+
+    /// Sets the scaling factors (in addition to `1.0` and relative to the framerate correction)
+    /// which each segment may choose in `AlignMode::PiecewiseLinear`. Every factor makes the
+    /// alignment slower. The default is `0.998, 0.999, 1.001, 1.002`.
+    ///
+    /// # Panics
+    ///
+    /// This function asserts that all factors are positive.
+    pub fn segment_scale_factors(mut self, segment_scale_factors: Vec<f64>) -> AlignmentConfig {
+        assert!(segment_scale_factors.iter().all(|&scale_factor| scale_factor > 0.));
+        self.segment_scale_factors = segment_scale_factors;
+        self
+    }
+
+    /// Restricts the offsets of all lines to `min..=max`.
+    ///
+    /// # Panics
+    ///
+    /// This function asserts that `min` is less or equal `max`.
+    pub fn offset_bounds(mut self, min: TimeDelta, max: TimeDelta) -> AlignmentConfig {
+        assert!(min <= max);
+        self.offset_bounds = Some((min, max));
+        self
+    }
+
+    /// Restricts the offsets of all lines to `-max_offset..=max_offset`.
+    ///
+    /// # Panics
+    ///
+    /// This function asserts that `max_offset` is not negative.
+    pub fn max_offset(self, max_offset: TimeDelta) -> AlignmentConfig {
+        self.offset_bounds(-max_offset, max_offset)
+    }
+
+    /// Prefers offsets near `offset` (e.g. known from a previous run), which breaks ties between
+    /// equally good offsets of repetitive dialogue. Every line gets a bonus of `weight` (a
+    /// perfectly matching line has a rating of 1) at `offset`, which falls off linearly to zero
+    /// at a distance of `width`. Not supported in `AlignMode::PiecewiseLinear`.
+    ///
+    /// # Panics
+    ///
+    /// This function asserts that `width` is positive and `weight` is between 0 and 1.
+    /// `align_with_config` panics if the mode is `AlignMode::PiecewiseLinear`.
+    pub fn expected_offset(mut self, offset: TimeDelta, width: TimeDelta, weight: f64) -> AlignmentConfig {
+        assert!(width > TimeDelta::zero());
+        assert!((0. ..=1.).contains(&weight));
+        self.expected_offset = Some(ExpectedOffset { offset, width, weight });
+        self
+    }
+
+    /// Pins the line with index `line_index` in the incorrect list. A later anchor
+    /// for the same line replaces the earlier one.
+    ///
+    /// # Panics
+    ///
+    /// This function asserts that the range of an `Anchor::OffsetRange` is not empty.
+    pub fn anchor(mut self, line_index: usize, anchor: Anchor) -> AlignmentConfig {
+        if let Anchor::OffsetRange(min, max) = anchor {
+            assert!(min <= max);
+        }
+        self.anchors
+            .retain(|&(other_line_index, _)| other_line_index != line_index);
+        self.anchors.push((line_index, anchor));
+        self
+    }
+
+    /// Sets a weight (from 0 to 1) for each span of the reference list (in the same order),
+    /// e.g. the confidence of the voice activity detection. Overlaps with a reference span are
+    /// rated proportionally to its weight, so unreliable spans attract fewer lines. An empty
+    /// list (the default) weights all spans with one.
+    ///
+    /// # Panics
+    ///
+    /// This function asserts that all weights are in `0..=1`. `align_with_config` panics if
+    /// the number of weights differs from the number of reference spans.
+    pub fn reference_weights(mut self, reference_weights: Vec<f64>) -> AlignmentConfig {
+        assert!(reference_weights.iter().all(|weight| (0. ..=1.).contains(weight)));
+        self.reference_weights = reference_weights;
+        self
+    }
+
+    /// Sets the text of every reference span and of every incorrect span (in the same order
+    /// as the spans), e.g. the lines of two subtitles in the same language. The rating of an
+    /// overlap then also depends on how many words the two lines share (see `text_weight`), so
+    /// lines rather end up at the reference line with the same text.
+    ///
+    /// # Panics
+    ///
+    /// `align_with_config` panics if the number of texts differs from the number of spans.
+    pub fn line_texts(mut self, reference_texts: Vec<String>, list_texts: Vec<String>) -> AlignmentConfig {
+        self.line_texts = Some((reference_texts, list_texts));
+        self
+    }
+
+    /// Sets how much the rating of an overlap depends on the text similarity of the two lines
+    /// (see `line_texts`): the rating is multiplied by `1 - text_weight + text_weight *
+    /// similarity`, where the similarity is between 0 (no common words) and 1 (the same words).
+    /// The default is `0.8`.
+    ///
+    /// # Panics
+    ///
+    /// This function asserts that `text_weight` is in `0..=1`.
+    pub fn text_weight(mut self, text_weight: f64) -> AlignmentConfig {
+        assert!((0. ..=1.).contains(&text_weight));
+        self.text_weight = text_weight;
+        self
+    }
+}
+
+/// An offset the lines are expected to have (see `AlignmentConfig::expected_offset`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct ExpectedOffset {
+    pub offset: TimeDelta,
+    pub width: TimeDelta,
+    pub weight: f64,
+}
+
+impl ExpectedOffset {
+    /// The prior for `span_count` spans which share their offset.
+    pub fn prior(&self, span_count: usize) -> OffsetPrior {
+        OffsetPrior {
+            center: self.offset,
+            width: self.width,
+            bonus: Rating::convert_from_f64(self.weight * span_count as f64),
+        }
+    }
+}
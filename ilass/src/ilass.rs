@@ -1,10 +1,12 @@
 use crate::rating_type::{Rating, RatingDelta, RatingDeltaDelta, RatingDeltaExt, RatingExt};
 use crate::segments::{
-    DifferentialRatingBufferBuilder, OffsetBuffer, RatingBuffer, RatingIterator, RatingSegment, SeparateDualBuffer,
-    combined_maximum_of_dual_iterators,
+    DifferentialRatingBufferBuilder, DualBuffer, OffsetBuffer, RatingBuffer, RatingIterator, RatingSegment,
+    SeparateDualBuffer, add_rating_iterators, combined_maximum_of_dual_iterators,
 };
 use crate::time_types::{TimeDelta, TimePoint, TimeSpan};
+use crate::weights::SpanWeights;
 
+use std::cmp::{max, min};
 use std::convert::TryInto;
 
 /// Use this trait if you want more detailed information about the progress of the align operation
@@ -30,6 +32,84 @@ pub trait ProgressHandler {
 pub struct NoProgressHandler;
 impl ProgressHandler for NoProgressHandler {}
 
+/// Restricts the offsets `Aligner::align_with_splits` may choose.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OffsetConstraints {
+    /// The (inclusive) window for the offsets of all spans.
+    pub window: Option<(TimeDelta, TimeDelta)>,
+
+    /// Either empty or an optional (inclusive) offset range for each span. The ranges
+    /// have to lie inside the window.
+    pub span_ranges: Vec<Option<(TimeDelta, TimeDelta)>>,
+
+    /// A bonus which is added to the rating of every span near an expected offset.
+    pub prior: Option<OffsetPrior>,
+}
+
+/// A bonus for offsets near an expected offset: it is `bonus` at `center` and falls off
+/// linearly to zero at `center - width` and `center + width`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OffsetPrior {
+    pub center: TimeDelta,
+    pub width: TimeDelta,
+    pub bonus: Rating,
+}
+
+impl OffsetPrior {
+    /// The offsets after which the slope of the bonus changes (and by how much).
+    fn slope_changes(&self) -> [(TimeDelta, RatingDeltaDelta); 3] {
+        assert!(self.width > TimeDelta::zero());
+        let slope: RatingDelta = self.bonus / self.width.as_i64();
+
+        [
+            (self.center - self.width, slope),
+            (self.center, -2 * slope),
+            (self.center + self.width, slope),
+        ]
+    }
+
+    /// The bonus for every offset in `min_offset..max_offset`.
+    fn ratings(
+        &self,
+        min_offset: TimeDelta,
+        max_offset: TimeDelta,
+    ) -> RatingIterator<impl Iterator<Item = RatingSegment>> {
+        let mut builder = DifferentialRatingBufferBuilder::new(min_offset, max_offset);
+        for (offset, slope_change) in self.slope_changes() {
+            builder.add_segment(offset, slope_change);
+        }
+        builder.extend_to_end();
+        builder.build().into_rating_iter()
+    }
+}
+
+/// The part of the cost of a split which depends on how far the offset jumps (see
+/// `Aligner::align_with_splits`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SplitJumpCost {
+    /// Added to the split penalty for every unit the offset changes.
+    pub penalty_per_unit: RatingDelta,
+
+    /// Splits which change the offset by less than this are not allowed.
+    pub min_jump: TimeDelta,
+}
+
+impl Default for SplitJumpCost {
+    /// Every split costs the same.
+    fn default() -> SplitJumpCost {
+        SplitJumpCost {
+            penalty_per_unit: RatingDelta::zero(),
+            min_jump: TimeDelta::zero(),
+        }
+    }
+}
+
+impl SplitJumpCost {
+    fn is_zero(&self) -> bool {
+        self.penalty_per_unit == RatingDelta::zero() && self.min_jump == TimeDelta::zero()
+    }
+}
+
 /// The "main" structure which holds the infomation needed to align the subtitles to each other.
 pub struct Aligner;
 
@@ -50,61 +130,78 @@ impl Aligner {
         (ref_start - in_end, ref_end - in_start)
     }
 
+    /// Intersects the offsets for which the spans can overlap (see `get_offsets_bounds`) with
+    /// the (inclusive) `offset_window`. Returns `None` if the intersection is empty.
+    pub fn get_windowed_offsets_bounds(
+        ref_spans: &[TimeSpan],
+        in_spans: &[TimeSpan],
+        offset_window: Option<(TimeDelta, TimeDelta)>,
+    ) -> Option<(TimeDelta, TimeDelta)> {
+        let (min_offset, max_offset) = Self::get_offsets_bounds(ref_spans, in_spans);
+
+        let (min_offset, max_offset) = match offset_window {
+            None => (min_offset, max_offset),
+            Some((window_min, window_max)) => (max(min_offset, window_min), min(max_offset, window_max)),
+        };
+
+        if min_offset <= max_offset {
+            Some((min_offset, max_offset))
+        } else {
+            None
+        }
+    }
+
+    /// The (inclusive) `offset_bounds` have to be a subset of `get_offsets_bounds()`. The
+    /// bonus of `prior` is added to the rating of every offset.
     pub fn align_constant_delta_bucket_sort(
         ref_spans: &[TimeSpan],
+        weights: &SpanWeights,
         in_spans: &[TimeSpan],
         score_fn: impl Fn(TimeDelta, TimeDelta) -> f64 + Copy,
+        offset_bounds: (TimeDelta, TimeDelta),
+        prior: Option<&OffsetPrior>,
     ) -> (TimeDelta, Rating) {
-        let (min_offset, max_offset) = Self::get_offsets_bounds(ref_spans, in_spans);
+        let (min_offset, max_offset) = offset_bounds;
+        let is_windowed = offset_bounds != Self::get_offsets_bounds(ref_spans, in_spans);
 
         let len: usize = (max_offset - min_offset).as_i64().try_into().unwrap();
 
         //let ta = std::time::Instant::now();
         let mut deltas: Vec<RatingDeltaDelta> = vec![RatingDeltaDelta::zero(); len + 1];
 
+        // jumps before the window are folded into the state "one step before the window"
+        let mut delta: RatingDelta = RatingDelta::zero();
+        let mut rating: Rating = Rating::zero();
+
+        let mut accum = |idx: TimeDelta, x: RatingDeltaDelta| {
+            if idx < min_offset {
+                rating = Rating::add_mul(rating, x, min_offset - TimeDelta::one() - idx);
+                delta += x;
+            } else if idx <= max_offset {
+                let idx: usize = (idx - min_offset).as_i64().try_into().unwrap();
+                deltas[idx] += x;
+            }
+        };
+
         //let tb = std::time::Instant::now();
-        for reference_ts in ref_spans {
-            for incorrect_ts in in_spans {
+        for (ref_nr, reference_ts) in ref_spans.iter().enumerate() {
+            for (in_nr, incorrect_ts) in in_spans.iter().enumerate() {
+                let weight = weights.get(ref_nr, in_nr);
                 let rating_delta_delta: RatingDeltaDelta =
-                    RatingDelta::compute_rating_delta(incorrect_ts.len(), reference_ts.len(), score_fn);
-
-                #[inline(always)]
-                fn accum(d: &mut [RatingDeltaDelta], idx: TimeDelta, x: RatingDeltaDelta, sigma_min: TimeDelta) {
-                    let idx: usize = (idx - sigma_min).as_i64().try_into().unwrap();
-                    d[idx] += x;
-                }
+                    RatingDelta::compute_rating_delta(incorrect_ts.len(), reference_ts.len(), weight, score_fn);
 
-                accum(
-                    &mut deltas,
-                    reference_ts.start() - incorrect_ts.end(),
-                    rating_delta_delta,
-                    min_offset,
-                );
-                accum(
-                    &mut deltas,
-                    reference_ts.end() - incorrect_ts.end(),
-                    -rating_delta_delta,
-                    min_offset,
-                );
-                accum(
-                    &mut deltas,
-                    reference_ts.start() - incorrect_ts.start(),
-                    -rating_delta_delta,
-                    min_offset,
-                );
-                accum(
-                    &mut deltas,
-                    reference_ts.end() - incorrect_ts.start(),
-                    rating_delta_delta,
-                    min_offset,
-                );
+                accum(reference_ts.start() - incorrect_ts.end(), rating_delta_delta);
+                accum(reference_ts.end() - incorrect_ts.end(), -rating_delta_delta);
+                accum(reference_ts.start() - incorrect_ts.start(), -rating_delta_delta);
+                accum(reference_ts.end() - incorrect_ts.start(), rating_delta_delta);
             }
         }
+        for (offset, slope_change) in prior.iter().flat_map(|prior| prior.slope_changes()) {
+            accum(offset, slope_change);
+        }
         //let tc = std::time::Instant::now();
 
         // compute maximum rating
-        let mut delta: RatingDelta = RatingDelta::zero();
-        let mut rating: Rating = Rating::zero();
         let mut maximum: (Rating, TimeDelta) = (Rating::zero(), min_offset);
         //let mut nonzero: i64 = 0;
         for (sigma, jump_value) in deltas.into_iter().enumerate() {
@@ -130,33 +227,49 @@ impl Aligner {
             (self.list.len() * self.reference.len() * 4) as f64 / len as f64 * 100.0
         );*/
 
-        assert_eq!(rating, 0);
+        // the prior might reach beyond the bounds
+        if !is_windowed && prior.is_none() {
+            assert_eq!(rating, 0);
+        }
 
         (maximum.1, maximum.0)
     }
 
+    /// The (inclusive) `offset_bounds` have to be a subset of `get_offsets_bounds()`. The
+    /// bonus of `prior` is added to the rating of every offset.
     pub fn align_constant_delta(
         ref_spans: &[TimeSpan],
+        weights: &SpanWeights,
         in_spans: &[TimeSpan],
         score_fn: impl Fn(TimeDelta, TimeDelta) -> f64 + Copy,
+        offset_bounds: (TimeDelta, TimeDelta),
+        prior: Option<&OffsetPrior>,
     ) -> (TimeDelta, Rating) {
-        let (min_offset, max_offset) = Self::get_offsets_bounds(ref_spans, in_spans);
+        let (min_offset, max_offset) = offset_bounds;
 
         let num_slots: usize = TryInto::<usize>::try_into((max_offset - min_offset).as_i64()).unwrap();
         let num_entries: usize = in_spans.len() * ref_spans.len() * 4;
 
         if num_entries as f64 > num_slots as f64 * 0.1 {
-            Self::align_constant_delta_bucket_sort(ref_spans, in_spans, score_fn)
+            Self::align_constant_delta_bucket_sort(ref_spans, weights, in_spans, score_fn, offset_bounds, prior)
         } else {
-            Self::align_constant_delta_merge_sort(ref_spans, in_spans, score_fn)
+            Self::align_constant_delta_merge_sort(ref_spans, weights, in_spans, score_fn, offset_bounds, prior)
         }
     }
 
+    /// The (inclusive) `offset_bounds` have to be a subset of `get_offsets_bounds()`. The
+    /// bonus of `prior` is added to the rating of every offset.
     pub fn align_constant_delta_merge_sort(
         ref_spans: &[TimeSpan],
+        weights: &SpanWeights,
         in_spans: &[TimeSpan],
         score_fn: impl Fn(TimeDelta, TimeDelta) -> f64 + Copy,
+        offset_bounds: (TimeDelta, TimeDelta),
+        prior: Option<&OffsetPrior>,
     ) -> (TimeDelta, Rating) {
+        let (min_offset, max_offset) = offset_bounds;
+        let is_windowed = offset_bounds != Self::get_offsets_bounds(ref_spans, in_spans);
+
         #[derive(PartialEq, Eq, Clone)]
         struct DeltaCorrect {
             rating: RatingDeltaDelta,
@@ -173,13 +286,13 @@ impl Aligner {
 
         let mut delta_corrects: Vec<OrderedDeltaCorrect> = Vec::new();
 
-        for incorrect_ts in in_spans {
+        for (in_nr, incorrect_ts) in in_spans.iter().enumerate() {
             let mut rise_ordered_delta_corrects: OrderedDeltaCorrect = OrderedDeltaCorrect::new();
             let mut up_ordered_delta_corrects: OrderedDeltaCorrect = OrderedDeltaCorrect::new();
             let mut fall_ordered_delta_corrects: OrderedDeltaCorrect = OrderedDeltaCorrect::new();
             let mut down_ordered_delta_corrects: OrderedDeltaCorrect = OrderedDeltaCorrect::new();
 
-            for reference_ts in ref_spans {
+            for (ref_nr, reference_ts) in ref_spans.iter().enumerate() {
                 let rise_time;
                 let up_time;
                 let fall_time;
@@ -197,8 +310,12 @@ impl Aligner {
                     down_time = reference_ts.end();
                 }
 
-                let rating_delta_delta: RatingDeltaDelta =
-                    RatingDelta::compute_rating_delta(incorrect_ts.len(), reference_ts.len(), score_fn);
+                let rating_delta_delta: RatingDeltaDelta = RatingDelta::compute_rating_delta(
+                    incorrect_ts.len(),
+                    reference_ts.len(),
+                    weights.get(ref_nr, in_nr),
+                    score_fn,
+                );
 
                 rise_ordered_delta_corrects
                     .push(DeltaCorrect::new(rating_delta_delta, rise_time - incorrect_ts.start()));
@@ -215,6 +332,16 @@ impl Aligner {
             delta_corrects.push(down_ordered_delta_corrects);
         }
 
+        if let Some(prior) = prior {
+            delta_corrects.push(
+                prior
+                    .slope_changes()
+                    .iter()
+                    .map(|&(offset, slope_change)| DeltaCorrect::new(slope_change, offset))
+                    .collect(),
+            );
+        }
+
         // test if all delta correct arrays are sorted (should be true)
         /*for dc in &delta_corrects {
             for (a, b) in dc.iter().zip(dc.iter().skip(1)) {
@@ -268,6 +395,7 @@ impl Aligner {
             }
 
             let mut heap = BinaryHeap::new();
+            let delta_correct_count: usize = delta_corrects.iter().map(Vec::len).sum();
 
             for (heap_id, data) in delta_corrects.iter_mut().enumerate() {
                 let last_elem: DeltaCorrect = data
@@ -279,7 +407,7 @@ impl Aligner {
                 });
             }
 
-            all_delta_corrects = Vec::with_capacity(4 * delta_corrects.len());
+            all_delta_corrects = Vec::with_capacity(delta_correct_count);
 
             loop {
                 let max_heap_elem: MaxHeapInfo;
@@ -301,7 +429,7 @@ impl Aligner {
                 }
             }
 
-            assert_eq!(all_delta_corrects.len(), 4 * delta_corrects.len());
+            assert_eq!(all_delta_corrects.len(), delta_correct_count);
             sorted_delta_corrects_iter = all_delta_corrects.iter().rev();
 
             first_delta_correct = all_delta_corrects
@@ -310,32 +438,68 @@ impl Aligner {
                 .expect("delta corrects should have at least one element");
         }
 
-        // compute maximum rating
+        // compute maximum rating (delta corrects before the window only change the
+        // start state, delta corrects after the window can be ignored)
         let mut delta: RatingDelta = RatingDelta::zero();
         let mut rating: Rating = Rating::zero();
-        let mut maximum: (Rating, TimeDelta) = (Rating::zero(), first_delta_correct.time);
-        for (delta_correct, next_delta_correct) in sorted_delta_corrects_iter
-            .clone()
-            .zip(sorted_delta_corrects_iter.skip(1))
-        {
-            delta += delta_correct.rating;
-            rating = Rating::add_mul(rating, delta, next_delta_correct.time - delta_correct.time);
-            if rating > maximum.0 {
-                maximum = (rating, next_delta_correct.time);
+        let mut time: TimeDelta = max(min_offset, first_delta_correct.time);
+        let mut maximum: (Rating, TimeDelta) = (Rating::zero(), time);
+        for delta_correct in sorted_delta_corrects_iter {
+            if delta_correct.time > max_offset {
+                break;
             }
+
+            if delta_correct.time > time {
+                // all delta corrects up to "time" are applied
+                if rating > maximum.0 {
+                    maximum = (rating, time);
+                }
+                rating = Rating::add_mul(rating, delta, delta_correct.time - time);
+                time = delta_correct.time;
+            } else {
+                rating = Rating::add_mul(rating, delta_correct.rating, time - delta_correct.time);
+            }
+
+            delta += delta_correct.rating;
+        }
+
+        if rating > maximum.0 {
+            maximum = (rating, time);
+        }
+
+        rating = Rating::add_mul(rating, delta, max_offset - time);
+        if rating > maximum.0 {
+            maximum = (rating, max_offset);
         }
 
-        assert_eq!(rating, 0);
+        // the prior might reach beyond the bounds
+        if !is_windowed && prior.is_none() {
+            assert_eq!(rating, 0);
+        }
 
         (maximum.1, maximum.0)
     }
 
+    /// The offsets are restricted by `constraints`. The intersection of the window with
+    /// `get_offsets_bounds()` must not be empty if no span ranges are given.
+    ///
+    /// Span ranges are soft in the sense that spans which can not all be placed in their
+    /// ranges (e.g. because the spans would have to swap their order) are still aligned.
+    ///
+    /// The rating of every overlap is scaled by the weight of the pair of spans (see
+    /// `SpanWeights`). `split_penalties` contains the penalty for a split between the spans `i`
+    /// and `i + 1` (one for each pair of consecutive spans), to which `jump_cost` adds a
+    /// penalty depending on the difference of their offsets.
+    #[allow(clippy::too_many_arguments)]
     pub fn align_with_splits(
         ref_spans: &[TimeSpan],
+        weights: &SpanWeights,
         in_spans: &[TimeSpan],
-        split_penalty: RatingDelta,
+        split_penalties: &[RatingDelta],
+        jump_cost: SplitJumpCost,
         speed_optimization_opt: Option<f64>,
         score_fn: impl Fn(TimeDelta, TimeDelta) -> f64 + Copy,
+        constraints: &OffsetConstraints,
         mut progress_handler: impl ProgressHandler,
     ) -> (Vec<TimeDelta>, Rating) {
         // For each segment the full rating can only be 1. So the maximum rating
@@ -346,20 +510,69 @@ impl Aligner {
 
         assert!(!in_spans.is_empty());
         assert!(!ref_spans.is_empty());
+        assert_eq!(split_penalties.len(), in_spans.len() - 1);
 
         progress_handler.init(in_spans.len() as i64);
 
         let speed_optimization = speed_optimization_opt.unwrap_or(0.0);
 
-        let (min_offset, max_offset) = Self::get_offsets_bounds(ref_spans, in_spans);
+        assert!(constraints.span_ranges.is_empty() || constraints.span_ranges.len() == in_spans.len());
+        let span_range = |span_nr: usize| constraints.span_ranges.get(span_nr).cloned().flatten();
+
+        // the spans with ranges might be placed where no span overlaps the reference
+        let (mut min_offset, mut max_offset) = Self::get_offsets_bounds(ref_spans, in_spans);
+        for &(range_min, range_max) in constraints.span_ranges.iter().flatten() {
+            assert!(range_min <= range_max);
+            min_offset = min(min_offset, range_min);
+            max_offset = max(max_offset, range_max);
+        }
         let (min_offset, max_offset) = (min_offset - TimeDelta::one(), max_offset + TimeDelta::one());
+        let (min_offset, max_offset) = match constraints.window {
+            None => (min_offset, max_offset),
+            Some((window_min, window_max)) => (
+                max(min_offset, window_min),
+                min(max_offset, window_max + TimeDelta::one()),
+            ),
+        };
+        assert!(min_offset < max_offset);
+
+        // Every span placed inside its range gets this bonus. It is larger than any
+        // possible sum of ratings, so violating a range never pays off (as long as all
+        // ranges can be satisfied at the same time). A bonus instead of a penalty keeps all
+        // ratings positive, which the left-to-right maximum assumes.
+        let range_count = constraints.span_ranges.iter().flatten().count() as i64;
+        let range_bonus: Rating = i64::MAX / (4 * (range_count + 1));
+
+        let span_ratings = |span_nr: usize| -> RatingBuffer {
+            let rating_buffer = Self::single_span_ratings(
+                ref_spans,
+                weights,
+                in_spans[span_nr],
+                span_nr,
+                score_fn,
+                min_offset,
+                max_offset,
+            )
+            .save();
+            let rating_buffer = match constraints.prior {
+                None => rating_buffer,
+                Some(prior) => add_rating_iterators(rating_buffer.into_iter(), prior.ratings(min_offset, max_offset))
+                    .discard_start_times()
+                    .save(),
+            };
+            match span_range(span_nr) {
+                None => rating_buffer,
+                Some((range_min, range_max)) => {
+                    rating_buffer.with_rating_added_in(range_min, range_max + TimeDelta::one(), range_bonus)
+                }
+            }
+        };
 
         // these buffers save the offsets of a subtitle line dependent on the offset of the next line,
         //  -> this allows to compute the final corrected line offsets
         let mut offset_buffers: Vec<OffsetBuffer> = Vec::new();
 
-        let mut culmulative_rating_buffer: RatingBuffer =
-            Self::single_span_ratings(ref_spans, in_spans[0], score_fn, min_offset, max_offset).save();
+        let mut culmulative_rating_buffer: RatingBuffer = span_ratings(0);
 
         progress_handler.inc();
 
@@ -371,21 +584,15 @@ impl Aligner {
 
             let span_distance = incorrect_span.start - last_incorrect_span.end;
 
-            assert!(span_distance >= TimeDelta::zero()); // otherwise shift_simple/extend_to creates a zero-length segment
-            assert!(culmulative_rating_buffer.first_end_point().unwrap() - min_offset > span_distance);
+            assert!(span_distance >= TimeDelta::zero());
 
-            let best_split_offsets = culmulative_rating_buffer
-                .iter()
-                .add_rating(-split_penalty)
-                .shift_simple(-span_distance)
-                //.clamp_end(self.get_max_offset())
-                .extend_to(max_offset)
-                .annotate_with_segment_start_points()
-                .annotate_with_offset_info(|offset| offset + span_distance)
-                .left_to_right_maximum()
-                .discard_start_times()
-                .simplify()
-                .discard_start_times();
+            let best_split_offsets = Self::best_split_offsets(
+                &culmulative_rating_buffer,
+                split_penalties[line_nr],
+                jump_cost,
+                span_distance,
+                max_offset,
+            );
 
             let nosplit_offsets = culmulative_rating_buffer
                 .iter()
@@ -395,14 +602,13 @@ impl Aligner {
             //.simplify()
             //.discard_start_times();
 
-            let single_span_ratings =
-                Self::single_span_ratings(ref_spans, incorrect_span, score_fn, min_offset, max_offset).save();
+            let single_span_ratings = span_ratings(line_nr + 1);
 
             let progress_factor = (line_nr + 1) as f64 / in_spans.len() as f64;
             let epsilon = Rating::convert_from_f64(speed_optimization * 0.05 * (progress_factor * 0.8 + 0.2));
 
             let combined_maximum_buffer: SeparateDualBuffer =
-                combined_maximum_of_dual_iterators(nosplit_offsets, best_split_offsets)
+                combined_maximum_of_dual_iterators(nosplit_offsets, best_split_offsets.into_iter())
                     .discard_start_times()
                     .add_ratings_from(single_span_ratings.iter())
                     .discard_start_times()
@@ -458,7 +664,355 @@ impl Aligner {
 
         progress_handler.finish();
 
-        (result_deltas, total_rating)
+        let satisfied_range_count = result_deltas
+            .iter()
+            .enumerate()
+            .filter(|&(span_nr, &delta)| {
+                span_range(span_nr).is_some_and(|(range_min, range_max)| range_min <= delta && delta <= range_max)
+            })
+            .count();
+
+        (
+            result_deltas,
+            Rating::add_mul_usize(total_rating, -range_bonus, satisfied_range_count),
+        )
+    }
+
+    /// Returns the best rating of the last span for every offset of the next span if the
+    /// offset changes between them (together with the offset of the last span).
+    /// `rating_buffer` holds the ratings for the offsets of the last span.
+    ///
+    /// A split from the offset `o'` to `o` costs `split_penalty` plus the per-unit penalty
+    /// of `jump_cost` times `|o - o'|`, and it is only allowed if `|o - o'|` is at least
+    /// the minimal jump.
+    fn best_split_offsets(
+        rating_buffer: &RatingBuffer,
+        split_penalty: RatingDelta,
+        jump_cost: SplitJumpCost,
+        span_distance: TimeDelta,
+        max_offset: TimeDelta,
+    ) -> DualBuffer {
+        // the last line may have any offset up to "offset + span_distance" if the current
+        // line has "offset" (otherwise the lines would swap their order)
+        if jump_cost.is_zero() {
+            return rating_buffer
+                .iter()
+                .add_rating(-split_penalty)
+                .annotate_with_segment_start_points()
+                .annotate_with_offset_info(|offset| offset)
+                .left_to_right_maximum()
+                .discard_start_times()
+                .shift_clamped(span_distance, max_offset)
+                .simplify()
+                .discard_start_times()
+                .save();
+        }
+
+        let start = rating_buffer.start();
+        let SplitJumpCost {
+            penalty_per_unit,
+            min_jump,
+        } = jump_cost;
+
+        // The jump costs are relative to `start`. For an earlier offset `o' <= o - min_jump`
+        // of the last line, `rating(o') + penalty_per_unit * (o' - start)` is maximized from
+        // left to right (these ratings are still positive).
+        let earlier_offsets = rating_buffer
+            .iter()
+            .add_linear_rating(penalty_per_unit, start)
+            .annotate_with_segment_start_points()
+            .annotate_with_offset_info(|offset| offset)
+            .left_to_right_maximum()
+            .discard_start_times()
+            .shift_clamped(-min_jump, max_offset)
+            .add_linear_rating(-split_penalty, -penalty_per_unit, start);
+
+        // a later offset has to lie in `o + min_jump..=o + span_distance`
+        if span_distance < min_jump {
+            return earlier_offsets.simplify().discard_start_times().save();
+        }
+
+        // no later offset is left near the end, so these offsets get a rating which never wins
+        let invalid_rating: Rating = -(i64::MAX / 4);
+        let later_offsets = rating_buffer
+            .iter()
+            .add_linear_rating(-penalty_per_unit, start)
+            .annotate_with_segment_start_points()
+            .annotate_with_offset_info(|offset| offset)
+            .discard_start_times()
+            .window_maximum(span_distance - min_jump, max_offset)
+            .into_iter()
+            .shift_with_end_rating(min_jump, max_offset, Some(invalid_rating))
+            .add_linear_rating(-split_penalty, penalty_per_unit, start);
+
+        combined_maximum_of_dual_iterators(earlier_offsets, later_offsets)
+            .discard_start_times()
+            .simplify()
+            .discard_start_times()
+            .save()
+    }
+
+    /// Like `align_with_splits`, but every run of consecutive spans which share an offset may
+    /// additionally be scaled by one of the `scale_factors`: a span with the scale factor `s` and
+    /// the offset `o` is moved to `in_span.with_scaled_start(s) + o`. Changing the scale factor
+    /// costs the split penalty (of the pair of spans) like changing the offset.
+    ///
+    /// `span_ranges` is either empty or contains an optional (inclusive) range for the resulting
+    /// delta `in_span.with_scaled_start(s).start() + o - in_span.start()` of each span. Like in
+    /// `align_with_splits`, these ranges are only violated if they contradict each other.
+    ///
+    /// Returns the index of the scale factor and the offset of every span.
+    #[allow(clippy::too_many_arguments)]
+    pub fn align_with_scaled_splits(
+        ref_spans: &[TimeSpan],
+        weights: &SpanWeights,
+        in_spans: &[TimeSpan],
+        scale_factors: &[f64],
+        split_penalties: &[RatingDelta],
+        speed_optimization_opt: Option<f64>,
+        score_fn: impl Fn(TimeDelta, TimeDelta) -> f64 + Copy,
+        span_ranges: &[Option<(TimeDelta, TimeDelta)>],
+        mut progress_handler: impl ProgressHandler,
+    ) -> (Vec<(usize, TimeDelta)>, Rating) {
+        assert!(!in_spans.is_empty());
+        assert!(!ref_spans.is_empty());
+        assert!(!scale_factors.is_empty());
+        assert!(span_ranges.is_empty() || span_ranges.len() == in_spans.len());
+        assert_eq!(split_penalties.len(), in_spans.len() - 1);
+
+        progress_handler.init(in_spans.len() as i64);
+
+        let speed_optimization = speed_optimization_opt.unwrap_or(0.0);
+
+        let scaled_spans: Vec<Vec<TimeSpan>> = scale_factors
+            .iter()
+            .map(|&scale_factor| {
+                in_spans
+                    .iter()
+                    .map(|span| span.with_scaled_start(scale_factor))
+                    .collect()
+            })
+            .collect();
+
+        // the offset range for a delta range depends on the scale factor
+        let offset_range = |scale_nr: usize, span_nr: usize| -> Option<(TimeDelta, TimeDelta)> {
+            let (range_min, range_max) = span_ranges.get(span_nr).cloned().flatten()?;
+            let scale_delta = in_spans[span_nr].start() - scaled_spans[scale_nr][span_nr].start();
+            Some((range_min + scale_delta, range_max + scale_delta))
+        };
+
+        // all scale factors share the same offsets, so the rating buffers can be combined
+        let (mut min_offset, mut max_offset) = Self::get_offsets_bounds(ref_spans, &scaled_spans[0]);
+        for (scale_nr, spans) in scaled_spans.iter().enumerate() {
+            let (scale_min_offset, scale_max_offset) = Self::get_offsets_bounds(ref_spans, spans);
+            min_offset = min(min_offset, scale_min_offset);
+            max_offset = max(max_offset, scale_max_offset);
+            for (range_min, range_max) in (0..in_spans.len()).filter_map(|span_nr| offset_range(scale_nr, span_nr)) {
+                assert!(range_min <= range_max);
+                min_offset = min(min_offset, range_min);
+                max_offset = max(max_offset, range_max);
+            }
+        }
+        let (min_offset, max_offset) = (min_offset - TimeDelta::one(), max_offset + TimeDelta::one());
+
+        // The offset buffers have to remember the scale factor of the previous span too. So
+        // the offsets of scale factor `i` are stored as `offset + i * scale_stride`.
+        let scale_stride: TimeDelta = max_offset - min_offset + TimeDelta::one();
+        let encode = move |scale_nr: usize, offset: TimeDelta| offset + scale_stride * scale_nr as i64;
+        let decode = move |encoded_offset: TimeDelta| -> (usize, TimeDelta) {
+            let scale_nr = (encoded_offset - min_offset).as_i64().div_euclid(scale_stride.as_i64());
+            (scale_nr as usize, encoded_offset - scale_stride * scale_nr)
+        };
+
+        // see `align_with_splits()`
+        let range_count = span_ranges.iter().flatten().count() as i64;
+        let range_bonus: Rating = i64::MAX / (4 * (range_count + 1));
+
+        let span_ratings = |scale_nr: usize, span_nr: usize| -> RatingBuffer {
+            let rating_buffer = Self::single_span_ratings(
+                ref_spans,
+                weights,
+                scaled_spans[scale_nr][span_nr],
+                span_nr,
+                score_fn,
+                min_offset,
+                max_offset,
+            )
+            .save();
+            match offset_range(scale_nr, span_nr) {
+                None => rating_buffer,
+                Some((range_min, range_max)) => {
+                    rating_buffer.with_rating_added_in(range_min, range_max + TimeDelta::one(), range_bonus)
+                }
+            }
+        };
+
+        // one buffer for each scale factor
+        let mut culmulative_rating_buffers: Vec<RatingBuffer> = (0..scale_factors.len())
+            .map(|scale_nr| span_ratings(scale_nr, 0))
+            .collect();
+        let mut offset_buffers: Vec<Vec<OffsetBuffer>> = Vec::new();
+
+        progress_handler.inc();
+
+        for span_nr in 1..in_spans.len() {
+            // the best rating of the previous spans if the previous span has an offset up to `p`
+            let best_split_buffers: Vec<DualBuffer> = culmulative_rating_buffers
+                .iter()
+                .enumerate()
+                .map(|(scale_nr, culmulative_rating_buffer)| {
+                    culmulative_rating_buffer
+                        .iter()
+                        .add_rating(-split_penalties[span_nr - 1])
+                        .annotate_with_segment_start_points()
+                        .annotate_with_offset_info(|offset| encode(scale_nr, offset))
+                        .left_to_right_maximum()
+                        .discard_start_times()
+                        .save()
+                })
+                .collect();
+
+            let progress_factor = span_nr as f64 / in_spans.len() as f64;
+            let epsilon = Rating::convert_from_f64(speed_optimization * 0.05 * (progress_factor * 0.8 + 0.2));
+
+            let mut next_culmulative_rating_buffers = Vec::with_capacity(scale_factors.len());
+            let mut next_offset_buffers = Vec::with_capacity(scale_factors.len());
+
+            for (scale_nr, culmulative_rating_buffer) in culmulative_rating_buffers.iter().enumerate() {
+                let mut best_buffer: DualBuffer = culmulative_rating_buffer
+                    .iter()
+                    .annotate_with_segment_start_points()
+                    .annotate_with_offset_info(|offset| encode(scale_nr, offset))
+                    .discard_start_times()
+                    .save();
+
+                for (last_scale_nr, best_split_buffer) in best_split_buffers.iter().enumerate() {
+                    // the spans must not swap their order (with both scale factors applied)
+                    let span_distance =
+                        scaled_spans[scale_nr][span_nr].start() - scaled_spans[last_scale_nr][span_nr - 1].end();
+
+                    let best_split_offsets = best_split_buffer
+                        .iter()
+                        .shift_clamped(span_distance, max_offset)
+                        .simplify()
+                        .discard_start_times();
+
+                    best_buffer = combined_maximum_of_dual_iterators(best_buffer.into_iter(), best_split_offsets)
+                        .discard_start_times()
+                        .save();
+                }
+
+                let combined_maximum_buffer: SeparateDualBuffer = best_buffer
+                    .into_iter()
+                    .add_ratings_from(span_ratings(scale_nr, span_nr).iter())
+                    .discard_start_times()
+                    .save_separate(epsilon);
+
+                next_culmulative_rating_buffers.push(combined_maximum_buffer.rating_buffer);
+                next_offset_buffers.push(combined_maximum_buffer.offset_buffer);
+            }
+
+            culmulative_rating_buffers = next_culmulative_rating_buffers;
+            offset_buffers.push(next_offset_buffers);
+
+            progress_handler.inc();
+        }
+
+        // ------------------------------------------------------------------------------
+        // Extract the best scale factor and offset for each span from the offset buffers
+
+        // the first scale factor wins ties
+        let (mut scale_nr, mut total_rating, mut span_offset) = (0, Rating::zero(), min_offset);
+        for (candidate_scale_nr, culmulative_rating_buffer) in culmulative_rating_buffers.iter().enumerate() {
+            let (rating, offset) = culmulative_rating_buffer.maximum();
+            if candidate_scale_nr == 0 || rating > total_rating {
+                (scale_nr, total_rating, span_offset) = (candidate_scale_nr, rating, offset);
+            }
+        }
+
+        let mut result = vec![(scale_nr, span_offset)];
+        for scale_offset_buffers in offset_buffers.iter().rev() {
+            (scale_nr, span_offset) = decode(scale_offset_buffers[scale_nr].get_offset_at(span_offset));
+            result.push((scale_nr, span_offset));
+        }
+
+        // the offsets were inserted back-to-front
+        result.reverse();
+
+        progress_handler.finish();
+
+        let satisfied_range_count = result
+            .iter()
+            .enumerate()
+            .filter(|&(span_nr, &(scale_nr, offset))| {
+                offset_range(scale_nr, span_nr)
+                    .is_some_and(|(range_min, range_max)| range_min <= offset && offset <= range_max)
+            })
+            .count();
+
+        (
+            result,
+            Rating::add_mul_usize(total_rating, -range_bonus, satisfied_range_count),
+        )
+    }
+
+    /// Returns how much better the rating of `in_span` at `offset` is than its best rating at an
+    /// alternative offset. Alternatives have to be at least the span length away from `offset` (so
+    /// the span is matched to other reference spans) and inside the (inclusive) `offset_window`.
+    /// Not overlapping any reference span (rating zero) is always an alternative. `in_nr` is the
+    /// index of `in_span` in the weights.
+    pub fn get_line_confidence(
+        ref_spans: &[TimeSpan],
+        weights: &SpanWeights,
+        in_span: TimeSpan,
+        in_nr: usize,
+        offset: TimeDelta,
+        score_fn: impl Fn(TimeDelta, TimeDelta) -> f64 + Copy,
+        offset_window: Option<(TimeDelta, TimeDelta)>,
+    ) -> Rating {
+        assert!(!ref_spans.is_empty());
+
+        if in_span.is_empty() {
+            return Rating::zero();
+        }
+
+        let (min_offset, max_offset) = Self::get_offsets_bounds(ref_spans, &[in_span]);
+        let (min_offset, max_offset) = (min_offset - TimeDelta::one(), max_offset + TimeDelta::one());
+
+        let (window_min, window_max) = offset_window.unwrap_or((min_offset, max_offset));
+        let alternative_ranges = [
+            (window_min, offset - in_span.len()),
+            (offset + in_span.len(), window_max),
+        ];
+
+        let mut rating = Rating::zero();
+        let mut best_alternative_rating = Rating::zero();
+
+        for segment in Self::single_span_ratings(ref_spans, weights, in_span, in_nr, score_fn, min_offset, max_offset)
+            .annotate_with_segment_start_points()
+            .into_iter()
+        {
+            let rating_at =
+                |p: TimeDelta| Rating::add_mul(segment.data.rating, segment.data.delta, p - segment.span.start);
+            let last_point = segment.span.end - TimeDelta::one();
+
+            if segment.span.start <= offset && offset <= last_point {
+                rating = rating_at(offset);
+            }
+
+            // the rating is linear in a segment -> the maximum is at one of the ends
+            for &(range_start, range_end) in &alternative_ranges {
+                let (range_start, range_end) = (max(range_start, segment.span.start), min(range_end, last_point));
+                if range_start <= range_end {
+                    best_alternative_rating = max(
+                        best_alternative_rating,
+                        max(rating_at(range_start), rating_at(range_end)),
+                    );
+                }
+            }
+        }
+
+        rating - best_alternative_rating
     }
 
     /// Requires "start1 <= start2". Returns the compressed rating vector for
@@ -469,7 +1023,9 @@ impl Aligner {
     /// reference list.
     fn single_span_ratings(
         ref_spans: &[TimeSpan],
+        weights: &SpanWeights,
         in_span: TimeSpan,
+        in_nr: usize,
         score_fn: impl Fn(TimeDelta, TimeDelta) -> f64 + Copy,
         min_offset: TimeDelta,
         max_offset: TimeDelta,
@@ -499,7 +1055,8 @@ impl Aligner {
         let len = ref_spans.len();
         let mut timepoints: Vec<Option<(TimeDelta, RatingDeltaDelta)>> = vec![None; 4 * len];
         for (i, &ref_span) in ref_spans.iter().enumerate() {
-            let rise_delta = RatingDelta::compute_rating_delta(ref_span.len(), in_span.len(), score_fn);
+            let rise_delta =
+                RatingDelta::compute_rating_delta(ref_span.len(), in_span.len(), weights.get(i, in_nr), score_fn);
 
             timepoints[i] = Some((ref_span.start() - in_span.end(), rise_delta));
             timepoints[len + i] = Some((ref_span.end() - in_span.end(), -rise_delta));
@@ -576,6 +1133,11 @@ mod tests {
     use crate::rating_type::RatingExt;
     use crate::segments::RatingFullSegment;
     use crate::tests::get_random_prepared_test_time_spans;
+    use rand::Rng;
+
+    fn span(start: i64, end: i64) -> TimeSpan {
+        TimeSpan::new(TimePoint::from(start), TimePoint::from(end))
+    }
 
     fn get_dummy_spans() -> Vec<TimeSpan> {
         loop {
@@ -599,15 +1161,351 @@ mod tests {
             let (ref_spans, in_spans) = (get_dummy_spans(), get_dummy_spans());
             Aligner::align_with_splits(
                 &ref_spans,
+                &SpanWeights::default(),
+                &in_spans,
+                &vec![RatingDelta::convert_from_f64(0.001); in_spans.len() - 1],
+                SplitJumpCost::default(),
+                None,
+                crate::standard_scoring,
+                &OffsetConstraints::default(),
+                NoProgressHandler,
+            );
+        }
+    }
+
+    /// Returns a random (inclusive) window which intersects the offset bounds of the spans.
+    fn get_random_window(ref_spans: &[TimeSpan], in_spans: &[TimeSpan]) -> (TimeDelta, TimeDelta) {
+        let mut rng = rand::rng();
+        let (min_offset, max_offset) = Aligner::get_offsets_bounds(ref_spans, in_spans);
+        let len = (max_offset - min_offset).as_i64() + 1;
+        let a = min_offset + TimeDelta::from_i64(rng.random_range(0..len));
+        let b = min_offset + TimeDelta::from_i64(rng.random_range(0..len));
+        (min(a, b), max(a, b))
+    }
+
+    #[test]
+    fn align_constant_delta_windowed() {
+        for _ in 0..30 {
+            let (ref_spans, in_spans) = (get_dummy_spans(), get_dummy_spans());
+            let full_bounds = Aligner::get_offsets_bounds(&ref_spans, &in_spans);
+            let window = get_random_window(&ref_spans, &in_spans);
+
+            let (full_delta, full_rating) = Aligner::align_constant_delta_bucket_sort(
+                &ref_spans,
+                &SpanWeights::default(),
+                &in_spans,
+                crate::standard_scoring,
+                full_bounds,
+                None,
+            );
+            let (delta1, rating1) = Aligner::align_constant_delta_bucket_sort(
+                &ref_spans,
+                &SpanWeights::default(),
+                &in_spans,
+                crate::standard_scoring,
+                window,
+                None,
+            );
+            let (delta2, rating2) = Aligner::align_constant_delta_merge_sort(
+                &ref_spans,
+                &SpanWeights::default(),
+                &in_spans,
+                crate::standard_scoring,
+                window,
+                None,
+            );
+
+            assert_eq!(rating1, rating2);
+            assert!(window.0 <= delta1 && delta1 <= window.1);
+            assert!(window.0 <= delta2 && delta2 <= window.1);
+            assert!(rating1 <= full_rating);
+            if window.0 <= full_delta && full_delta <= window.1 {
+                assert_eq!(rating1, full_rating);
+            }
+        }
+    }
+
+    #[test]
+    fn align_with_splits_windowed() {
+        for _ in 0..20 {
+            let (ref_spans, in_spans) = (get_dummy_spans(), get_dummy_spans());
+            let window = get_random_window(&ref_spans, &in_spans);
+
+            let (deltas, _) = Aligner::align_with_splits(
+                &ref_spans,
+                &SpanWeights::default(),
+                &in_spans,
+                &vec![RatingDelta::convert_from_f64(0.001); in_spans.len() - 1],
+                SplitJumpCost::default(),
+                None,
+                crate::standard_scoring,
+                &OffsetConstraints {
+                    window: Some(window),
+                    span_ranges: Vec::new(),
+                    prior: None,
+                },
+                NoProgressHandler,
+            );
+
+            assert_eq!(deltas.len(), in_spans.len());
+            assert!(deltas.iter().all(|&delta| window.0 <= delta && delta <= window.1));
+        }
+    }
+
+    #[test]
+    fn align_with_splits_span_ranges() {
+        let mut rng = rand::rng();
+
+        for _ in 0..20 {
+            let (ref_spans, in_spans) = (get_dummy_spans(), get_dummy_spans());
+            let anchored_span_nr = rng.random_range(0..in_spans.len());
+            let (anchor_offset, _) = get_random_window(&ref_spans, &in_spans);
+
+            let mut span_ranges = vec![None; in_spans.len()];
+            span_ranges[anchored_span_nr] = Some((anchor_offset, anchor_offset));
+
+            let (deltas, rating) = Aligner::align_with_splits(
+                &ref_spans,
+                &SpanWeights::default(),
+                &in_spans,
+                &vec![RatingDelta::convert_from_f64(0.001); in_spans.len() - 1],
+                SplitJumpCost::default(),
+                None,
+                crate::standard_scoring,
+                &OffsetConstraints {
+                    window: None,
+                    span_ranges,
+                    prior: None,
+                },
+                NoProgressHandler,
+            );
+
+            assert_eq!(deltas[anchored_span_nr], anchor_offset);
+            assert!(rating >= Rating::zero());
+            assert!(rating.as_readable_f64() <= in_spans.len() as f64);
+        }
+    }
+
+    #[test]
+    fn align_constant_delta_prior() {
+        let mut rng = rand::rng();
+
+        for _ in 0..30 {
+            let (ref_spans, in_spans) = (get_dummy_spans(), get_dummy_spans());
+            let bounds = Aligner::get_offsets_bounds(&ref_spans, &in_spans);
+            let (center, _) = get_random_window(&ref_spans, &in_spans);
+            let prior = OffsetPrior {
+                center,
+                width: TimeDelta::from_i64(rng.random_range(1..1000)),
+                bonus: Rating::convert_from_f64(rng.random_range(0.0..3.0)),
+            };
+
+            let (delta1, rating1) = Aligner::align_constant_delta_bucket_sort(
+                &ref_spans,
+                &SpanWeights::default(),
+                &in_spans,
+                crate::standard_scoring,
+                bounds,
+                Some(&prior),
+            );
+            let (delta2, rating2) = Aligner::align_constant_delta_merge_sort(
+                &ref_spans,
+                &SpanWeights::default(),
+                &in_spans,
+                crate::standard_scoring,
+                bounds,
+                Some(&prior),
+            );
+            assert_eq!((delta1, rating1), (delta2, rating2));
+
+            // a prior which outweighs all overlaps decides the offset
+            let strong_prior = OffsetPrior {
+                bonus: Rating::convert_from_f64((in_spans.len() + 1) as f64 * 1000.),
+                ..prior
+            };
+            let (delta, _) = Aligner::align_constant_delta(
+                &ref_spans,
+                &SpanWeights::default(),
+                &in_spans,
+                crate::standard_scoring,
+                bounds,
+                Some(&strong_prior),
+            );
+            assert_eq!(delta, center);
+        }
+    }
+
+    #[test]
+    /// Compares `align_with_splits` with a jump cost to a brute-force search over all offsets.
+    fn align_with_splits_jump_cost() {
+        let mut rng = rand::rng();
+        let random_spans = |rng: &mut rand::rngs::ThreadRng, count: usize| -> Vec<TimeSpan> {
+            let mut time = rng.random_range(0..50);
+            (0..count)
+                .map(|_| {
+                    let start = time + rng.random_range(0..30);
+                    time = start + rng.random_range(5..40);
+                    TimeSpan::new(TimePoint::from(start), TimePoint::from(time))
+                })
+                .collect()
+        };
+
+        for _ in 0..20 {
+            let (ref_spans, in_spans) = (random_spans(&mut rng, 6), random_spans(&mut rng, 5));
+            let split_penalty = RatingDelta::convert_from_f64(0.05);
+            let jump_cost = SplitJumpCost {
+                penalty_per_unit: RatingDelta::convert_from_f64(0.002),
+                min_jump: TimeDelta::from_i64(rng.random_range(0..20)),
+            };
+
+            let (deltas, rating) = Aligner::align_with_splits(
+                &ref_spans,
+                &SpanWeights::default(),
+                &in_spans,
+                &vec![split_penalty; in_spans.len() - 1],
+                jump_cost,
+                None,
+                crate::standard_scoring,
+                &OffsetConstraints::default(),
+                NoProgressHandler,
+            );
+
+            let (min_offset, max_offset) = Aligner::get_offsets_bounds(&ref_spans, &in_spans);
+            let (min_offset, max_offset) = (min_offset - TimeDelta::one(), max_offset + TimeDelta::one());
+            let offsets: Vec<TimeDelta> = (min_offset.as_i64()..max_offset.as_i64())
+                .map(TimeDelta::from_i64)
+                .collect();
+
+            // the rating of every span at every offset
+            let span_ratings: Vec<Vec<Rating>> = (0..in_spans.len())
+                .map(|span_nr| {
+                    Aligner::single_span_ratings(
+                        &ref_spans,
+                        &SpanWeights::default(),
+                        in_spans[span_nr],
+                        span_nr,
+                        crate::standard_scoring,
+                        min_offset,
+                        max_offset,
+                    )
+                    .annotate_with_segment_start_points()
+                    .into_iter()
+                    .flat_map(|segment| {
+                        (0..(segment.span.end - segment.span.start).as_i64()).map(move |i| {
+                            Rating::add_mul(segment.data.rating, segment.data.delta, TimeDelta::from_i64(i))
+                        })
+                    })
+                    .collect()
+                })
+                .collect();
+
+            let split_cost = |last_offset: TimeDelta, offset: TimeDelta| -> Option<Rating> {
+                let jump = (offset - last_offset).as_i64().abs();
+                if jump == 0 {
+                    Some(Rating::zero())
+                } else if jump < jump_cost.min_jump.as_i64() {
+                    None
+                } else {
+                    Some(split_penalty + jump_cost.penalty_per_unit * jump)
+                }
+            };
+
+            let mut best: Vec<Rating> = span_ratings[0].clone();
+            for span_nr in 1..in_spans.len() {
+                let span_distance = in_spans[span_nr].start() - in_spans[span_nr - 1].end();
+                best = (0..offsets.len())
+                    .map(|i| {
+                        let best_last = (0..offsets.len())
+                            .filter(|&j| offsets[j] <= offsets[i] + span_distance)
+                            .filter_map(|j| split_cost(offsets[j], offsets[i]).map(|cost| best[j] - cost))
+                            .max()
+                            .unwrap();
+                        best_last + span_ratings[span_nr][i]
+                    })
+                    .collect();
+            }
+            assert_eq!(rating, best.into_iter().max().unwrap());
+
+            // the deltas have to reach this rating
+            let offset_index = |delta: TimeDelta| (delta - min_offset).as_i64() as usize;
+            let deltas_rating = deltas
+                .iter()
+                .enumerate()
+                .map(|(span_nr, &delta)| span_ratings[span_nr][offset_index(delta)])
+                .sum::<Rating>()
+                - deltas
+                    .windows(2)
+                    .map(|pair| split_cost(pair[0], pair[1]).unwrap())
+                    .sum::<Rating>();
+            assert_eq!(deltas_rating, rating);
+        }
+    }
+
+    #[test]
+    fn align_with_scaled_splits_without_scaling() {
+        for _ in 0..20 {
+            let (ref_spans, in_spans) = (get_dummy_spans(), get_dummy_spans());
+            let split_penalties = vec![RatingDelta::convert_from_f64(0.001); in_spans.len() - 1];
+
+            let (deltas, rating) = Aligner::align_with_splits(
+                &ref_spans,
+                &SpanWeights::default(),
                 &in_spans,
-                RatingDelta::convert_from_f64(0.001),
+                &split_penalties,
+                SplitJumpCost::default(),
                 None,
                 crate::standard_scoring,
+                &OffsetConstraints::default(),
                 NoProgressHandler,
             );
+            let (scales_and_offsets, scaled_rating) = Aligner::align_with_scaled_splits(
+                &ref_spans,
+                &SpanWeights::default(),
+                &in_spans,
+                &[1.],
+                &split_penalties,
+                None,
+                crate::standard_scoring,
+                &[],
+                NoProgressHandler,
+            );
+
+            assert_eq!(rating, scaled_rating);
+            assert_eq!(
+                scales_and_offsets,
+                deltas.into_iter().map(|delta| (0, delta)).collect::<Vec<_>>()
+            );
         }
     }
 
+    #[test]
+    fn line_confidence() {
+        let ref_spans = vec![span(0, 100), span(1000, 1050)];
+        let in_span = span(0, 100);
+        let confidence = |offset: i64, window| {
+            Aligner::get_line_confidence(
+                &ref_spans,
+                &SpanWeights::default(),
+                in_span,
+                0,
+                TimeDelta::from_i64(offset),
+                crate::standard_scoring,
+                window,
+            )
+            .as_readable_f64()
+        };
+
+        // the second reference span is a worse (but possible) match
+        assert!((confidence(0, None) - 0.5).abs() < 1e-6);
+
+        // this offset does not overlap anything
+        assert!((confidence(500, None) + 1.0).abs() < 1e-6);
+
+        // the second reference span is outside of the window
+        let window = Some((TimeDelta::from_i64(-200), TimeDelta::from_i64(200)));
+        assert!((confidence(0, window) - 1.0).abs() < 1e-6);
+    }
+
     #[test]
     fn test_single_span_ratings() {
         for _ in 0..30 {
@@ -616,12 +1514,19 @@ mod tests {
             let (min_offset, max_offset) = (min_offset - TimeDelta::one(), max_offset + TimeDelta::one());
 
             for in_span in in_spans {
-                let last: RatingFullSegment =
-                    Aligner::single_span_ratings(&ref_spans, in_span, crate::standard_scoring, min_offset, max_offset)
-                        .annotate_with_segment_start_points()
-                        .into_iter()
-                        .last()
-                        .unwrap();
+                let last: RatingFullSegment = Aligner::single_span_ratings(
+                    &ref_spans,
+                    &SpanWeights::default(),
+                    in_span,
+                    0,
+                    crate::standard_scoring,
+                    min_offset,
+                    max_offset,
+                )
+                .annotate_with_segment_start_points()
+                .into_iter()
+                .last()
+                .unwrap();
                 assert_eq!(last.end_rating(), 0);
                 //assert_eq!(dbg!(last.data.delta), RatingDelta::zero());
             }
@@ -19,25 +19,51 @@
 #[cfg(test)]
 extern crate rand;
 
+mod config;
 mod ilass;
 mod rating_type;
+mod result;
 #[allow(dead_code)]
 mod segments;
 mod time_types;
 mod timespan_ops;
+mod weights;
 
-use crate::ilass::Aligner;
+use crate::config::ExpectedOffset;
+pub use crate::config::{AlignMode, AlignmentConfig, Anchor, ScoreFn, SplitPenaltyCurve};
 pub use crate::ilass::NoProgressHandler;
 pub use crate::ilass::ProgressHandler;
+use crate::ilass::{Aligner, OffsetConstraints, SplitJumpCost};
 use crate::rating_type::{Rating, RatingDelta, RatingExt};
+pub use crate::result::{AlignmentResult, AlignmentSegment, SplitPenaltyCandidate};
 pub use crate::time_types::{TimeDelta, TimePoint, TimeSpan};
 use crate::timespan_ops::prepare_time_spans;
+use crate::weights::{LineTexts, LineWeights};
 use std::cmp::{max, min};
 
 fn denormalize_split_penalty(ref_list_len: usize, in_list_len: usize, split_penalty_normalized: f64) -> RatingDelta {
     RatingDelta::convert_from_f64(min(ref_list_len, in_list_len) as f64 * split_penalty_normalized / 1000.0)
 }
 
+/// Returns the penalty for a split between each pair of consecutive `spans`: the split penalty
+/// scaled by the factor of `curve` at the gap between the spans (overlapping spans have a gap
+/// of zero).
+fn get_split_penalties(
+    ref_list_len: usize,
+    in_list_len: usize,
+    spans: &[TimeSpan],
+    split_penalty_normalized: f64,
+    curve: &SplitPenaltyCurve,
+) -> Vec<RatingDelta> {
+    spans
+        .windows(2)
+        .map(|pair| {
+            let gap = max(pair[1].start() - pair[0].end(), TimeDelta::zero());
+            denormalize_split_penalty(ref_list_len, in_list_len, split_penalty_normalized * curve.factor(gap))
+        })
+        .collect()
+}
+
 pub type Score = f64;
 
 /// This score is 1 for equally length spans and lower the more the spans are unequal in length (use this scoring if you're not sure what to take).
@@ -65,23 +91,76 @@ pub fn align_nosplit(
     reference: &[TimeSpan],
     list: &[TimeSpan],
     score_fn: impl Fn(TimeDelta, TimeDelta) -> f64 + Copy,
-    mut progress_handler: impl ProgressHandler,
+    progress_handler: impl ProgressHandler,
 ) -> (TimeDelta, Score) {
+    let (delta, rating) = align_nosplit_windowed(
+        reference,
+        &LineWeights::default(),
+        list,
+        score_fn,
+        None,
+        None,
+        progress_handler,
+    );
+    (delta, rating.as_readable_f64())
+}
+
+/// Returns the offset in `offset_window` which is nearest to zero.
+fn clamp_to_window(offset_window: Option<(TimeDelta, TimeDelta)>) -> TimeDelta {
+    match offset_window {
+        None => TimeDelta::zero(),
+        Some((window_min, window_max)) => max(window_min, min(window_max, TimeDelta::zero())),
+    }
+}
+
+fn align_nosplit_windowed(
+    reference: &[TimeSpan],
+    weights: &LineWeights,
+    list: &[TimeSpan],
+    score_fn: impl Fn(TimeDelta, TimeDelta) -> f64 + Copy,
+    offset_window: Option<(TimeDelta, TimeDelta)>,
+    expected_offset: Option<ExpectedOffset>,
+    mut progress_handler: impl ProgressHandler,
+) -> (TimeDelta, Rating) {
     progress_handler.init(1);
 
-    let (ref_nonoverlapping, _) = prepare_time_spans(reference);
-    let (list_nonoverlapping, _) = prepare_time_spans(list);
+    let (ref_nonoverlapping, ref_indices) = prepare_time_spans(reference);
+    let (list_nonoverlapping, list_indices) = prepare_time_spans(list);
 
     if list_nonoverlapping.is_empty() || ref_nonoverlapping.is_empty() {
-        return (TimeDelta::zero(), 0.);
+        return (clamp_to_window(offset_window), Rating::zero());
     }
 
+    let weights = weights.for_spans(
+        reference,
+        &ref_indices,
+        ref_nonoverlapping.len(),
+        &list_indices,
+        list_nonoverlapping.len(),
+    );
+
+    // no offset in the window lets the spans overlap -> every offset is equally bad
+    let offset_bounds =
+        match Aligner::get_windowed_offsets_bounds(&ref_nonoverlapping, &list_nonoverlapping, offset_window) {
+            Some(offset_bounds) => offset_bounds,
+            None => return (clamp_to_window(offset_window), Rating::zero()),
+        };
+
     // get deltas for non-overlapping timespans
-    let (delta, score) = Aligner::align_constant_delta(&ref_nonoverlapping, &list_nonoverlapping, score_fn);
+    let (delta, rating) = Aligner::align_constant_delta(
+        &ref_nonoverlapping,
+        &weights,
+        &list_nonoverlapping,
+        score_fn,
+        offset_bounds,
+        expected_offset
+            .map(|expected_offset| expected_offset.prior(list_nonoverlapping.len()))
+            .as_ref(),
+    );
     progress_handler.inc();
     progress_handler.finish();
 
-    (delta, score.as_readable_f64())
+    (delta, rating)
 }
 
 /// Matches an `incorrect` subtitle list to a `reference` subtitle list.
@@ -111,30 +190,635 @@ pub fn align(
     score_fn: impl Fn(TimeDelta, TimeDelta) -> f64 + Copy,
     progress_handler: impl ProgressHandler,
 ) -> (Vec<TimeDelta>, f64) {
+    let (deltas, rating) = align_constrained(
+        reference,
+        &LineWeights::default(),
+        list,
+        split_penalty,
+        &SplitPenaltyCurve::default(),
+        0.,
+        TimeDelta::zero(),
+        speed_optimization,
+        score_fn,
+        &OffsetConstraints::default(),
+        progress_handler,
+    );
+    (deltas, rating.as_readable_f64())
+}
+
+/// Like `align`, but the offsets are restricted by `constraints` (with span ranges
+/// given per line of `list`), the overlaps can be weighted (see
+/// `AlignmentConfig::reference_weights` and `AlignmentConfig::line_texts`) and the split
+/// penalty can depend on the size of the jump (see `AlignmentConfig::split_jump_penalty` and
+/// `AlignmentConfig::min_split_jump`).
+#[allow(clippy::too_many_arguments)]
+fn align_constrained(
+    reference: &[TimeSpan],
+    weights: &LineWeights,
+    list: &[TimeSpan],
+    split_penalty: f64,
+    split_penalty_curve: &SplitPenaltyCurve,
+    split_jump_penalty: f64,
+    min_split_jump: TimeDelta,
+    speed_optimization: Option<f64>,
+    score_fn: impl Fn(TimeDelta, TimeDelta) -> f64 + Copy,
+    constraints: &OffsetConstraints,
+    progress_handler: impl ProgressHandler,
+) -> (Vec<TimeDelta>, Rating) {
     let (list_nonoverlapping, list_indices) = prepare_time_spans(list);
-    let (ref_nonoverlapping, _) = prepare_time_spans(reference);
+    let (ref_nonoverlapping, ref_indices) = prepare_time_spans(reference);
+
+    // every line gets the offset nearest to zero which satisfies the constraints
+    let fallback_deltas = || -> Vec<TimeDelta> {
+        (0..list.len())
+            .map(|line_nr| {
+                let line_range = constraints.span_ranges.get(line_nr).cloned().flatten();
+                clamp_to_window(line_range.or(constraints.window))
+            })
+            .collect()
+    };
 
     if list_nonoverlapping.is_empty() || ref_nonoverlapping.is_empty() {
-        return (vec![TimeDelta::zero(); list.len()], 0.);
+        return (fallback_deltas(), Rating::zero());
+    }
+
+    let has_span_ranges = constraints.span_ranges.iter().any(Option::is_some);
+    if !has_span_ranges
+        && Aligner::get_windowed_offsets_bounds(&ref_nonoverlapping, &list_nonoverlapping, constraints.window).is_none()
+    {
+        return (fallback_deltas(), Rating::zero());
     }
 
-    let nosplit_bonus = denormalize_split_penalty(ref_nonoverlapping.len(), list_nonoverlapping.len(), split_penalty);
+    let span_ranges = get_span_ranges(&constraints.span_ranges, list_nonoverlapping.len(), &list_indices);
+    let weights = weights.for_spans(
+        reference,
+        &ref_indices,
+        ref_nonoverlapping.len(),
+        &list_indices,
+        list_nonoverlapping.len(),
+    );
+
+    let split_penalties = get_split_penalties(
+        ref_nonoverlapping.len(),
+        list_nonoverlapping.len(),
+        &list_nonoverlapping,
+        split_penalty,
+        split_penalty_curve,
+    );
+
+    let jump_cost = SplitJumpCost {
+        penalty_per_unit: denormalize_split_penalty(
+            ref_nonoverlapping.len(),
+            list_nonoverlapping.len(),
+            split_jump_penalty,
+        ),
+        min_jump: min_split_jump,
+    };
 
     // get deltas for non-overlapping timespans
-    let (deltas, score) = Aligner::align_with_splits(
+    let (deltas, rating) = Aligner::align_with_splits(
         &ref_nonoverlapping,
+        &weights,
         &list_nonoverlapping,
-        nosplit_bonus,
+        &split_penalties,
+        jump_cost,
         speed_optimization,
         score_fn,
+        &OffsetConstraints {
+            window: constraints.window,
+            span_ranges,
+            prior: constraints.prior,
+        },
         progress_handler,
     );
 
     // get deltas for overlapping timspan-list
-    (
-        list_indices.into_iter().map(|i| deltas[i]).collect(),
-        score.as_readable_f64(),
-    )
+    (list_indices.into_iter().map(|i| deltas[i]).collect(), rating)
+}
+
+/// Converts the (optional) ranges of the lines to ranges of the non-overlapping spans
+/// (see `prepare_time_spans()`). Returns an empty vector if no line has a range.
+fn get_span_ranges(
+    line_ranges: &[Option<(TimeDelta, TimeDelta)>],
+    span_count: usize,
+    list_indices: &[usize],
+) -> Vec<Option<(TimeDelta, TimeDelta)>> {
+    if line_ranges.iter().all(Option::is_none) {
+        return Vec::new();
+    }
+
+    // lines which were merged into the same non-overlapping span have to share their ranges
+    let mut span_ranges = vec![None; span_count];
+    for (line_nr, line_range) in line_ranges.iter().enumerate() {
+        let span_range = &mut span_ranges[list_indices[line_nr]];
+        *span_range = match (*span_range, *line_range) {
+            (None, line_range) => line_range,
+            (span_range, None) => span_range,
+            (Some((min1, max1)), Some((min2, max2))) if max(min1, min2) <= min(max1, max2) => {
+                Some((max(min1, min2), min(max1, max2)))
+            }
+            // conflicting ranges -> keep the first one
+            (span_range, Some(_)) => span_range,
+        };
+    }
+
+    span_ranges
+}
+
+/// Like `align_constrained`, but each segment may also be scaled by one of the
+/// `scale_factors` (see `Aligner::align_with_scaled_splits`). The line ranges restrict the
+/// deltas of the lines. Returns the delta and the scale factor of every line.
+#[allow(clippy::too_many_arguments)]
+fn align_piecewise_linear(
+    reference: &[TimeSpan],
+    weights: &LineWeights,
+    list: &[TimeSpan],
+    scale_factors: &[f64],
+    split_penalty: f64,
+    split_penalty_curve: &SplitPenaltyCurve,
+    speed_optimization: Option<f64>,
+    score_fn: impl Fn(TimeDelta, TimeDelta) -> f64 + Copy,
+    line_ranges: &[Option<(TimeDelta, TimeDelta)>],
+    progress_handler: impl ProgressHandler,
+) -> (Vec<TimeDelta>, Vec<f64>) {
+    let (list_nonoverlapping, list_indices) = prepare_time_spans(list);
+    let (ref_nonoverlapping, ref_indices) = prepare_time_spans(reference);
+
+    if list_nonoverlapping.is_empty() || ref_nonoverlapping.is_empty() {
+        let deltas = (0..list.len())
+            .map(|line_nr| clamp_to_window(line_ranges.get(line_nr).cloned().flatten()))
+            .collect();
+        return (deltas, vec![1.; list.len()]);
+    }
+
+    let span_ranges = get_span_ranges(line_ranges, list_nonoverlapping.len(), &list_indices);
+    let weights = weights.for_spans(
+        reference,
+        &ref_indices,
+        ref_nonoverlapping.len(),
+        &list_indices,
+        list_nonoverlapping.len(),
+    );
+
+    let split_penalties = get_split_penalties(
+        ref_nonoverlapping.len(),
+        list_nonoverlapping.len(),
+        &list_nonoverlapping,
+        split_penalty,
+        split_penalty_curve,
+    );
+
+    let (scales_and_offsets, _) = Aligner::align_with_scaled_splits(
+        &ref_nonoverlapping,
+        &weights,
+        &list_nonoverlapping,
+        scale_factors,
+        &split_penalties,
+        speed_optimization,
+        score_fn,
+        &span_ranges,
+        progress_handler,
+    );
+
+    // every line gets the scale factor and offset of its non-overlapping span
+    list.iter()
+        .zip(list_indices)
+        .map(|(&line, span_nr)| {
+            let (scale_nr, offset) = scales_and_offsets[span_nr];
+            let scale_factor = scale_factors[scale_nr];
+            (
+                line.with_scaled_start(scale_factor).start() - line.start() + offset,
+                scale_factor,
+            )
+        })
+        .unzip()
+}
+
+/// Evaluates `f` for all `items` (on a thread pool if the `parallel` feature is enabled).
+///
+/// The results are in the same order as the items, so choosing the best result does not depend
+/// on the scheduling of the threads.
+fn map_independent<T: Sync, R: Send>(items: &[T], f: impl Fn(&T) -> R + Sync + Send) -> Vec<R> {
+    #[cfg(feature = "parallel")]
+    {
+        use rayon::prelude::*;
+        items.par_iter().map(f).collect()
+    }
+
+    #[cfg(not(feature = "parallel"))]
+    {
+        items.iter().map(f).collect()
+    }
+}
+
+/// Aligns `list` scaled by each of the `scale_factors` to `reference` in no-split mode (see
+/// `overlap_scoring`). These alignments run on a thread pool if the `parallel` feature is enabled.
+///
+/// Returns the offset and the score for every scaling factor (in the same order).
+pub fn align_nosplit_scaled(
+    reference: &[TimeSpan],
+    list: &[TimeSpan],
+    scale_factors: &[f64],
+) -> Vec<(TimeDelta, Score)> {
+    map_independent(scale_factors, |&scale_factor| {
+        let stretched_list: Vec<TimeSpan> = list.iter().map(|ts| ts.scaled(scale_factor)).collect();
+        align_nosplit(reference, &stretched_list, overlap_scoring, NoProgressHandler)
+    })
+}
+
+/// Tries to find the framerate ratio between `reference` and `list` by aligning
+/// `list` scaled by `1.0` and by each of the `scale_factors` in no-split mode.
+///
+/// Returns the scaling factor with the best overlap (see `overlap_scoring`). On equal
+/// scores, `1.0` and then the earlier scaling factor wins.
+pub fn guess_scale_factor(
+    reference: &[TimeSpan],
+    list: &[TimeSpan],
+    scale_factors: &[f64],
+    mut progress_handler: impl ProgressHandler,
+) -> f64 {
+    progress_handler.init(scale_factors.len() as i64 + 1);
+
+    let candidates: Vec<f64> = std::iter::once(1.).chain(scale_factors.iter().cloned()).collect();
+    let results = align_nosplit_scaled(reference, list, &candidates);
+
+    let (mut opt_scale_factor, mut opt_score) = (1., f64::NEG_INFINITY);
+    for (&scale_factor, &(_, score)) in candidates.iter().zip(&results) {
+        progress_handler.inc();
+
+        if score > opt_score {
+            opt_score = score;
+            opt_scale_factor = scale_factor;
+        }
+    }
+
+    progress_handler.finish();
+
+    opt_scale_factor
+}
+
+/// Number of scaling factors tried in the first (coarse) step of `estimate_scale_factor`.
+const SCALE_SEARCH_COARSE_STEPS: usize = 40;
+
+/// Number of scaling factors tried on each side of the best factor in a refinement step.
+const SCALE_SEARCH_REFINE_STEPS: usize = 4;
+
+/// The refinement stops when the distance between two tried factors is below this value.
+const SCALE_SEARCH_PRECISION: f64 = 1e-6;
+
+/// Estimates an arbitrary linear drift between `reference` and `list` by searching the scaling
+/// factor in `min_scale..=max_scale`. The factors are first tried on an evenly spaced grid, then
+/// the grid is refined around the best factor. Each factor is rated by aligning the scaled
+/// `list` in no-split mode (see `overlap_scoring`).
+///
+/// Returns the best scaling factor together with the offset and the score of the scaled `list`
+/// (the corrected version of a span is `span.scaled(scale) + offset`).
+///
+/// # Panics
+///
+/// This function asserts that `min_scale` is positive and less or equal `max_scale`.
+pub fn estimate_scale_factor(
+    reference: &[TimeSpan],
+    list: &[TimeSpan],
+    min_scale: f64,
+    max_scale: f64,
+    mut progress_handler: impl ProgressHandler,
+) -> (f64, TimeDelta, Score) {
+    assert!(0. < min_scale && min_scale <= max_scale);
+
+    let mut step = (max_scale - min_scale) / SCALE_SEARCH_COARSE_STEPS as f64;
+    let mut refine_count = 0;
+    let mut refined_step = step;
+    while refined_step > SCALE_SEARCH_PRECISION {
+        refined_step /= SCALE_SEARCH_REFINE_STEPS as f64;
+        refine_count += 1;
+    }
+    progress_handler.init((SCALE_SEARCH_COARSE_STEPS + 2 + refine_count * 2 * SCALE_SEARCH_REFINE_STEPS) as i64);
+
+    // the factors are evaluated in batches (which can run in parallel), the earliest
+    // factor wins ties
+    let mut best: (f64, TimeDelta, Score) = (1., TimeDelta::zero(), f64::NEG_INFINITY);
+    let mut try_scale_factors = |scale_factors: Vec<f64>| {
+        let results = align_nosplit_scaled(reference, list, &scale_factors);
+        for (scale_factor, (offset, score)) in scale_factors.into_iter().zip(results) {
+            progress_handler.inc();
+            if score > best.2 {
+                best = (scale_factor, offset, score);
+            }
+        }
+        best
+    };
+
+    // no scaling wins ties
+    let coarse_scale_factors = (0..=SCALE_SEARCH_COARSE_STEPS).map(|i| min_scale + step * i as f64);
+    let mut current_best = try_scale_factors(std::iter::once(1.).chain(coarse_scale_factors).collect());
+
+    for _ in 0..refine_count {
+        // the new grid covers the neighbors of the best factor in the previous grid
+        let center = current_best.0;
+        step /= SCALE_SEARCH_REFINE_STEPS as f64;
+        let scale_factors = (1..=SCALE_SEARCH_REFINE_STEPS)
+            .flat_map(|i| [center - step * i as f64, center + step * i as f64])
+            .filter(|scale_factor| (min_scale..=max_scale).contains(scale_factor));
+        current_best = try_scale_factors(scale_factors.collect());
+    }
+
+    progress_handler.finish();
+
+    current_best
+}
+
+/// Matches an `incorrect` subtitle list to a `reference` subtitle list with the
+/// parameters from `config` (see `AlignmentConfig`).
+///
+/// If framerate candidates are given, the best one is applied to `list` first (see
+/// `guess_scale_factor`). A continuous scale search (see `estimate_scale_factor`) replaces
+/// the candidates. If no offset within the offset bounds lets the lists overlap,
+/// all lines get the offset within the bounds which is nearest to zero.
+///
+/// The reference weights and the texts (see `AlignmentConfig::reference_weights` and
+/// `AlignmentConfig::line_texts`) are not used for the framerate guessing.
+///
+/// Anchors are moved into the offset bounds. In no-split mode the common offset satisfies
+/// all anchors (conflicting anchors are ignored). In split mode, anchors are only violated
+/// if they contradict each other (e.g. because lines would have to swap their order).
+///
+/// # Panics
+///
+/// This function panics if an anchor refers to a line index which is not in `list`, if
+/// reference weights or texts are given, but not one for each span, or if an expected offset
+/// is given in `AlignMode::PiecewiseLinear` (see `AlignmentConfig::expected_offset`).
+pub fn align_with_config(
+    reference: &[TimeSpan],
+    list: &[TimeSpan],
+    config: &AlignmentConfig,
+    progress_handler: impl ProgressHandler,
+) -> AlignmentResult {
+    assert!(
+        config.expected_offset.is_none() || config.mode != AlignMode::PiecewiseLinear,
+        "an expected offset is not supported in piecewise linear mode"
+    );
+    let ref_weights = &config.reference_weights;
+    assert!(
+        ref_weights.is_empty() || ref_weights.len() == reference.len(),
+        "expected {} reference weights, got {}",
+        reference.len(),
+        ref_weights.len()
+    );
+    let texts = config.line_texts.as_ref().map(|(reference_texts, list_texts)| {
+        assert_eq!(
+            reference_texts.len(),
+            reference.len(),
+            "expected one text per reference span"
+        );
+        assert_eq!(list_texts.len(), list.len(), "expected one text per incorrect span");
+        LineTexts {
+            reference: reference_texts,
+            list: list_texts,
+            text_weight: config.text_weight,
+        }
+    });
+    let weights = LineWeights { ref_weights, texts };
+
+    let scale_factor = config_scale_factor(reference, list, config);
+
+    let scaled_list: Vec<TimeSpan>;
+    let list = if scale_factor == 1. {
+        list
+    } else {
+        scaled_list = list.iter().map(|ts| ts.scaled(scale_factor)).collect();
+        &scaled_list
+    };
+
+    // anchors are converted to offset ranges of the (scaled) lines
+    let clamp = |offset: TimeDelta| match config.offset_bounds {
+        None => offset,
+        Some((min_offset, max_offset)) => max(min_offset, min(max_offset, offset)),
+    };
+    let mut line_ranges: Vec<Option<(TimeDelta, TimeDelta)>> = Vec::new();
+    for &(line_nr, anchor) in &config.anchors {
+        assert!(line_nr < list.len(), "anchor for line {} is out of bounds", line_nr);
+        let (range_min, range_max) = match anchor {
+            Anchor::Time(time_point) => (time_point - list[line_nr].start(), time_point - list[line_nr].start()),
+            Anchor::OffsetRange(range_min, range_max) => (range_min, range_max),
+        };
+        line_ranges.resize(list.len(), None);
+        line_ranges[line_nr] = Some((clamp(range_min), clamp(range_max)));
+    }
+
+    let (deltas, line_scale_factors): (Vec<TimeDelta>, Vec<f64>) = match config.mode {
+        AlignMode::NoSplit => {
+            // the common offset has to satisfy all anchors (if possible)
+            let anchored_window =
+                line_ranges
+                    .iter()
+                    .flatten()
+                    .try_fold(config.offset_bounds, |window, &(range_min, range_max)| match window {
+                        None => Some(Some((range_min, range_max))),
+                        Some((window_min, window_max)) if max(window_min, range_min) <= min(window_max, range_max) => {
+                            Some(Some((max(window_min, range_min), min(window_max, range_max))))
+                        }
+                        Some(_) => None,
+                    });
+
+            let (delta, _) = align_nosplit_windowed(
+                reference,
+                &weights,
+                list,
+                config.score_fn,
+                anchored_window.unwrap_or(config.offset_bounds),
+                config.expected_offset,
+                progress_handler,
+            );
+            (vec![delta; list.len()], vec![1.; list.len()])
+        }
+        AlignMode::Split => {
+            let (deltas, _) = align_constrained(
+                reference,
+                &weights,
+                list,
+                config.split_penalty,
+                &config.split_penalty_curve,
+                config.split_jump_penalty,
+                config.min_split_jump,
+                config.speed_optimization,
+                config.score_fn,
+                &OffsetConstraints {
+                    window: config.offset_bounds,
+                    span_ranges: line_ranges,
+                    prior: config.expected_offset.map(|expected_offset| expected_offset.prior(1)),
+                },
+                progress_handler,
+            );
+            (deltas, vec![1.; list.len()])
+        }
+        AlignMode::PiecewiseLinear => {
+            // the window restricts the delta of each line (like an anchor)
+            let line_ranges: Vec<Option<(TimeDelta, TimeDelta)>> = (0..list.len())
+                .map(|line_nr| line_ranges.get(line_nr).cloned().flatten().or(config.offset_bounds))
+                .collect();
+
+            // no scaling wins ties
+            let scale_factors: Vec<f64> = std::iter::once(1.)
+                .chain(
+                    config
+                        .segment_scale_factors
+                        .iter()
+                        .cloned()
+                        .filter(|&scale_factor| scale_factor != 1.),
+                )
+                .collect();
+
+            align_piecewise_linear(
+                reference,
+                &weights,
+                list,
+                &scale_factors,
+                config.split_penalty,
+                &config.split_penalty_curve,
+                config.speed_optimization,
+                config.score_fn,
+                &line_ranges,
+                progress_handler,
+            )
+        }
+    };
+
+    let (ref_nonoverlapping, ref_indices) = prepare_time_spans(reference);
+    let (list_nonoverlapping, _) = prepare_time_spans(list);
+    // the result rates every line on its own
+    let line_indices: Vec<usize> = (0..list.len()).collect();
+    let weights = weights.for_spans(
+        reference,
+        &ref_indices,
+        ref_nonoverlapping.len(),
+        &line_indices,
+        list.len(),
+    );
+    let mut split_penalties = match config.mode {
+        AlignMode::NoSplit => Vec::new(),
+        AlignMode::Split | AlignMode::PiecewiseLinear => get_split_penalties(
+            ref_nonoverlapping.len(),
+            list_nonoverlapping.len(),
+            list,
+            config.split_penalty,
+            &config.split_penalty_curve,
+        ),
+    };
+    if config.mode == AlignMode::Split {
+        let jump_penalty = denormalize_split_penalty(
+            ref_nonoverlapping.len(),
+            list_nonoverlapping.len(),
+            config.split_jump_penalty,
+        );
+        for (penalty, pair) in split_penalties.iter_mut().zip(deltas.windows(2)) {
+            let jump = TimeDelta::from_i64((pair[1] - pair[0]).as_i64().abs());
+            *penalty = Rating::add_mul(*penalty, jump_penalty, jump);
+        }
+    }
+
+    AlignmentResult {
+        scale_factor,
+        ..AlignmentResult::new(
+            &ref_nonoverlapping,
+            &weights,
+            list,
+            deltas,
+            &line_scale_factors,
+            &split_penalties,
+            config.score_fn,
+            config.offset_bounds,
+        )
+    }
+}
+
+/// The scale factor which `align_with_config` applies to `list` (from the continuous scale search
+/// or the framerate candidates of `config`).
+fn config_scale_factor(reference: &[TimeSpan], list: &[TimeSpan], config: &AlignmentConfig) -> f64 {
+    match config.scale_range {
+        Some((min_scale, max_scale)) => {
+            estimate_scale_factor(reference, list, min_scale, max_scale, NoProgressHandler).0
+        }
+        None if config.framerate_candidates.is_empty() => 1.,
+        None => guess_scale_factor(reference, list, &config.framerate_candidates, NoProgressHandler),
+    }
+}
+
+/// Aligns `list` with every split penalty in `split_penalties` (and the remaining settings of
+/// `config`) and chooses the split penalty at the elbow of the trade-off curve between the
+/// number of splits and the score without split penalty (see `AlignmentResult::raw_score`).
+/// The scale factor does not depend on the split penalty, so it is only searched once.
+///
+/// Both axes are normalized to `[0, 1]` over all candidates and the elbow is the candidate
+/// which lies furthest above the diagonal: beyond it, additional splits gain little score.
+/// Ties are resolved in favor of the larger split penalty. If no candidate lies above the
+/// diagonal (e.g. because all alignments have the same number of splits), the curve has no
+/// elbow and the split penalty of `config` is returned.
+///
+/// The curve is returned in the order of `split_penalties`.
+///
+/// # Panics
+///
+/// This function panics if `split_penalties` is empty or under the same conditions as
+/// `align_with_config`.
+pub fn choose_split_penalty(
+    reference: &[TimeSpan],
+    list: &[TimeSpan],
+    config: &AlignmentConfig,
+    split_penalties: &[f64],
+) -> (f64, Vec<SplitPenaltyCandidate>) {
+    assert!(!split_penalties.is_empty(), "expected at least one split penalty");
+
+    let scale_factor = config_scale_factor(reference, list, config);
+    let scaled_list: Vec<TimeSpan> = list.iter().map(|ts| ts.scaled(scale_factor)).collect();
+    let mut scaled_config = config.clone();
+    scaled_config.framerate_candidates = Vec::new();
+    scaled_config.scale_range = None;
+
+    let curve = map_independent(split_penalties, |&split_penalty| {
+        let config = scaled_config.clone().split_penalty(split_penalty);
+        let alignment = align_with_config(reference, &scaled_list, &config, NoProgressHandler);
+
+        SplitPenaltyCandidate {
+            split_penalty,
+            split_count: alignment.segments.len().saturating_sub(1),
+            score: alignment.raw_score,
+        }
+    });
+
+    let min_splits = curve.iter().map(|c| c.split_count).min().unwrap();
+    let max_splits = curve.iter().map(|c| c.split_count).max().unwrap();
+    let min_score = curve.iter().map(|c| c.score).fold(f64::INFINITY, f64::min);
+    let max_score = curve.iter().map(|c| c.score).fold(f64::NEG_INFINITY, f64::max);
+    if min_splits == max_splits || min_score >= max_score {
+        return (config.split_penalty, curve);
+    }
+
+    let distance_above_diagonal = |c: &SplitPenaltyCandidate| {
+        (c.score - min_score) / (max_score - min_score)
+            - (c.split_count - min_splits) as f64 / (max_splits - min_splits) as f64
+    };
+
+    let mut elbow: Option<(f64, &SplitPenaltyCandidate)> = None;
+    for candidate in &curve {
+        let distance = distance_above_diagonal(candidate);
+        if distance <= 1e-9 {
+            continue;
+        }
+        let is_better = match elbow {
+            None => true,
+            Some((best_distance, best)) => {
+                distance > best_distance + 1e-9
+                    || (distance > best_distance - 1e-9 && candidate.split_penalty > best.split_penalty)
+            }
+        };
+        if is_better {
+            elbow = Some((distance, candidate));
+        }
+    }
+
+    let split_penalty = elbow.map_or(config.split_penalty, |(_, candidate)| candidate.split_penalty);
+    (split_penalty, curve)
 }
 
 /// Calculate the split score (see thesis in repository of source code).
@@ -195,7 +879,7 @@ fn get_nosplit_rating_iter(
     }
 
     loop {
-        let rating = Rating::from_timespans(ref_span, in_span, score_fn);
+        let rating = Rating::from_timespans(ref_span, in_span, 1., score_fn);
         total_rating += rating;
 
         if ref_span.end() <= in_span.end() {
@@ -220,6 +904,10 @@ mod tests {
     use crate::{TimePoint, prepare_time_spans};
     use rand::RngCore;
 
+    fn span(start: i64, end: i64) -> TimeSpan {
+        TimeSpan::new(TimePoint::from(start), TimePoint::from(end))
+    }
+
     /// Some special time span sequences.
     fn predefined_time_spans() -> Vec<Vec<TimeSpan>> {
         let t0 = TimePoint::from(0);
@@ -270,4 +958,285 @@ mod tests {
     pub fn get_random_prepared_test_time_spans() -> Vec<TimeSpan> {
         prepare_time_spans(&generate_random_time_spans()).0
     }
+
+    #[test]
+    fn align_with_config_offset_bounds() {
+        let mut rng = rand::rng();
+
+        for _ in 0..20 {
+            let (reference, list) = (generate_random_time_spans(), generate_random_time_spans());
+            let max_offset = TimeDelta::from_i64((rng.next_u32() % 5000) as i64);
+
+            for mode in [AlignMode::NoSplit, AlignMode::Split] {
+                let config = AlignmentConfig::new().mode(mode).max_offset(max_offset);
+                let result = align_with_config(&reference, &list, &config, NoProgressHandler);
+
+                assert_eq!(result.deltas.len(), list.len());
+                assert!(
+                    result
+                        .deltas
+                        .iter()
+                        .all(|&delta| -max_offset <= delta && delta <= max_offset)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn align_with_config_unbounded_like_align() {
+        for _ in 0..20 {
+            // the line scores of overlapping lines would be counted multiple times
+            let (reference, list) = (
+                get_random_prepared_test_time_spans(),
+                get_random_prepared_test_time_spans(),
+            );
+
+            let (deltas, score) = align(&reference, &list, 7.0, None, standard_scoring, NoProgressHandler);
+            let config = AlignmentConfig::new().split_penalty(7.0);
+            let result = align_with_config(&reference, &list, &config, NoProgressHandler);
+
+            assert_eq!(result.deltas, deltas);
+            assert!((result.score - score).abs() < 1e-3 * (list.len() + 1) as f64);
+        }
+    }
+
+    #[test]
+    fn estimate_scale_factor_finds_drift() {
+        let mut rng = rand::rng();
+
+        for _ in 0..3 {
+            let mut reference = Vec::new();
+            let mut current_pos = 0i64;
+            for _ in 0..300 {
+                current_pos += (rng.next_u32() % 2000) as i64 + 200;
+                let current_len = (rng.next_u32() % 2000) as i64 + 500;
+                reference.push(TimeSpan::new(
+                    TimePoint::from(current_pos),
+                    TimePoint::from(current_pos + current_len),
+                ));
+                current_pos += current_len;
+            }
+
+            let scale_factor = 0.95 + (rng.next_u32() % 1000) as f64 * 0.0001;
+            let offset = TimeDelta::from_i64((rng.next_u32() % 20000) as i64);
+            let list: Vec<TimeSpan> = reference
+                .iter()
+                .map(|ts| ts.scaled(1. / scale_factor) + offset)
+                .collect();
+
+            let (estimated_scale_factor, _, score) =
+                estimate_scale_factor(&reference, &list, 0.9, 1.1, NoProgressHandler);
+            let (_, unscaled_score) = align_nosplit(&reference, &list, overlap_scoring, NoProgressHandler);
+
+            assert!((estimated_scale_factor - scale_factor).abs() < 1e-4);
+            assert!(score >= unscaled_score);
+        }
+    }
+
+    #[test]
+    fn align_piecewise_linear_drift() {
+        let mut rng = rand::rng();
+
+        let mut reference = Vec::new();
+        let mut current_pos = 0i64;
+        for _ in 0..100 {
+            current_pos += (rng.next_u32() % 2000) as i64 + 200;
+            let current_len = (rng.next_u32() % 2000) as i64 + 500;
+            reference.push(TimeSpan::new(
+                TimePoint::from(current_pos),
+                TimePoint::from(current_pos + current_len),
+            ));
+            current_pos += current_len;
+        }
+
+        // the drift changes its slope after the first half
+        let list: Vec<TimeSpan> = reference
+            .iter()
+            .enumerate()
+            .map(|(line_nr, &span)| match line_nr < 50 {
+                true => span.with_scaled_start(1. / 1.002) + TimeDelta::from_i64(3000),
+                false => span.with_scaled_start(1. / 0.998) + TimeDelta::from_i64(8000),
+            })
+            .collect();
+
+        let config = AlignmentConfig::new().mode(AlignMode::PiecewiseLinear);
+        let result = align_with_config(&reference, &list, &config, NoProgressHandler);
+
+        assert_eq!(result.segments.len(), 2);
+        assert_eq!(result.segments[0].scale_factor, 1.002);
+        assert_eq!(result.segments[1].scale_factor, 0.998);
+        for ((&span, &delta), &ref_span) in list.iter().zip(&result.deltas).zip(&reference) {
+            assert!(((span + delta).start() - ref_span.start()).as_i64().abs() <= 10);
+        }
+    }
+
+    #[test]
+    fn align_with_config_reference_weights() {
+        let pattern = [span(0, 1000), span(1500, 1800), span(3000, 4200), span(5000, 5600)];
+
+        // the same pattern occurs twice, but the first occurrence is unreliable
+        let reference: Vec<TimeSpan> = pattern
+            .iter()
+            .map(|&ts| ts + TimeDelta::from_i64(10000))
+            .chain(pattern.iter().map(|&ts| ts + TimeDelta::from_i64(50000)))
+            .collect();
+        let reference_weights: Vec<f64> = (0..reference.len()).map(|i| if i < 4 { 0.2 } else { 1. }).collect();
+
+        for mode in [AlignMode::NoSplit, AlignMode::Split] {
+            let config = AlignmentConfig::new()
+                .mode(mode)
+                .reference_weights(reference_weights.clone());
+            let result = align_with_config(&reference, &pattern, &config, NoProgressHandler);
+
+            assert!(result.deltas.iter().all(|&delta| delta == TimeDelta::from_i64(50000)));
+            assert!((result.raw_score - 4.).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn align_with_config_line_texts() {
+        let pattern = [span(0, 1000), span(1500, 1800), span(3000, 4200), span(5000, 5600)];
+        let texts = |lines: &[&str]| -> Vec<String> { lines.iter().map(|line| line.to_string()).collect() };
+
+        // the same timing occurs twice, only the text tells the occurrences apart
+        let reference: Vec<TimeSpan> = pattern
+            .iter()
+            .map(|&ts| ts + TimeDelta::from_i64(10000))
+            .chain(pattern.iter().map(|&ts| ts + TimeDelta::from_i64(50000)))
+            .collect();
+        let reference_texts = texts(&[
+            "Good morning.",
+            "Is it?",
+            "<i>The train leaves at six.</i>",
+            "Hurry up!",
+            "Where have you been?",
+            "Out.",
+            "I waited for you all night.",
+            "I'm sorry.",
+        ]);
+        let list_texts = texts(&[
+            "Where have you been?",
+            "Outside.",
+            "I waited for you the whole night!",
+            "I am sorry.",
+        ]);
+
+        for mode in [AlignMode::NoSplit, AlignMode::Split] {
+            let config = AlignmentConfig::new()
+                .mode(mode)
+                .line_texts(reference_texts.clone(), list_texts.clone());
+            let result = align_with_config(&reference, &pattern, &config, NoProgressHandler);
+
+            assert!(result.deltas.iter().all(|&delta| delta == TimeDelta::from_i64(50000)));
+        }
+    }
+
+    #[test]
+    fn align_with_config_expected_offset() {
+        // every multiple of 1000 matches all lines perfectly
+        let reference: Vec<TimeSpan> = (0..20).map(|i| span(i * 1000, i * 1000 + 500)).collect();
+        let list: Vec<TimeSpan> = (0..5).map(|i| span(i * 1000, i * 1000 + 500)).collect();
+
+        for mode in [AlignMode::NoSplit, AlignMode::Split] {
+            let result = align_with_config(&reference, &list, &AlignmentConfig::new().mode(mode), NoProgressHandler);
+            assert!(result.deltas.iter().all(|&delta| delta == result.deltas[0]));
+
+            let config = AlignmentConfig::new().mode(mode).expected_offset(
+                TimeDelta::from_i64(7400),
+                TimeDelta::from_i64(2000),
+                0.1,
+            );
+            let result = align_with_config(&reference, &list, &config, NoProgressHandler);
+            assert_eq!(result.deltas, vec![TimeDelta::from_i64(7000); list.len()]);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "an expected offset is not supported in piecewise linear mode")]
+    fn align_with_config_expected_offset_piecewise_linear() {
+        let list = [TimeSpan::new(TimePoint::from(0), TimePoint::from(500))];
+        let config = AlignmentConfig::new().mode(AlignMode::PiecewiseLinear).expected_offset(
+            TimeDelta::from_i64(7400),
+            TimeDelta::from_i64(2000),
+            0.1,
+        );
+        align_with_config(&list, &list, &config, NoProgressHandler);
+    }
+
+    #[test]
+    fn align_with_config_split_penalty_curve() {
+        let list = [
+            span(0, 800),
+            span(1200, 2200),
+            span(2400, 3000),
+            span(10000, 11300),
+            span(11500, 12200),
+        ];
+
+        // the third line moved with the lines after the long gap
+        let reference: Vec<TimeSpan> = list
+            .iter()
+            .enumerate()
+            .map(|(i, &ts)| if i < 2 { ts } else { ts + TimeDelta::from_i64(3000) })
+            .collect();
+
+        let result = align_with_config(&reference, &list, &AlignmentConfig::new(), NoProgressHandler);
+        let expected: Vec<TimeDelta> = [0, 0, 3000, 3000, 3000].into_iter().map(TimeDelta::from_i64).collect();
+        assert_eq!(result.deltas, expected);
+
+        // a split in the dense dialogue costs more than the mismatch of the third line
+        let curve = SplitPenaltyCurve::new(vec![(TimeDelta::from_i64(1000), 100.), (TimeDelta::from_i64(5000), 1.)]);
+        let config = AlignmentConfig::new().split_penalty_curve(curve);
+        let result = align_with_config(&reference, &list, &config, NoProgressHandler);
+        let expected: Vec<TimeDelta> = [0, 0, 0, 3000, 3000].into_iter().map(TimeDelta::from_i64).collect();
+        assert_eq!(result.deltas, expected);
+        assert!((result.split_penalty - 5. * 7. / 1000.).abs() < 1e-6);
+    }
+
+    #[test]
+    fn choose_split_penalty_elbow() {
+        let list: Vec<TimeSpan> = (0..30)
+            .scan(0, |start, i| {
+                let span = TimeSpan::new(TimePoint::from(*start), TimePoint::from(*start + 1000 + i * 37 % 300));
+                *start = span.end().as_i64() + 600 + i * 53 % 400;
+                Some(span)
+            })
+            .collect();
+
+        // three blocks with different offsets, and three lines which are slightly off
+        let reference: Vec<TimeSpan> = list
+            .iter()
+            .enumerate()
+            .map(|(i, &ts)| {
+                let block_offset = [0, 5000, 12000][i / 10];
+                let jitter = if i % 10 == 4 { 60 } else { 0 };
+                ts + TimeDelta::from_i64(block_offset + jitter)
+            })
+            .collect();
+
+        let split_penalties = [0.1, 0.3, 3., 7., 20., 100., 1000.];
+        let (split_penalty, curve) = choose_split_penalty(&reference, &list, &AlignmentConfig::new(), &split_penalties);
+
+        let split_counts: Vec<usize> = curve.iter().map(|c| c.split_count).collect();
+        assert_eq!(split_counts, vec![8, 8, 2, 2, 2, 2, 0]);
+        assert!(curve.iter().zip(&split_penalties).all(|(c, &p)| c.split_penalty == p));
+        assert_eq!(split_penalty, 100.);
+
+        // the framerate is corrected once for all split penalties
+        let slow_list: Vec<TimeSpan> = list.iter().map(|ts| ts.scaled(24. / 25.)).collect();
+        let config = AlignmentConfig::new().framerate_candidates(vec![25. / 24., 24. / 25.]);
+        let (split_penalty, curve) = choose_split_penalty(&reference, &slow_list, &config, &split_penalties);
+        assert_eq!(
+            curve.iter().map(|c| c.split_count).collect::<Vec<usize>>(),
+            split_counts
+        );
+        assert_eq!(split_penalty, 100.);
+
+        // without splits there is no elbow
+        let reference: Vec<TimeSpan> = list.iter().map(|&ts| ts + TimeDelta::from_i64(3000)).collect();
+        let config = AlignmentConfig::new().split_penalty(5.);
+        let (split_penalty, curve) = choose_split_penalty(&reference, &list, &config, &split_penalties);
+        assert!(curve.iter().all(|c| c.split_count == 0));
+        assert_eq!(split_penalty, 5.);
+    }
 }
@@ -128,13 +128,19 @@ mod rating_i64 {
             r + rd * td as i64
         }
 
+        /// The rating of two overlapping spans, scaled by `weight` (the weight of the reference span).
         #[inline]
-        fn from_timespans(a: TimeSpan, b: TimeSpan, score_fn: impl Fn(TimeDelta, TimeDelta) -> f64 + Copy) -> Rating {
+        fn from_timespans(
+            a: TimeSpan,
+            b: TimeSpan,
+            weight: f64,
+            score_fn: impl Fn(TimeDelta, TimeDelta) -> f64 + Copy,
+        ) -> Rating {
             let overlap = TimeSpan::get_overlapping_length(a, b).as_f64();
             let max_rating = score_fn(a.len(), b.len());
             let length_normalization_factor = min(a.len(), b.len()).as_f64();
 
-            Rating::convert_from_f64(max_rating * overlap / length_normalization_factor)
+            Rating::convert_from_f64(weight * max_rating * overlap / length_normalization_factor)
         }
 
         #[inline]
@@ -167,10 +173,11 @@ mod rating_i64 {
         fn compute_rating_delta(
             a: TimeDelta,
             b: TimeDelta,
+            weight: f64,
             score_fn: impl Fn(TimeDelta, TimeDelta) -> f64 + Copy,
         ) -> RatingDelta {
             let min: f64 = min(a, b).as_f64();
-            RatingDelta::convert_from_f64(score_fn(a, b) / min)
+            RatingDelta::convert_from_f64(weight * score_fn(a, b) / min)
             //Rating::compute(a, b) / min(a, b).as_i64()
         }
     }
@@ -0,0 +1,232 @@
+use crate::Score;
+use crate::ilass::Aligner;
+use crate::rating_type::{Rating, RatingExt};
+use crate::time_types::{TimeDelta, TimeSpan};
+use crate::weights::SpanWeights;
+
+/// A maximal run of consecutive lines (in the order of the incorrect list) which
+/// were scaled by the same factor and shifted by the same offset.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlignmentSegment {
+    /// Index of the first line of the segment.
+    pub start_index: usize,
+
+    /// Index after the last line of the segment (exclusive).
+    pub end_index: usize,
+
+    /// The offset of all lines in this segment (after their start was scaled by
+    /// `scale_factor`). This is the delta of all lines if `scale_factor` is `1.0`.
+    pub delta: TimeDelta,
+
+    /// The scale factor for the start of the lines in this segment (in addition to
+    /// `AlignmentResult::scale_factor`). It is `1.0` unless `AlignMode::PiecewiseLinear`
+    /// is used.
+    pub scale_factor: f64,
+
+    /// The sum of the line scores in this segment.
+    pub score: Score,
+}
+
+/// The result of `align_with_config`.
+///
+/// The corrected version of the i-th incorrect span is
+/// `list[i].scaled(scale_factor) + deltas[i]`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlignmentResult {
+    /// The delta for every time span in the incorrect list (including the scaling of its
+    /// segment).
+    pub deltas: Vec<TimeDelta>,
+
+    /// The overlap score of every corrected line with the reference spans.
+    pub line_scores: Vec<Score>,
+
+    /// For every line, the margin between its score and its best score at an alternative
+    /// offset (at least one line length away and within the offset bounds). Lines with low
+    /// or negative values might be matched to the wrong reference spans.
+    pub line_confidences: Vec<Score>,
+
+    /// The lines grouped by their delta.
+    pub segments: Vec<AlignmentSegment>,
+
+    /// The sum of all line scores.
+    pub raw_score: Score,
+
+    /// The penalty for all splits between segments (zero in no-split mode).
+    pub split_penalty: Score,
+
+    /// The total score of the alignment: `raw_score - split_penalty`.
+    pub score: Score,
+
+    /// The framerate candidate which was applied to the incorrect list (`1.0` if none).
+    pub scale_factor: f64,
+}
+
+impl AlignmentResult {
+    /// Scores the corrected `list` against the `reference` which has to be sorted and
+    /// non-overlapping (see `prepare_time_spans()`). The `weights` are indexed by these reference
+    /// spans and by the lines of `list`. `split_penalties` is either empty (splits cost nothing)
+    /// or contains the penalty for a split between the lines `i` and `i + 1`. The `scale_factor`
+    /// of the result is `1.0`.
+    ///
+    /// The segments consist of the lines with the same scale factor and the same offset
+    /// (see `AlignmentSegment::delta`).
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new(
+        reference: &[TimeSpan],
+        weights: &SpanWeights,
+        list: &[TimeSpan],
+        deltas: Vec<TimeDelta>,
+        line_scale_factors: &[f64],
+        split_penalties: &[Rating],
+        score_fn: impl Fn(TimeDelta, TimeDelta) -> f64 + Copy,
+        offset_window: Option<(TimeDelta, TimeDelta)>,
+    ) -> AlignmentResult {
+        assert_eq!(list.len(), deltas.len());
+        assert_eq!(list.len(), line_scale_factors.len());
+
+        let line_ratings: Vec<Rating> = list
+            .iter()
+            .zip(deltas.iter())
+            .enumerate()
+            .map(|(line_nr, (&span, &delta))| get_line_rating(reference, weights, span + delta, line_nr, score_fn))
+            .collect();
+
+        let line_confidences: Vec<Score> = list
+            .iter()
+            .zip(deltas.iter())
+            .enumerate()
+            .map(|(line_nr, (&span, &delta))| {
+                if reference.is_empty() {
+                    0.
+                } else {
+                    Aligner::get_line_confidence(reference, weights, span, line_nr, delta, score_fn, offset_window)
+                        .as_readable_f64()
+                }
+            })
+            .collect();
+
+        let mut segments: Vec<AlignmentSegment> = Vec::new();
+        for (line_nr, (&delta, &scale_factor)) in deltas.iter().zip(line_scale_factors).enumerate() {
+            let span = list[line_nr];
+            let offset = delta - (span.with_scaled_start(scale_factor).start() - span.start());
+            match segments.last_mut() {
+                Some(segment) if segment.delta == offset && segment.scale_factor == scale_factor => {
+                    segment.end_index = line_nr + 1
+                }
+                _ => segments.push(AlignmentSegment {
+                    start_index: line_nr,
+                    end_index: line_nr + 1,
+                    delta: offset,
+                    scale_factor,
+                    score: 0.,
+                }),
+            }
+        }
+
+        for segment in &mut segments {
+            let segment_rating: Rating = line_ratings[segment.start_index..segment.end_index].iter().sum();
+            segment.score = segment_rating.as_readable_f64();
+        }
+
+        let raw_rating: Rating = line_ratings.iter().sum();
+        let split_penalty: Rating = segments
+            .iter()
+            .skip(1)
+            .map(|segment| {
+                split_penalties
+                    .get(segment.start_index - 1)
+                    .cloned()
+                    .unwrap_or_else(Rating::zero)
+            })
+            .sum();
+
+        AlignmentResult {
+            deltas,
+            line_scores: line_ratings.iter().map(RatingExt::as_readable_f64).collect(),
+            line_confidences,
+            segments,
+            raw_score: raw_rating.as_readable_f64(),
+            split_penalty: split_penalty.as_readable_f64(),
+            score: (raw_rating - split_penalty).as_readable_f64(),
+            scale_factor: 1.,
+        }
+    }
+}
+
+/// A point on the trade-off curve of `choose_split_penalty`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SplitPenaltyCandidate {
+    /// The normalized split penalty of the alignment (see `AlignmentConfig::split_penalty`).
+    pub split_penalty: f64,
+
+    /// The number of splits between the segments of the alignment.
+    pub split_count: usize,
+
+    /// The score of the alignment without the split penalty (see `AlignmentResult::raw_score`).
+    pub score: Score,
+}
+
+/// Sums the (weighted) ratings of `span` (the line `line_nr`) with all overlapping (sorted,
+/// non-overlapping) reference spans.
+fn get_line_rating(
+    reference: &[TimeSpan],
+    weights: &SpanWeights,
+    span: TimeSpan,
+    line_nr: usize,
+    score_fn: impl Fn(TimeDelta, TimeDelta) -> f64 + Copy,
+) -> Rating {
+    if span.is_empty() {
+        return Rating::zero();
+    }
+
+    let first_idx = reference.partition_point(|ref_span| ref_span.end() <= span.start());
+
+    reference[first_idx..]
+        .iter()
+        .enumerate()
+        .take_while(|(_, ref_span)| ref_span.start() < span.end())
+        .map(|(i, &ref_span)| Rating::from_timespans(ref_span, span, weights.get(first_idx + i, line_nr), score_fn))
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TimePoint;
+
+    fn span(start: i64, end: i64) -> TimeSpan {
+        TimeSpan::new(TimePoint::from(start), TimePoint::from(end))
+    }
+
+    #[test]
+    fn segments_and_scores() {
+        let reference = vec![span(0, 100), span(200, 300), span(400, 500)];
+        let list = vec![span(10, 110), span(210, 310), span(300, 400)];
+        let deltas = vec![
+            TimeDelta::from_i64(-10),
+            TimeDelta::from_i64(-10),
+            TimeDelta::from_i64(100),
+        ];
+
+        let result = AlignmentResult::new(
+            &reference,
+            &SpanWeights::default(),
+            &list,
+            deltas,
+            &[1.; 3],
+            &[Rating::convert_from_f64(0.5); 2],
+            crate::standard_scoring,
+            None,
+        );
+
+        assert_eq!(result.line_scores, vec![1., 1., 1.]);
+        assert_eq!(result.segments.len(), 2);
+        assert_eq!((result.segments[0].start_index, result.segments[0].end_index), (0, 2));
+        assert_eq!((result.segments[1].start_index, result.segments[1].end_index), (2, 3));
+        assert_eq!(result.segments[0].score, 2.);
+        assert_eq!(result.segments[1].delta, TimeDelta::from_i64(100));
+        assert_eq!(result.raw_score, 3.);
+        assert_eq!(result.split_penalty, 0.5);
+        assert_eq!(result.score, 2.5);
+    }
+}
@@ -26,8 +26,9 @@ impl PointSpan {
         self.end - self.start
     }
 
+    /// A point in the span (rounded down, so `start` for a span of length one).
     fn half(self) -> Point {
-        Point::from_i64((self.end.as_i64() + self.start.as_i64()) / 2)
+        Point::from_i64((self.end.as_i64() + self.start.as_i64()).div_euclid(2))
     }
 }
 
@@ -198,6 +199,41 @@ impl RatingBuffer {
         }
     }
 
+    /// Returns a copy of this buffer where `rating` is added to all points in `start..end`.
+    pub fn with_rating_added_in(&self, start: Point, end: Point, rating: Rating) -> RatingBuffer {
+        let mut buffer: Vec<RatingSegment> = Vec::with_capacity(self.buffer.len() + 2);
+        let mut segment_start = self.start;
+
+        for segment in &self.buffer {
+            let mut push_piece = |piece_start: Point, piece_end: Point| {
+                let mut data = segment.data.advanced(piece_start - segment_start);
+                if start <= piece_start && piece_end <= end {
+                    data.rating += rating;
+                }
+                buffer.push(RatingSegment {
+                    end_point: piece_end,
+                    data,
+                });
+            };
+
+            let mut piece_start = segment_start;
+            for split_point in [start, end] {
+                if piece_start < split_point && split_point < segment.end_point {
+                    push_piece(piece_start, split_point);
+                    piece_start = split_point;
+                }
+            }
+            push_piece(piece_start, segment.end_point);
+
+            segment_start = segment.end_point;
+        }
+
+        RatingBuffer {
+            start: self.start,
+            buffer,
+        }
+    }
+
     #[inline]
     pub fn first_end_point(&self) -> Option<Point> {
         self.buffer.first().map(|rating_segment| rating_segment.end_point)
@@ -223,10 +259,11 @@ impl RatingBuffer {
                 let start_rating = segment.start_rating();
                 let end_rating = segment.end_rating(segment.end_point - segment_start);
 
-                if start_rating > current_max {
+                // the rating is linear in a segment -> the maximum is at one of the ends
+                if start_rating > current_max && start_rating >= end_rating {
                     (start_rating, segment_start, segment.end_point)
                 } else if end_rating > current_max {
-                    (start_rating, segment.end_point - TimeDelta::one(), segment.end_point)
+                    (end_rating, segment.end_point - TimeDelta::one(), segment.end_point)
                 } else {
                     (current_max, current_max_point, segment.end_point)
                 }
@@ -239,6 +276,7 @@ impl RatingBuffer {
 pub struct DifferentialRatingBufferBuilder {
     start: TimeDelta,
     end: TimeDelta,
+    initial: RatingInfo,
     buffer: Vec<Segment<RatingDeltaDelta>>,
 }
 
@@ -250,12 +288,27 @@ impl DifferentialRatingBufferBuilder {
         DifferentialRatingBufferBuilder {
             start,
             end,
+            initial: RatingInfo::default(),
             buffer: Vec::new(),
         }
     }
 
+    /// Segment ends have to be added in ascending order. Changes before `start`
+    /// are folded into the initial rating, changes at or after `end` are ignored.
     #[inline]
     pub fn add_segment(&mut self, segment_end: Point, segment_end_delta_delta: RatingDeltaDelta) {
+        if segment_end <= self.start {
+            assert!(self.buffer.is_empty());
+            self.initial.rating =
+                Rating::add_mul(self.initial.rating, segment_end_delta_delta, self.start - segment_end);
+            self.initial.delta += segment_end_delta_delta;
+            return;
+        }
+
+        if segment_end > self.end {
+            return;
+        }
+
         if let Some(last_segment) = self.buffer.last_mut() {
             assert!(last_segment.end_point <= segment_end);
 
@@ -282,6 +335,7 @@ impl DifferentialRatingBufferBuilder {
     pub fn build(self) -> DifferentialRatingBuffer {
         DifferentialRatingBuffer {
             start: self.start,
+            initial: self.initial,
             buffer: self.buffer,
         }
     }
@@ -289,6 +343,7 @@ impl DifferentialRatingBufferBuilder {
 
 pub struct DifferentialRatingBuffer {
     start: TimeDelta,
+    initial: RatingInfo,
     buffer: Vec<Segment<RatingDeltaDelta>>,
 }
 
@@ -302,8 +357,8 @@ impl DifferentialRatingBuffer {
         }
 
         let start_state = ScanState {
-            rating: Rating::zero(),
-            delta: RatingDelta::zero(),
+            rating: self.initial.rating,
+            delta: self.initial.delta,
             last_segment_end: self.start,
         };
 
@@ -728,6 +783,158 @@ impl<I: Di> DualIterator<I> {
         }
     }
 
+    /// Returns an iterator which has at point `p` the value that `self` has at `p + t`. It
+    /// covers the same span as `self` (which has to end at `end`).
+    ///
+    /// This is meant for buffers created by `left_to_right_maximum()`: points
+    /// before the start get the same neutral value the maximum starts with and
+    /// points after the end keep the last (maximal) value.
+    #[inline]
+    pub fn shift_clamped(self, t: TimeDelta, end: Point) -> DualIterator<impl Di> {
+        self.shift_with_end_rating(t, end, None)
+    }
+
+    /// Like `shift_clamped()`, but points after the end get the (constant) rating
+    /// `end_rating`. This is meant for buffers where no value after the end is valid.
+    #[inline]
+    pub fn shift_with_end_rating(self, t: TimeDelta, end: Point, end_rating: Option<Rating>) -> DualIterator<impl Di> {
+        let start = self.start;
+        let mut result: Vec<DualSegment> = Vec::new();
+
+        if t < TimeDelta::zero() {
+            result.push(DualSegment {
+                end_point: min(start - t, end),
+                data: DualInfo {
+                    rating_info: RatingInfo::constant(Rating::zero()),
+                    offset_info: OffsetInfo::constant(start),
+                },
+            });
+        }
+
+        let mut last_segment: Option<DualFullSegment> = None;
+        let mut segment_start = start;
+        for segment in self.iter {
+            let full_segment = segment.with_start_point(segment_start);
+            segment_start = full_segment.span.end;
+
+            let shifted_start = full_segment.span.start - t;
+            let shifted_end = full_segment.span.end - t;
+            if shifted_start >= end {
+                break;
+            }
+
+            last_segment = Some(full_segment.clone());
+            if shifted_end <= start {
+                continue;
+            }
+
+            let data = if shifted_start < start {
+                full_segment.data.advanced(start - shifted_start)
+            } else {
+                full_segment.data
+            };
+
+            result.push(DualSegment {
+                end_point: min(shifted_end, end),
+                data,
+            });
+        }
+
+        let covered_end = result.last().map(|segment| segment.end_point).unwrap_or(start);
+        if covered_end < end {
+            let last_segment = last_segment.expect("buffer should have at least one segment");
+            result.push(DualSegment {
+                end_point: end,
+                data: DualInfo {
+                    rating_info: RatingInfo::constant(end_rating.unwrap_or_else(|| last_segment.end_rating())),
+                    offset_info: OffsetInfo::constant(
+                        last_segment.data.offset_info.end_offset(last_segment.span.len()),
+                    ),
+                },
+            });
+        }
+
+        DualIterator::<_> {
+            start,
+            iter: result.into_iter(),
+        }
+    }
+
+    /// Adds `rating + delta * (p - origin)` to the rating at every point `p`.
+    #[inline]
+    pub fn add_linear_rating(self, rating: Rating, delta: RatingDelta, origin: Point) -> DualIterator<impl Di> {
+        let start = self.start;
+        DualIterator::<_> {
+            start,
+            iter: self
+                .iter
+                .scan(start, move |segment_start: &mut Point, segment: DualSegment| {
+                    let rating_info = RatingInfo {
+                        rating: Rating::add_mul(
+                            segment.data.rating_info.rating + rating,
+                            delta,
+                            *segment_start - origin,
+                        ),
+                        delta: segment.data.rating_info.delta + delta,
+                    };
+                    *segment_start = segment.end_point;
+                    Some(DualSegment {
+                        end_point: segment.end_point,
+                        data: DualInfo {
+                            rating_info,
+                            offset_info: segment.data.offset_info,
+                        },
+                    })
+                }),
+        }
+    }
+
+    /// Returns a buffer which has at point `p` the maximum of `self` in the (inclusive)
+    /// window `[p, p + window]`, cut at `end` (where `self` has to end). The offset info
+    /// is the one of the maximum.
+    pub fn window_maximum(self, window: TimeDelta, end: Point) -> DualBuffer {
+        assert!(window >= TimeDelta::zero());
+
+        // the maximum of two windows is the maximum of the first window and the shifted
+        // second one, so the windows grow by doubling (`power`) and are combined for every
+        // set bit of the number of points in the requested window (`result`); a window which
+        // reaches past the end contains the last point, so `shift_clamped()` is exact here
+        let mut power: DualBuffer = self.save();
+        let mut power_len = TimeDelta::one();
+        let mut result: Option<(DualBuffer, TimeDelta)> = None;
+        let mut remaining_points = window.as_i64() + 1;
+
+        loop {
+            if remaining_points & 1 == 1 {
+                result = Some(match result {
+                    None => (power.iter().save(), power_len),
+                    Some((buffer, len)) => (
+                        combined_maximum_of_dual_iterators(buffer.into_iter(), power.iter().shift_clamped(len, end))
+                            .discard_start_times()
+                            .simplify()
+                            .discard_start_times()
+                            .save(),
+                        len + power_len,
+                    ),
+                });
+            }
+
+            remaining_points >>= 1;
+            if remaining_points == 0 {
+                break;
+            }
+
+            power = combined_maximum_of_dual_iterators(power.iter(), power.iter().shift_clamped(power_len, end))
+                .discard_start_times()
+                .simplify()
+                .discard_start_times()
+                .save();
+            power_len += power_len;
+        }
+
+        result.expect("window should contain at least one point").0
+    }
+
     #[inline]
     pub fn simplify(mut self) -> DualFullSegmentIterator<impl Dfi> {
         DualFullSegmentIterator {
@@ -814,6 +1021,29 @@ impl<I: Ri> RatingIterator<I> {
         }
     }
 
+    /// Adds `delta * (p - origin)` to the rating at every point `p`.
+    #[inline]
+    pub fn add_linear_rating(self, delta: RatingDelta, origin: Point) -> RatingIterator<impl Ri> {
+        let start = self.start;
+        RatingIterator::<_> {
+            start,
+            iter: self.iter.scan(
+                start,
+                move |segment_start: &mut Point, rating_segment: RatingSegment| {
+                    let rating = Rating::add_mul(rating_segment.data.rating, delta, *segment_start - origin);
+                    *segment_start = rating_segment.end_point;
+                    Some(RatingSegment {
+                        end_point: rating_segment.end_point,
+                        data: RatingInfo {
+                            rating,
+                            delta: rating_segment.data.delta + delta,
+                        },
+                    })
+                },
+            ),
+        }
+    }
+
     #[inline]
     pub fn clamp_end(self, clamp: Point) -> RatingIterator<impl Ri> {
         //println!("CLAMP {}", clamp);
@@ -1522,13 +1752,13 @@ impl<I1: Ri, I2: Ri> Iterator for RatingAdderIterator<I1, I2> {
     }
 }
 
-/*pub fn add_rating_iterators(
-    mut iter1: RatingIterator<impl RI>,
-    mut iter2: RatingIterator<impl RI>,
-) -> RatingFullIterator<impl RFI> {
+#[inline]
+pub fn add_rating_iterators(
+    mut iter1: RatingIterator<impl Ri>,
+    mut iter2: RatingIterator<impl Ri>,
+) -> RatingFullIterator<impl Rfi> {
     assert!(iter1.start == iter2.start);
     let start = iter1.start;
-    //println!("ADDER segment start {}", start);
 
     let dual_seg1 = iter1
         .iter
@@ -1540,17 +1770,17 @@ impl<I1: Ri, I2: Ri> Iterator for RatingAdderIterator<I1, I2> {
         .expect("Second iterator should have at least one element");
 
     RatingFullIterator::<_> {
-        start: start,
+        start,
         iter: RatingAdderIterator::<_, _> {
             segment_start: start,
-            dual_seg1: dual_seg1,
-            dual_seg2: dual_seg2,
+            dual_seg1,
+            dual_seg2,
             input_iter1: iter1.iter,
             input_iter2: iter2.iter,
             finished: false,
         },
     }
-}*/
+}
 
 // ----------------------------------------------------------------------------------------------------
 
@@ -2170,3 +2400,51 @@ impl<I: Di> Iterator for DualSimplifyIterator<I> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn point_span_half_inside_negative_spans() {
+        let span = |start: i64, end: i64| PointSpan::new(TimeDelta::from_i64(start), TimeDelta::from_i64(end));
+
+        // rounding towards zero would return the (exclusive) end of the first two spans
+        assert_eq!(span(-3, -2).half(), TimeDelta::from_i64(-3));
+        assert_eq!(span(-6, -5).half(), TimeDelta::from_i64(-6));
+        assert_eq!(span(-5, -2).half(), TimeDelta::from_i64(-4));
+        assert_eq!(span(-1, 1).half(), TimeDelta::from_i64(0));
+        assert_eq!(span(2, 3).half(), TimeDelta::from_i64(2));
+    }
+
+    #[test]
+    fn rating_buffer_maximum_at_segment_end() {
+        let segment = |end_point: i64, rating: f64, delta: f64| RatingSegment {
+            end_point: TimeDelta::from_i64(end_point),
+            data: RatingInfo {
+                rating: Rating::convert_from_f64(rating),
+                delta: RatingDelta::convert_from_f64(delta),
+            },
+        };
+
+        // rising from 2 (at point 10) to 11 (at point 19) -> the maximum is at the end of the last segment
+        let buffer = RatingBuffer {
+            start: TimeDelta::from_i64(0),
+            buffer: vec![segment(10, 5., 0.), segment(20, 2., 1.)],
+        };
+        assert_eq!(
+            buffer.maximum(),
+            (Rating::convert_from_f64(11.), TimeDelta::from_i64(19))
+        );
+
+        // the end of a rising segment is better than its start
+        let buffer = RatingBuffer {
+            start: TimeDelta::from_i64(0),
+            buffer: vec![segment(10, 3., 1.), segment(20, 4., 0.)],
+        };
+        assert_eq!(
+            buffer.maximum(),
+            (Rating::convert_from_f64(12.), TimeDelta::from_i64(9))
+        );
+    }
+}
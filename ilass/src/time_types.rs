@@ -376,6 +376,12 @@ impl TimeSpan {
         TimeSpan::new(new_start, new_end)
     }
 
+    /// Scale the start time point to zero by `scaling_factor` and keep the length.
+    pub fn with_scaled_start(self, scaling_factor: f64) -> TimeSpan {
+        let new_start = TimePoint::from((self.start.as_f64() * scaling_factor) as i64);
+        TimeSpan::new(new_start, new_start + self.len())
+    }
+
     /// Compares two `TimeSpan`s by their start timepoint.
     pub fn cmp_start(self, other: TimeSpan) -> Ordering {
         self.start.cmp(&other.start)
@@ -125,6 +125,37 @@ pub fn prepare_time_spans(v: &[TimeSpan]) -> (Vec<TimeSpan>, Vec<usize>) {
     (result, mapping)
 }
 
+/// Returns the weights of the `span_count` non-overlapping spans which `prepare_time_spans()`
+/// created from `spans` (with `mapping`). Each weight is the mean of the weights of the merged
+/// spans (weighted by their lengths). `weights` is either empty (all weights are one) or contains
+/// one weight per span. In the first case the result is empty too.
+pub fn prepare_weights(spans: &[TimeSpan], weights: &[f64], mapping: &[usize], span_count: usize) -> Vec<f64> {
+    if weights.is_empty() {
+        return Vec::new();
+    }
+    assert_eq!(spans.len(), weights.len());
+
+    let mut weighted_sums = vec![0.; span_count];
+    let mut lengths = vec![0.; span_count];
+    let mut max_weights = vec![0.; span_count];
+    for ((span, &weight), &i) in spans.iter().zip(weights).zip(mapping) {
+        weighted_sums[i] += weight * span.len().as_f64();
+        lengths[i] += span.len().as_f64();
+        max_weights[i] = f64::max(max_weights[i], weight);
+    }
+
+    // only zero-length spans were merged -> take the largest weight
+    (0..span_count)
+        .map(|i| {
+            if lengths[i] > 0. {
+                weighted_sums[i] / lengths[i]
+            } else {
+                max_weights[i]
+            }
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
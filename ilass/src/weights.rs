@@ -0,0 +1,221 @@
+use crate::TimeSpan;
+use crate::timespan_ops::prepare_weights;
+use std::collections::HashMap;
+
+/// The weights of the lines as given by the user: one weight per reference line and
+/// optionally the texts of all lines (see `AlignmentConfig::reference_weights` and
+/// `AlignmentConfig::line_texts`).
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct LineWeights<'a> {
+    pub ref_weights: &'a [f64],
+    pub texts: Option<LineTexts<'a>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct LineTexts<'a> {
+    pub reference: &'a [String],
+    pub list: &'a [String],
+
+    /// How much of the rating depends on the text similarity (from 0 to 1).
+    pub text_weight: f64,
+}
+
+impl LineWeights<'_> {
+    /// Returns the weights of the non-overlapping spans which `prepare_time_spans()` created
+    /// from `reference` and from the incorrect list. The mappings map the original lines to the
+    /// spans, and the texts of the lines in a span are joined.
+    pub fn for_spans(
+        &self,
+        reference: &[TimeSpan],
+        ref_mapping: &[usize],
+        ref_count: usize,
+        list_mapping: &[usize],
+        list_count: usize,
+    ) -> SpanWeights {
+        let ref_weights = prepare_weights(reference, self.ref_weights, ref_mapping, ref_count);
+
+        let texts = match self.texts {
+            Some(texts) => texts,
+            None => {
+                return SpanWeights {
+                    ref_weights,
+                    ..SpanWeights::default()
+                };
+            }
+        };
+
+        let mut words = WordIds::default();
+        let ref_words = words.span_words(texts.reference, ref_mapping, ref_count);
+        let list_words = words.span_words(texts.list, list_mapping, list_count);
+
+        let similarities = list_words
+            .iter()
+            .flat_map(|list_span_words| {
+                ref_words
+                    .iter()
+                    .map(move |ref_span_words| word_similarity(ref_span_words, list_span_words))
+            })
+            .collect();
+
+        SpanWeights {
+            ref_weights,
+            similarities,
+            ref_count,
+            text_weight: texts.text_weight,
+        }
+    }
+}
+
+/// The weight (from 0 to 1) of every pair of a reference span and an incorrect span. The
+/// ratings of their overlaps are multiplied by this weight.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SpanWeights {
+    /// Either empty (all weights are one) or one weight per reference span.
+    ref_weights: Vec<f64>,
+
+    /// Either empty (no texts) or the text similarity of every incorrect span (row) with every
+    /// reference span (column).
+    similarities: Vec<f32>,
+    ref_count: usize,
+    text_weight: f64,
+}
+
+impl SpanWeights {
+    pub fn get(&self, ref_nr: usize, in_nr: usize) -> f64 {
+        let ref_weight = self.ref_weights.get(ref_nr).cloned().unwrap_or(1.);
+        if self.similarities.is_empty() {
+            return ref_weight;
+        }
+
+        let similarity = f64::from(self.similarities[in_nr * self.ref_count + ref_nr]);
+        ref_weight * (1. - self.text_weight + self.text_weight * similarity)
+    }
+}
+
+/// Assigns a number to every distinct word.
+#[derive(Debug, Default)]
+struct WordIds {
+    ids: HashMap<String, u32>,
+}
+
+impl WordIds {
+    /// Returns the sorted word ids of every span (the words of all lines mapped to it).
+    fn span_words(&mut self, texts: &[String], mapping: &[usize], span_count: usize) -> Vec<Vec<u32>> {
+        let mut span_words: Vec<Vec<u32>> = vec![Vec::new(); span_count];
+        for (text, &span_nr) in texts.iter().zip(mapping) {
+            for word in normalized_words(text) {
+                let next_id = self.ids.len() as u32;
+                span_words[span_nr].push(*self.ids.entry(word).or_insert(next_id));
+            }
+        }
+
+        for words in &mut span_words {
+            words.sort_unstable();
+        }
+        span_words
+    }
+}
+
+/// Splits a subtitle line into lowercase words. Formatting tags like `<i>` or `{\an8}` and
+/// punctuation are dropped.
+fn normalized_words(text: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut tag_end: Option<char> = None;
+
+    for c in text.chars() {
+        match tag_end {
+            Some(end) => {
+                if c == end {
+                    tag_end = None;
+                }
+                continue;
+            }
+            None if c == '<' => tag_end = Some('>'),
+            None if c == '{' => tag_end = Some('}'),
+            None if c.is_alphanumeric() => {
+                word.extend(c.to_lowercase());
+                continue;
+            }
+            None => {}
+        }
+
+        if !word.is_empty() {
+            words.push(std::mem::take(&mut word));
+        }
+    }
+
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+/// The Dice coefficient of two sorted word lists: twice the number of common words divided
+/// by the number of all words (0 if both are empty). Repeated words are counted as often as
+/// they occur in both lists.
+fn word_similarity(a: &[u32], b: &[u32]) -> f32 {
+    if a.is_empty() && b.is_empty() {
+        return 0.;
+    }
+
+    let (mut i, mut j, mut common) = (0, 0, 0);
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(&b[j]) {
+            std::cmp::Ordering::Less => i += 1,
+            std::cmp::Ordering::Greater => j += 1,
+            std::cmp::Ordering::Equal => {
+                common += 1;
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+
+    (2 * common) as f32 / (a.len() + b.len()) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TimePoint;
+
+    fn span(start: i64, end: i64) -> TimeSpan {
+        TimeSpan::new(TimePoint::from(start), TimePoint::from(end))
+    }
+
+    #[test]
+    fn normalized_words_drop_tags_and_punctuation() {
+        assert_eq!(
+            normalized_words("{\\an8}<i>Don't  GO,</i> Mr. Smith!"),
+            vec!["don", "t", "go", "mr", "smith"]
+        );
+        assert!(normalized_words("<font color=\"red\">...</font>").is_empty());
+    }
+
+    #[test]
+    fn span_weights_combine_reference_weights_and_text() {
+        let reference = [span(0, 100), span(200, 300)];
+        let ref_texts = ["Where are you going?".to_string(), "Home.".to_string()];
+        let list_texts = ["Where are you going?".to_string(), "I'm going home.".to_string()];
+
+        let line_weights = LineWeights {
+            ref_weights: &[1., 0.5],
+            texts: Some(LineTexts {
+                reference: &ref_texts,
+                list: &list_texts,
+                text_weight: 0.8,
+            }),
+        };
+        let weights = line_weights.for_spans(&reference, &[0, 1], 2, &[0, 1], 2);
+
+        assert!((weights.get(0, 0) - 1.).abs() < 1e-6);
+        assert!((weights.get(1, 0) - 0.5 * 0.2).abs() < 1e-6);
+        // "going" is common with the first reference line, "home" with the second one
+        assert!((weights.get(0, 1) - (0.2 + 0.8 * 2. / 8.)).abs() < 1e-6);
+        assert!((weights.get(1, 1) - 0.5 * (0.2 + 0.8 * 2. / 5.)).abs() < 1e-6);
+
+        let no_texts = LineWeights::default().for_spans(&reference, &[0, 1], 2, &[0, 1], 2);
+        assert_eq!(no_texts.get(1, 1), 1.);
+    }
+}